@@ -0,0 +1,9 @@
+ALTER TABLE public.payments ADD COLUMN tx_id text COLLATE pg_catalog."default";
+
+UPDATE public.payments
+    SET tx_id = (
+        SELECT min(tx_id) FROM public.payment_transactions
+        WHERE payment_transactions.payment_id = payments.id
+    );
+
+DROP TABLE public.payment_transactions;
@@ -0,0 +1,16 @@
+CREATE TABLE public.payment_transactions
+(
+    payment_id uuid NOT NULL,
+    tx_id text COLLATE pg_catalog."default" NOT NULL,
+    CONSTRAINT payment_transactions_pkey PRIMARY KEY (payment_id, tx_id),
+    CONSTRAINT payment_transactions_payment_id_fkey FOREIGN KEY (payment_id)
+        REFERENCES public.payments (id)
+);
+
+CREATE INDEX payment_transactions_tx_id_idx
+    ON public.payment_transactions (tx_id);
+
+INSERT INTO public.payment_transactions (payment_id, tx_id)
+    SELECT id, tx_id FROM public.payments WHERE tx_id IS NOT NULL;
+
+ALTER TABLE public.payments DROP COLUMN tx_id;
@@ -0,0 +1,5 @@
+ALTER TABLE public.payments DROP COLUMN refund_tx_id;
+
+ALTER TABLE public.payments ADD COLUMN refund_to text COLLATE pg_catalog."default";
+
+DROP TABLE public.refund_outputs;
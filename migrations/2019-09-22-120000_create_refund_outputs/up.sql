@@ -0,0 +1,14 @@
+CREATE TABLE public.refund_outputs
+(
+    payment_id uuid NOT NULL,
+    idx integer NOT NULL,
+    amount bigint,
+    script bytea NOT NULL,
+    CONSTRAINT refund_outputs_pkey PRIMARY KEY (payment_id, idx),
+    CONSTRAINT refund_outputs_payment_id_fkey FOREIGN KEY (payment_id)
+        REFERENCES public.payments (id)
+);
+
+ALTER TABLE public.payments DROP COLUMN refund_to;
+
+ALTER TABLE public.payments ADD COLUMN refund_tx_id text COLLATE pg_catalog."default";
@@ -0,0 +1,3 @@
+DROP TABLE public.callbacks;
+
+DROP TYPE public.callback_state_enum;
@@ -0,0 +1,22 @@
+CREATE TYPE public.callback_state_enum AS ENUM
+    ('pending', 'delivered', 'failed');
+
+CREATE TABLE public.callbacks
+(
+    id bigserial NOT NULL,
+    payment_id uuid NOT NULL,
+    url text COLLATE pg_catalog."default" NOT NULL,
+    payload bytea NOT NULL,
+    callback_state callback_state_enum NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt timestamp without time zone NOT NULL,
+    last_attempt timestamp without time zone,
+    last_error text COLLATE pg_catalog."default",
+    CONSTRAINT callbacks_pkey PRIMARY KEY (id),
+    CONSTRAINT callbacks_payment_id_fkey FOREIGN KEY (payment_id)
+        REFERENCES public.payments (id)
+);
+
+CREATE INDEX callbacks_pending_idx
+    ON public.callbacks (next_attempt)
+    WHERE callback_state = 'pending';
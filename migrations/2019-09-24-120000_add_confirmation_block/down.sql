@@ -0,0 +1,3 @@
+ALTER TABLE public.payments DROP COLUMN block_height;
+
+ALTER TABLE public.payments DROP COLUMN block_hash;
@@ -0,0 +1,3 @@
+ALTER TABLE public.payments ADD COLUMN block_hash text COLLATE pg_catalog."default";
+
+ALTER TABLE public.payments ADD COLUMN block_height integer;
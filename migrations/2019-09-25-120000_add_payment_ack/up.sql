@@ -0,0 +1 @@
+ALTER TABLE public.payments ADD COLUMN payment_ack bytea;
@@ -0,0 +1,10 @@
+CREATE TABLE public.payment_outputs
+(
+    payment_id uuid NOT NULL,
+    idx integer NOT NULL,
+    amount bigint NOT NULL,
+    script bytea NOT NULL,
+    CONSTRAINT payment_outputs_pkey PRIMARY KEY (payment_id, idx),
+    CONSTRAINT payment_outputs_payment_id_fkey FOREIGN KEY (payment_id)
+        REFERENCES public.payments (id)
+);
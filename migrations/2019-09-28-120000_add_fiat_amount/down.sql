@@ -0,0 +1,5 @@
+ALTER TABLE public.payments DROP COLUMN exchange_rate;
+
+ALTER TABLE public.payments DROP COLUMN fiat_amount;
+
+ALTER TABLE public.payments DROP COLUMN fiat_currency;
@@ -0,0 +1,5 @@
+ALTER TABLE public.payments ADD COLUMN fiat_currency text COLLATE pg_catalog."default";
+
+ALTER TABLE public.payments ADD COLUMN fiat_amount double precision;
+
+ALTER TABLE public.payments ADD COLUMN exchange_rate double precision;
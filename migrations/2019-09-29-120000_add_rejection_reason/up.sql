@@ -0,0 +1 @@
+ALTER TABLE public.payments ADD COLUMN rejection_reason text COLLATE pg_catalog."default";
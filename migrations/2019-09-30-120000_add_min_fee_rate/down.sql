@@ -0,0 +1 @@
+ALTER TABLE public.payments DROP COLUMN min_fee_rate;
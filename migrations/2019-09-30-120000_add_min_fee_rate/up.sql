@@ -0,0 +1 @@
+ALTER TABLE public.payments ADD COLUMN min_fee_rate double precision;
@@ -0,0 +1,14 @@
+DROP TABLE public.payment_inputs;
+
+ALTER TABLE public.payments DROP COLUMN double_spend_tx_id;
+
+-- Double spent payments revert to received
+UPDATE public.payments SET payment_state = 'received' WHERE payment_state = 'double_spent';
+
+-- Recreate the type without the value
+ALTER TYPE public.payment_state_enum RENAME TO payment_state_enum_old;
+CREATE TYPE public.payment_state_enum AS ENUM
+    ('confirmed', 'pending', 'received', 'rejected', 'expired');
+ALTER TABLE public.payments ALTER COLUMN payment_state TYPE public.payment_state_enum
+    USING payment_state::text::public.payment_state_enum;
+DROP TYPE public.payment_state_enum_old;
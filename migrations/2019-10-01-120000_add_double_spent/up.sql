@@ -0,0 +1,21 @@
+-- ADD VALUE cannot run inside the migration's transaction before PostgreSQL 12, so the type is
+-- recreated instead
+ALTER TYPE public.payment_state_enum RENAME TO payment_state_enum_old;
+CREATE TYPE public.payment_state_enum AS ENUM
+    ('confirmed', 'pending', 'received', 'rejected', 'expired', 'double_spent');
+ALTER TABLE public.payments ALTER COLUMN payment_state TYPE public.payment_state_enum
+    USING payment_state::text::public.payment_state_enum;
+DROP TYPE public.payment_state_enum_old;
+
+ALTER TABLE public.payments ADD COLUMN double_spend_tx_id text COLLATE pg_catalog."default";
+
+CREATE TABLE public.payment_inputs
+(
+    payment_id uuid NOT NULL,
+    tx_id text COLLATE pg_catalog."default" NOT NULL,
+    prev_tx_id text COLLATE pg_catalog."default" NOT NULL,
+    prev_vout integer NOT NULL,
+    CONSTRAINT payment_inputs_pkey PRIMARY KEY (payment_id, prev_tx_id, prev_vout),
+    CONSTRAINT payment_inputs_payment_id_fkey FOREIGN KEY (payment_id)
+        REFERENCES public.payments (id)
+);
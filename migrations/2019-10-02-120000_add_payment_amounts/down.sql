@@ -0,0 +1,16 @@
+ALTER TABLE public.payments DROP COLUMN overpaid_amount;
+
+ALTER TABLE public.payments DROP COLUMN amount_due;
+
+ALTER TABLE public.payments DROP COLUMN amount_received;
+
+-- Underpaid payments revert to pending
+UPDATE public.payments SET payment_state = 'pending' WHERE payment_state = 'underpaid';
+
+-- Recreate the type without the value
+ALTER TYPE public.payment_state_enum RENAME TO payment_state_enum_old;
+CREATE TYPE public.payment_state_enum AS ENUM
+    ('confirmed', 'pending', 'received', 'rejected', 'expired', 'double_spent');
+ALTER TABLE public.payments ALTER COLUMN payment_state TYPE public.payment_state_enum
+    USING payment_state::text::public.payment_state_enum;
+DROP TYPE public.payment_state_enum_old;
@@ -0,0 +1,28 @@
+-- ADD VALUE cannot run inside the migration's transaction before PostgreSQL 12, so the type is
+-- recreated instead
+ALTER TYPE public.payment_state_enum RENAME TO payment_state_enum_old;
+CREATE TYPE public.payment_state_enum AS ENUM
+    ('confirmed', 'pending', 'received', 'rejected', 'expired', 'double_spent', 'underpaid');
+ALTER TABLE public.payments ALTER COLUMN payment_state TYPE public.payment_state_enum
+    USING payment_state::text::public.payment_state_enum;
+DROP TYPE public.payment_state_enum_old;
+
+ALTER TABLE public.payments ADD COLUMN amount_received bigint NOT NULL DEFAULT 0;
+
+ALTER TABLE public.payments ADD COLUMN amount_due bigint NOT NULL DEFAULT 0;
+
+ALTER TABLE public.payments ADD COLUMN overpaid_amount bigint NOT NULL DEFAULT 0;
+
+UPDATE public.payments
+    SET amount_received = amount + COALESCE((
+        SELECT sum(amount) FROM public.payment_outputs
+        WHERE payment_outputs.payment_id = payments.id
+    ), 0)
+    WHERE payment_state IN ('received', 'confirmed', 'double_spent');
+
+UPDATE public.payments
+    SET amount_due = amount + COALESCE((
+        SELECT sum(amount) FROM public.payment_outputs
+        WHERE payment_outputs.payment_id = payments.id
+    ), 0)
+    WHERE payment_state IN ('pending', 'rejected', 'expired');
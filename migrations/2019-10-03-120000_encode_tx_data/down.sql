@@ -0,0 +1,7 @@
+UPDATE public.payments
+    SET tx_data = CASE
+        WHEN get_byte(tx_data, 0) < 76 THEN substring(tx_data FROM 2)
+        WHEN get_byte(tx_data, 0) = 76 THEN substring(tx_data FROM 3)
+        ELSE substring(tx_data FROM 4)
+    END
+    WHERE tx_data IS NOT NULL AND length(tx_data) > 0;
@@ -0,0 +1,7 @@
+UPDATE public.payments
+    SET tx_data = CASE
+        WHEN length(tx_data) < 76 THEN set_byte('\x00'::bytea, 0, length(tx_data)) || tx_data
+        WHEN length(tx_data) < 256 THEN '\x4c'::bytea || set_byte('\x00'::bytea, 0, length(tx_data)) || tx_data
+        ELSE '\x4d'::bytea || set_byte(set_byte('\x0000'::bytea, 0, length(tx_data) % 256), 1, length(tx_data) / 256) || tx_data
+    END
+    WHERE tx_data IS NOT NULL;
@@ -0,0 +1 @@
+DROP TABLE public.derivation_indices;
@@ -0,0 +1,7 @@
+CREATE TABLE public.derivation_indices
+(
+    xpub text COLLATE pg_catalog."default" NOT NULL,
+    path text COLLATE pg_catalog."default" NOT NULL,
+    next_index integer NOT NULL,
+    CONSTRAINT derivation_indices_pkey PRIMARY KEY (xpub, path)
+);
@@ -0,0 +1 @@
+DROP TABLE public.address_pool;
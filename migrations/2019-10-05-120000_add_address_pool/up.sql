@@ -0,0 +1,8 @@
+CREATE TABLE public.address_pool
+(
+    address text COLLATE pg_catalog."default" NOT NULL,
+    added timestamp without time zone NOT NULL,
+    CONSTRAINT address_pool_pkey PRIMARY KEY (address)
+);
+
+CREATE INDEX address_pool_added_idx ON public.address_pool (added);
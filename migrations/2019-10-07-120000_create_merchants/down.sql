@@ -0,0 +1,8 @@
+DROP INDEX public.address_pool_merchant_id_added_idx;
+CREATE INDEX address_pool_added_idx ON public.address_pool (added);
+
+ALTER TABLE public.address_pool DROP COLUMN merchant_id;
+
+ALTER TABLE public.payments DROP COLUMN merchant_id;
+
+DROP TABLE public.merchants;
@@ -0,0 +1,32 @@
+CREATE TABLE public.merchants
+(
+    id text COLLATE pg_catalog."default" NOT NULL,
+    api_key_hash text COLLATE pg_catalog."default",
+    secret text COLLATE pg_catalog."default" NOT NULL,
+    address_source text COLLATE pg_catalog."default" NOT NULL,
+    wallet text COLLATE pg_catalog."default",
+    xpub text COLLATE pg_catalog."default",
+    derivation_path text COLLATE pg_catalog."default" NOT NULL,
+    req_memo text COLLATE pg_catalog."default",
+    ack_memo text COLLATE pg_catalog."default",
+    expiry bigint,
+    callback_url text COLLATE pg_catalog."default",
+    CONSTRAINT merchants_pkey PRIMARY KEY (id),
+    CONSTRAINT merchants_api_key_hash_key UNIQUE (api_key_hash)
+);
+
+-- Existing invoices belong to the default merchant, configured on startup. Until then its secret
+-- is random so nothing can be signed with a known one
+INSERT INTO public.merchants (id, secret, address_source, derivation_path)
+    VALUES ('default', md5(random()::text), 'node', 'm/0');
+
+ALTER TABLE public.payments ADD COLUMN merchant_id text COLLATE pg_catalog."default" NOT NULL DEFAULT 'default'
+    REFERENCES public.merchants (id);
+ALTER TABLE public.payments ALTER COLUMN merchant_id DROP DEFAULT;
+
+ALTER TABLE public.address_pool ADD COLUMN merchant_id text COLLATE pg_catalog."default" NOT NULL DEFAULT 'default'
+    REFERENCES public.merchants (id);
+ALTER TABLE public.address_pool ALTER COLUMN merchant_id DROP DEFAULT;
+
+DROP INDEX public.address_pool_added_idx;
+CREATE INDEX address_pool_merchant_id_added_idx ON public.address_pool (merchant_id, added);
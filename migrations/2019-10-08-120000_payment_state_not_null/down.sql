@@ -0,0 +1 @@
+ALTER TABLE public.payments ALTER COLUMN payment_state DROP NOT NULL;
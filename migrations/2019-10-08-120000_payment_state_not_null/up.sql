@@ -0,0 +1,4 @@
+-- The first migration left the state nullable, a payment without one was never paid
+UPDATE public.payments SET payment_state = 'pending' WHERE payment_state IS NULL;
+
+ALTER TABLE public.payments ALTER COLUMN payment_state SET NOT NULL;
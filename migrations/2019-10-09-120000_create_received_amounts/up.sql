@@ -0,0 +1,9 @@
+CREATE TABLE public.received_amounts
+(
+    payment_id uuid NOT NULL,
+    script bytea NOT NULL,
+    amount bigint NOT NULL,
+    CONSTRAINT received_amounts_pkey PRIMARY KEY (payment_id, script),
+    CONSTRAINT received_amounts_payment_id_fkey FOREIGN KEY (payment_id)
+        REFERENCES public.payments (id)
+);
@@ -0,0 +1,8 @@
+DROP TABLE address_pool;
+DROP TABLE derivation_indices;
+DROP TABLE callbacks;
+DROP TABLE refund_outputs;
+DROP TABLE payment_inputs;
+DROP TABLE payment_transactions;
+DROP TABLE payment_outputs;
+DROP TABLE payments;
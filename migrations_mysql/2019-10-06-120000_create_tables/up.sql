@@ -0,0 +1,103 @@
+CREATE TABLE payments
+(
+    id char(36) NOT NULL,
+    issue_time datetime(6) NOT NULL,
+    amount bigint NOT NULL,
+    address varchar(128) NOT NULL,
+    expiry_time datetime(6),
+    req_memo text,
+    merchant_data blob,
+    ack_memo text,
+    tokenize boolean NOT NULL,
+    tx_data blob,
+    payment_state enum('pending', 'received', 'confirmed', 'rejected', 'expired', 'double_spent', 'underpaid') NOT NULL,
+    payment_time datetime(6),
+    callback_url text,
+    refund_tx_id char(64),
+    block_hash char(64),
+    block_height integer,
+    payment_ack mediumblob,
+    payment_request mediumblob,
+    fiat_currency varchar(8),
+    fiat_amount double,
+    exchange_rate double,
+    rejection_reason text,
+    min_fee_rate double,
+    double_spend_tx_id char(64),
+    amount_received bigint NOT NULL DEFAULT 0,
+    amount_due bigint NOT NULL DEFAULT 0,
+    overpaid_amount bigint NOT NULL DEFAULT 0,
+    CONSTRAINT payments_pkey PRIMARY KEY (id)
+);
+
+CREATE TABLE payment_outputs
+(
+    payment_id char(36) NOT NULL,
+    idx integer NOT NULL,
+    amount bigint NOT NULL,
+    script blob NOT NULL,
+    CONSTRAINT payment_outputs_pkey PRIMARY KEY (payment_id, idx),
+    CONSTRAINT payment_outputs_payment_id_fkey FOREIGN KEY (payment_id) REFERENCES payments (id)
+);
+
+CREATE TABLE payment_transactions
+(
+    payment_id char(36) NOT NULL,
+    tx_id char(64) NOT NULL,
+    CONSTRAINT payment_transactions_pkey PRIMARY KEY (payment_id, tx_id),
+    CONSTRAINT payment_transactions_payment_id_fkey FOREIGN KEY (payment_id) REFERENCES payments (id)
+);
+
+CREATE TABLE payment_inputs
+(
+    payment_id char(36) NOT NULL,
+    tx_id char(64) NOT NULL,
+    prev_tx_id char(64) NOT NULL,
+    prev_vout integer NOT NULL,
+    CONSTRAINT payment_inputs_pkey PRIMARY KEY (payment_id, prev_tx_id, prev_vout),
+    CONSTRAINT payment_inputs_payment_id_fkey FOREIGN KEY (payment_id) REFERENCES payments (id)
+);
+
+CREATE TABLE refund_outputs
+(
+    payment_id char(36) NOT NULL,
+    idx integer NOT NULL,
+    amount bigint,
+    script blob NOT NULL,
+    CONSTRAINT refund_outputs_pkey PRIMARY KEY (payment_id, idx),
+    CONSTRAINT refund_outputs_payment_id_fkey FOREIGN KEY (payment_id) REFERENCES payments (id)
+);
+
+CREATE TABLE callbacks
+(
+    id bigint NOT NULL AUTO_INCREMENT,
+    payment_id char(36) NOT NULL,
+    url text NOT NULL,
+    payload mediumblob NOT NULL,
+    callback_state enum('pending', 'delivered', 'failed') NOT NULL DEFAULT 'pending',
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt datetime(6) NOT NULL,
+    last_attempt datetime(6),
+    last_error text,
+    CONSTRAINT callbacks_pkey PRIMARY KEY (id),
+    CONSTRAINT callbacks_payment_id_fkey FOREIGN KEY (payment_id) REFERENCES payments (id)
+);
+
+CREATE INDEX callbacks_due_idx ON callbacks (callback_state, next_attempt);
+
+CREATE TABLE derivation_indices
+(
+    xpub varchar(128) NOT NULL,
+    path varchar(255) NOT NULL,
+    next_index integer NOT NULL,
+    CONSTRAINT derivation_indices_pkey PRIMARY KEY (xpub, path)
+);
+
+CREATE TABLE address_pool
+(
+    address varchar(128) NOT NULL,
+    added datetime(6) NOT NULL,
+    CONSTRAINT address_pool_pkey PRIMARY KEY (address)
+);
+
+CREATE INDEX address_pool_added_idx ON address_pool (added);
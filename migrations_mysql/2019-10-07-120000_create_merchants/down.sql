@@ -0,0 +1,10 @@
+DROP INDEX address_pool_merchant_id_added_idx ON address_pool;
+CREATE INDEX address_pool_added_idx ON address_pool (added);
+
+ALTER TABLE address_pool DROP FOREIGN KEY address_pool_merchant_id_fkey;
+ALTER TABLE address_pool DROP COLUMN merchant_id;
+
+ALTER TABLE payments DROP FOREIGN KEY payments_merchant_id_fkey;
+ALTER TABLE payments DROP COLUMN merchant_id;
+
+DROP TABLE merchants;
@@ -0,0 +1,32 @@
+CREATE TABLE merchants
+(
+    id varchar(64) NOT NULL,
+    api_key_hash char(64),
+    secret text NOT NULL,
+    address_source varchar(16) NOT NULL,
+    wallet varchar(255),
+    xpub varchar(128),
+    derivation_path varchar(255) NOT NULL,
+    req_memo text,
+    ack_memo text,
+    expiry bigint,
+    callback_url text,
+    CONSTRAINT merchants_pkey PRIMARY KEY (id),
+    CONSTRAINT merchants_api_key_hash_key UNIQUE (api_key_hash)
+);
+
+-- Existing invoices belong to the default merchant, configured on startup. Until then its secret
+-- is random so nothing can be signed with a known one
+INSERT INTO merchants (id, secret, address_source, derivation_path)
+    VALUES ('default', SHA2(UUID(), 256), 'node', 'm/0');
+
+ALTER TABLE payments ADD COLUMN merchant_id varchar(64) NOT NULL DEFAULT 'default';
+ALTER TABLE payments ALTER COLUMN merchant_id DROP DEFAULT;
+ALTER TABLE payments ADD CONSTRAINT payments_merchant_id_fkey FOREIGN KEY (merchant_id) REFERENCES merchants (id);
+
+ALTER TABLE address_pool ADD COLUMN merchant_id varchar(64) NOT NULL DEFAULT 'default';
+ALTER TABLE address_pool ALTER COLUMN merchant_id DROP DEFAULT;
+ALTER TABLE address_pool ADD CONSTRAINT address_pool_merchant_id_fkey FOREIGN KEY (merchant_id) REFERENCES merchants (id);
+
+DROP INDEX address_pool_added_idx ON address_pool;
+CREATE INDEX address_pool_merchant_id_added_idx ON address_pool (merchant_id, added);
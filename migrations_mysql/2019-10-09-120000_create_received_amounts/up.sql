@@ -0,0 +1,8 @@
+CREATE TABLE received_amounts
+(
+    payment_id char(36) NOT NULL,
+    script varbinary(255) NOT NULL, -- part of the key, so not a blob
+    amount bigint NOT NULL,
+    CONSTRAINT received_amounts_pkey PRIMARY KEY (payment_id, script),
+    CONSTRAINT received_amounts_payment_id_fkey FOREIGN KEY (payment_id) REFERENCES payments (id)
+);
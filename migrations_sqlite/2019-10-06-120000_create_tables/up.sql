@@ -0,0 +1,95 @@
+-- Databases created before migrations were embedded already hold these tables
+
+CREATE TABLE IF NOT EXISTS payments
+(
+    id text NOT NULL PRIMARY KEY,
+    issue_time timestamp NOT NULL,
+    amount bigint NOT NULL,
+    address text NOT NULL,
+    expiry_time timestamp,
+    req_memo text,
+    merchant_data blob,
+    ack_memo text,
+    tokenize boolean NOT NULL,
+    tx_data blob,
+    payment_state text NOT NULL,
+    payment_time timestamp,
+    callback_url text,
+    refund_tx_id text,
+    block_hash text,
+    block_height integer,
+    payment_ack blob,
+    payment_request blob,
+    fiat_currency text,
+    fiat_amount double,
+    exchange_rate double,
+    rejection_reason text,
+    min_fee_rate double,
+    double_spend_tx_id text,
+    amount_received bigint NOT NULL DEFAULT 0,
+    amount_due bigint NOT NULL DEFAULT 0,
+    overpaid_amount bigint NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS payment_outputs
+(
+    payment_id text NOT NULL REFERENCES payments (id),
+    idx integer NOT NULL,
+    amount bigint NOT NULL,
+    script blob NOT NULL,
+    PRIMARY KEY (payment_id, idx)
+);
+
+CREATE TABLE IF NOT EXISTS payment_transactions
+(
+    payment_id text NOT NULL REFERENCES payments (id),
+    tx_id text NOT NULL,
+    PRIMARY KEY (payment_id, tx_id)
+);
+
+CREATE TABLE IF NOT EXISTS payment_inputs
+(
+    payment_id text NOT NULL REFERENCES payments (id),
+    tx_id text NOT NULL,
+    prev_tx_id text NOT NULL,
+    prev_vout integer NOT NULL,
+    PRIMARY KEY (payment_id, prev_tx_id, prev_vout)
+);
+
+CREATE TABLE IF NOT EXISTS refund_outputs
+(
+    payment_id text NOT NULL REFERENCES payments (id),
+    idx integer NOT NULL,
+    amount bigint,
+    script blob NOT NULL,
+    PRIMARY KEY (payment_id, idx)
+);
+
+CREATE TABLE IF NOT EXISTS callbacks
+(
+    id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+    payment_id text NOT NULL REFERENCES payments (id),
+    url text NOT NULL,
+    payload blob NOT NULL,
+    callback_state text NOT NULL DEFAULT 'pending',
+    attempts integer NOT NULL DEFAULT 0,
+    next_attempt timestamp NOT NULL,
+    last_attempt timestamp,
+    last_error text
+);
+
+CREATE INDEX IF NOT EXISTS callbacks_due_idx ON callbacks (callback_state, next_attempt);
+
+CREATE TABLE IF NOT EXISTS derivation_indices
+(
+    xpub text NOT NULL,
+    path text NOT NULL,
+    next_index integer NOT NULL,
+    PRIMARY KEY (xpub, path)
+);
+
+CREATE TABLE IF NOT EXISTS address_pool
+(
+    address text NOT NULL PRIMARY KEY,
+    added timestamp NOT NULL
+);
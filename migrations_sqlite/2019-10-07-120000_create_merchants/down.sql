@@ -0,0 +1,55 @@
+-- SQLite cannot drop a column holding a foreign key, so the tables are rebuilt without it
+
+CREATE TABLE address_pool_old
+(
+    address text NOT NULL PRIMARY KEY,
+    added timestamp NOT NULL
+);
+
+INSERT INTO address_pool_old (address, added)
+    SELECT address, added FROM address_pool;
+DROP TABLE address_pool;
+ALTER TABLE address_pool_old RENAME TO address_pool;
+
+CREATE TABLE payments_old
+(
+    id text NOT NULL PRIMARY KEY,
+    issue_time timestamp NOT NULL,
+    amount bigint NOT NULL,
+    address text NOT NULL,
+    expiry_time timestamp,
+    req_memo text,
+    merchant_data blob,
+    ack_memo text,
+    tokenize boolean NOT NULL,
+    tx_data blob,
+    payment_state text NOT NULL,
+    payment_time timestamp,
+    callback_url text,
+    refund_tx_id text,
+    block_hash text,
+    block_height integer,
+    payment_ack blob,
+    payment_request blob,
+    fiat_currency text,
+    fiat_amount double,
+    exchange_rate double,
+    rejection_reason text,
+    min_fee_rate double,
+    double_spend_tx_id text,
+    amount_received bigint NOT NULL DEFAULT 0,
+    amount_due bigint NOT NULL DEFAULT 0,
+    overpaid_amount bigint NOT NULL DEFAULT 0
+);
+
+INSERT INTO payments_old
+    SELECT id, issue_time, amount, address, expiry_time, req_memo, merchant_data, ack_memo,
+        tokenize, tx_data, payment_state, payment_time, callback_url, refund_tx_id,
+        block_hash, block_height, payment_ack, payment_request, fiat_currency, fiat_amount,
+        exchange_rate, rejection_reason, min_fee_rate, double_spend_tx_id, amount_received,
+        amount_due, overpaid_amount
+    FROM payments;
+DROP TABLE payments;
+ALTER TABLE payments_old RENAME TO payments;
+
+DROP TABLE merchants;
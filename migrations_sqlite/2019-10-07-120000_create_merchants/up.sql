@@ -0,0 +1,26 @@
+CREATE TABLE merchants
+(
+    id text NOT NULL PRIMARY KEY,
+    api_key_hash text UNIQUE,
+    secret text NOT NULL,
+    address_source text NOT NULL,
+    wallet text,
+    xpub text,
+    derivation_path text NOT NULL,
+    req_memo text,
+    ack_memo text,
+    expiry bigint,
+    callback_url text
+);
+
+-- Existing invoices belong to the default merchant, configured on startup. Until then its secret
+-- is random so nothing can be signed with a known one
+INSERT INTO merchants (id, secret, address_source, derivation_path)
+    VALUES ('default', lower(hex(randomblob(32))), 'node', 'm/0');
+
+-- SQLite cannot drop a column default, the store always sets the merchant
+ALTER TABLE payments ADD COLUMN merchant_id text NOT NULL DEFAULT 'default'
+    REFERENCES merchants (id);
+
+ALTER TABLE address_pool ADD COLUMN merchant_id text NOT NULL DEFAULT 'default'
+    REFERENCES merchants (id);
@@ -0,0 +1,7 @@
+CREATE TABLE received_amounts
+(
+    payment_id text NOT NULL REFERENCES payments (id),
+    script blob NOT NULL,
+    amount bigint NOT NULL,
+    PRIMARY KEY (payment_id, script)
+);
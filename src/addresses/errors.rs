@@ -0,0 +1,31 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum AddressSourceError {
+    UnknownSource,
+    MissingXpub,
+    InvalidXpub,
+    InvalidPath,
+    MismatchedNetwork,
+    Exhausted,
+    Fetch,
+    Derivation,
+    Database,
+}
+
+impl fmt::Display for AddressSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match *self {
+            AddressSourceError::UnknownSource => "unknown address source",
+            AddressSourceError::MissingXpub => "no xpub configured",
+            AddressSourceError::InvalidXpub => "invalid xpub",
+            AddressSourceError::InvalidPath => "invalid derivation path",
+            AddressSourceError::MismatchedNetwork => "xpub mismatched with node network",
+            AddressSourceError::Exhausted => "no unused derivation index left",
+            AddressSourceError::Fetch => "failed to fetch address",
+            AddressSourceError::Derivation => "failed to derive address",
+            AddressSourceError::Database => "failed to reserve derivation index",
+        };
+        write!(f, "{}", printable)
+    }
+}
@@ -0,0 +1,44 @@
+pub mod errors;
+pub mod node;
+pub mod pool;
+pub mod xpub;
+
+use std::sync::Arc;
+
+use futures::Future;
+
+use crate::{
+    bitcoin::{BitcoinClient, Network},
+    settings::Addresses,
+    sql::Store,
+};
+
+use errors::AddressSourceError;
+
+pub type AddressProvider = Arc<dyn AddressSource + Send + Sync>;
+
+pub trait AddressSource {
+    // Fresh address to be paid by a new invoice
+    fn next_address(&self) -> Box<dyn Future<Item = String, Error = AddressSourceError>>;
+}
+
+// Construct the address source described by the settings
+pub fn from_settings(
+    settings: &Addresses,
+    network: &Network,
+    bitcoin_client: BitcoinClient,
+    store: Store,
+) -> Result<AddressProvider, AddressSourceError> {
+    match settings.source.as_str() {
+        "node" => Ok(Arc::new(node::NodeAddresses::new(bitcoin_client))),
+        "xpub" => {
+            let xpub = settings
+                .xpub
+                .as_ref()
+                .ok_or(AddressSourceError::MissingXpub)?;
+            let source = xpub::XpubAddresses::new(xpub, &settings.path, network, store)?;
+            Ok(Arc::new(source))
+        }
+        _ => Err(AddressSourceError::UnknownSource),
+    }
+}
@@ -0,0 +1,27 @@
+use futures::Future;
+
+use crate::bitcoin::BitcoinClient;
+
+use super::{errors::AddressSourceError, AddressSource};
+
+// Addresses generated by the node wallet via getnewaddress
+pub struct NodeAddresses {
+    client: BitcoinClient,
+}
+
+impl NodeAddresses {
+    pub fn new(client: BitcoinClient) -> Self {
+        NodeAddresses { client }
+    }
+}
+
+impl AddressSource for NodeAddresses {
+    fn next_address(&self) -> Box<dyn Future<Item = String, Error = AddressSourceError>> {
+        Box::new(
+            self.client
+                .clone()
+                .get_new_addr()
+                .map_err(|_| AddressSourceError::Fetch),
+        )
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc,
+};
+
+use futures::future::{ok, Either, Future};
+use log::warn;
+
+use crate::sql::Store;
+
+use super::{errors::AddressSourceError, AddressProvider, AddressSource};
+
+// Address pool gauges and counters, exposed on the metrics endpoint
+#[derive(Debug, Default)]
+pub struct PoolMetrics {
+    depth: AtomicI64,
+    claimed: AtomicU64,
+    misses: AtomicU64,
+    fetched: AtomicU64,
+}
+
+impl PoolMetrics {
+    pub fn set_depth(&self, depth: i64) {
+        self.depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn add_fetched(&self, n_fetched: u64) {
+        self.fetched.fetch_add(n_fetched, Ordering::Relaxed);
+    }
+
+    fn claim(&self) {
+        self.claimed.fetch_add(1, Ordering::Relaxed);
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.depth.store(0, Ordering::Relaxed);
+    }
+
+    // Prometheus text exposition, labelled with the merchant owning the pool
+    pub fn render(&self, merchant_id: &str) -> String {
+        format!(
+            "# HELP address_pool_depth Unused addresses in the pool.\n\
+             # TYPE address_pool_depth gauge\n\
+             address_pool_depth{{merchant=\"{merchant}\"}} {}\n\
+             # HELP address_pool_claimed_total Invoice addresses taken from the pool.\n\
+             # TYPE address_pool_claimed_total counter\n\
+             address_pool_claimed_total{{merchant=\"{merchant}\"}} {}\n\
+             # HELP address_pool_misses_total Invoice addresses fetched directly as the pool was empty.\n\
+             # TYPE address_pool_misses_total counter\n\
+             address_pool_misses_total{{merchant=\"{merchant}\"}} {}\n\
+             # HELP address_pool_fetched_total Addresses fetched to refill the pool.\n\
+             # TYPE address_pool_fetched_total counter\n\
+             address_pool_fetched_total{{merchant=\"{merchant}\"}} {}\n",
+            self.depth.load(Ordering::Relaxed).max(0),
+            self.claimed.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.fetched.load(Ordering::Relaxed),
+            merchant = merchant_id,
+        )
+    }
+}
+
+// Addresses pre-fetched from another source and stored in the database for a merchant, falling
+// back to the source itself when the pool runs dry
+pub struct PooledAddresses {
+    merchant_id: String,
+    source: AddressProvider,
+    store: Store,
+    metrics: Arc<PoolMetrics>,
+}
+
+impl PooledAddresses {
+    pub fn new(
+        merchant_id: String,
+        source: AddressProvider,
+        store: Store,
+        metrics: Arc<PoolMetrics>,
+    ) -> Self {
+        PooledAddresses {
+            merchant_id,
+            source,
+            store,
+            metrics,
+        }
+    }
+}
+
+impl AddressSource for PooledAddresses {
+    fn next_address(&self) -> Box<dyn Future<Item = String, Error = AddressSourceError>> {
+        let merchant_id = self.merchant_id.clone();
+        let store = self.store.clone();
+        let source = self.source.clone();
+        let metrics = self.metrics.clone();
+        let address = actix_web::web::block(move || {
+            store
+                .claim_pooled_address(&merchant_id)
+                .map_err(|_| AddressSourceError::Database)
+        })
+        .map_err(|err| match err {
+            actix_threadpool::BlockingError::Error(e) => e,
+            _ => unreachable!(),
+        })
+        .and_then(move |claimed| match claimed {
+            Some(address) => {
+                metrics.claim();
+                Either::A(ok(address))
+            }
+            None => {
+                warn!("address pool empty, fetching address directly");
+                metrics.miss();
+                Either::B(source.next_address())
+            }
+        });
+        Box::new(address)
+    }
+}
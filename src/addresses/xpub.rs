@@ -0,0 +1,184 @@
+use std::{str::FromStr, sync::Arc};
+
+use bitcoin::{
+    secp256k1::{Secp256k1, VerifyOnly},
+    util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey},
+    Network as XpubNetwork,
+};
+use bitcoin_hashes::{hash160::Hash as Hash160, Hash};
+use futures::Future;
+
+use crate::{
+    bitcoin::Network,
+    crypto::{Address, HashType, Scheme},
+    sql::Store,
+};
+
+use super::{errors::AddressSourceError, AddressSource};
+
+// Watch-only P2PKH addresses derived from a merchant xpub, the next child index
+// is persisted so addresses are never reused across restarts
+pub struct XpubAddresses {
+    raw_xpub: String,
+    raw_path: String,
+    // Key of the address chain, children are derived from it per invoice
+    chain: ExtendedPubKey,
+    network: Network,
+    secp: Arc<Secp256k1<VerifyOnly>>,
+    store: Store,
+}
+
+impl XpubAddresses {
+    pub fn new(
+        raw_xpub: &str,
+        raw_path: &str,
+        network: &Network,
+        store: Store,
+    ) -> Result<Self, AddressSourceError> {
+        let xpub =
+            ExtendedPubKey::from_str(raw_xpub).map_err(|_| AddressSourceError::InvalidXpub)?;
+        // Test networks share the tpub version bytes
+        let xpub_mainnet = xpub.network == XpubNetwork::Bitcoin;
+        if xpub_mainnet != (*network == Network::Mainnet) {
+            return Err(AddressSourceError::MismatchedNetwork);
+        }
+
+        // Hardened steps cannot be derived from a public key
+        let path =
+            DerivationPath::from_str(raw_path).map_err(|_| AddressSourceError::InvalidPath)?;
+        let secp = Secp256k1::verification_only();
+        let chain = xpub
+            .derive_pub(&secp, &path)
+            .map_err(|_| AddressSourceError::InvalidPath)?;
+
+        Ok(XpubAddresses {
+            raw_xpub: raw_xpub.to_string(),
+            raw_path: raw_path.to_string(),
+            chain,
+            network: network.clone(),
+            secp: Arc::new(secp),
+            store,
+        })
+    }
+}
+
+// Cashaddr of the P2PKH output paying the key at the given index of the chain
+fn derive_address(
+    secp: &Secp256k1<VerifyOnly>,
+    chain: &ExtendedPubKey,
+    index: u32,
+    network: Network,
+) -> Result<String, AddressSourceError> {
+    let child_number =
+        ChildNumber::from_normal_idx(index).map_err(|_| AddressSourceError::Exhausted)?;
+    let child = chain
+        .ckd_pub(secp, child_number)
+        .map_err(|_| AddressSourceError::Derivation)?;
+    let pk_hash = Hash160::hash(&child.public_key.to_bytes()).to_vec();
+    Address::new(pk_hash, Scheme::CashAddr, HashType::Key, network.into())
+        .encode()
+        .map_err(|_| AddressSourceError::Derivation)
+}
+
+impl AddressSource for XpubAddresses {
+    fn next_address(&self) -> Box<dyn Future<Item = String, Error = AddressSourceError>> {
+        let store = self.store.clone();
+        let raw_xpub = self.raw_xpub.clone();
+        let raw_path = self.raw_path.clone();
+        let fut_index = actix_web::web::block(move || {
+            store
+                .reserve_derivation_index(&raw_xpub, &raw_path)
+                .map_err(|_| AddressSourceError::Database)
+        })
+        .map_err(|err| match err {
+            actix_threadpool::BlockingError::Error(e) => e,
+            _ => unreachable!(),
+        });
+
+        let secp = self.secp.clone();
+        let chain = self.chain;
+        let network = self.network.clone();
+        let address =
+            fut_index.and_then(move |index| derive_address(&secp, &chain, index as u32, network));
+        Box::new(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sql::memory::MemoryStore;
+
+    // Public keys of BIP32 test vector 1
+    const XPUB_0H: &str = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+    const XPUB_0H_1: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+    const XPUB_0H_1_2H: &str = "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5";
+    const XPUB_0H_1_2H_2: &str = "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV";
+
+    fn xpub_addresses(
+        raw_xpub: &str,
+        raw_path: &str,
+        network: &Network,
+    ) -> Result<XpubAddresses, AddressSourceError> {
+        XpubAddresses::new(
+            raw_xpub,
+            raw_path,
+            network,
+            Arc::new(MemoryStore::default()),
+        )
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        let addresses = xpub_addresses(XPUB_0H, "m/1", &Network::Mainnet).unwrap();
+        assert_eq!(
+            addresses.chain,
+            ExtendedPubKey::from_str(XPUB_0H_1).unwrap()
+        );
+        let addresses = xpub_addresses(XPUB_0H_1_2H, "m/2", &Network::Mainnet).unwrap();
+        assert_eq!(
+            addresses.chain,
+            ExtendedPubKey::from_str(XPUB_0H_1_2H_2).unwrap()
+        );
+        let addresses = xpub_addresses(XPUB_0H_1_2H_2, "m", &Network::Mainnet).unwrap();
+        assert_eq!(
+            addresses.chain,
+            ExtendedPubKey::from_str(XPUB_0H_1_2H_2).unwrap()
+        );
+
+        match xpub_addresses(XPUB_0H, "m/1'", &Network::Mainnet) {
+            Err(AddressSourceError::InvalidPath) => (),
+            other => panic!("expected invalid path, got {:?}", other.map(|_| ())),
+        }
+        match xpub_addresses(XPUB_0H, "m/1", &Network::Testnet) {
+            Err(AddressSourceError::MismatchedNetwork) => (),
+            other => panic!("expected mismatched network, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_derive_address() {
+        let secp = Secp256k1::verification_only();
+
+        // Key of m/0H/1/2H/2
+        let chain = ExtendedPubKey::from_str(XPUB_0H_1_2H).unwrap();
+        assert_eq!(
+            derive_address(&secp, &chain, 2, Network::Mainnet).unwrap(),
+            "bitcoincash:qrvgp47cjwzg2zdx9k8mwn3jzjx6c6zp9udsad2sqq"
+        );
+
+        // Key of m/0H/1/2H/2/1000000000
+        let chain = ExtendedPubKey::from_str(XPUB_0H_1_2H_2).unwrap();
+        assert_eq!(
+            derive_address(&secp, &chain, 1_000_000_000, Network::Mainnet).unwrap(),
+            "bitcoincash:qrtf4ggzy4076aphsfuv0qf8q84xg87lxgpd6hdfeu"
+        );
+
+        // Hardened indices need the private key
+        match derive_address(&secp, &chain, 1 << 31, Network::Mainnet) {
+            Err(AddressSourceError::Exhausted) => (),
+            other => panic!("expected exhausted, got {:?}", other),
+        }
+    }
+}
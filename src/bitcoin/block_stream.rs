@@ -0,0 +1,15 @@
+use bitcoin_zmq::{errors::SubscriptionError, Topic, ZMQSubscriber};
+use futures::{Future, Stream};
+
+// Stream of the hashes of new blocks
+pub fn get_block_stream(
+    node_addr: &str,
+) -> (
+    impl Stream<Item = String, Error = ()>,
+    impl Future<Item = (), Error = SubscriptionError> + Send + Sized,
+) {
+    let (stream, broker) = ZMQSubscriber::single_stream(node_addr, Topic::HashBlock, 256);
+    let stream = stream.map(hex::encode);
+
+    (stream, broker)
+}
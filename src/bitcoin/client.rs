@@ -3,7 +3,35 @@ use crate::net::jsonrpc_client::*;
 use std::sync::Arc;
 
 use futures::Future;
-use serde_json::Value;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TxStatus {
+    pub confirmations: Option<u32>,
+    pub blockhash: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BlockHeader {
+    pub hash: String,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MempoolAcceptance {
+    pub txid: String,
+    pub allowed: bool,
+    #[serde(rename = "reject-reason")]
+    pub reject_reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct TxOutInfo {
+    value: f64,
+}
+
+const SATS_PER_COIN: f64 = 100_000_000.;
 
 #[derive(Clone)]
 pub struct BitcoinClient(Arc<JsonClient>);
@@ -36,4 +64,137 @@ impl BitcoinClient {
                 .and_then(|resp| resp.into_result::<String>()),
         )
     }
+
+    pub fn test_mempool_accept(
+        &self,
+        raw_tx: &[u8],
+    ) -> Box<dyn Future<Item = MempoolAcceptance, Error = ClientError> + Send> {
+        let request = self.0.build_request(
+            "testmempoolaccept".to_string(),
+            vec![json!([hex::encode(raw_tx)])],
+        );
+        Box::new(
+            self.0
+                .send_request(&request)
+                .and_then(|resp| resp.into_result::<Vec<MempoolAcceptance>>())
+                .and_then(|acceptances| {
+                    acceptances
+                        .into_iter()
+                        .next()
+                        .ok_or(ClientError::NoErrorOrResult)
+                }),
+        )
+    }
+
+    // Whether a transaction is in the node's mempool
+    pub fn in_mempool(
+        &self,
+        tx_id: &str,
+    ) -> Box<dyn Future<Item = bool, Error = ClientError> + Send> {
+        let request = self.0.build_request(
+            "getmempoolentry".to_string(),
+            vec![Value::String(tx_id.to_string())],
+        );
+        Box::new(
+            self.0
+                .send_request(&request)
+                .and_then(|resp| match resp.into_result::<Value>() {
+                    Ok(_) => Ok(true),
+                    // The node reports transactions outside of its mempool as an error
+                    Err(ClientError::Rpc(_)) => Ok(false),
+                    Err(e) => Err(e),
+                }),
+        )
+    }
+
+    // Value of an unspent output, including those in the mempool
+    pub fn get_tx_out(
+        &self,
+        tx_id: &str,
+        vout: u32,
+    ) -> Box<dyn Future<Item = Option<u64>, Error = ClientError> + Send> {
+        let request = self.0.build_request(
+            "gettxout".to_string(),
+            vec![
+                Value::String(tx_id.to_string()),
+                json!(vout),
+                Value::Bool(true),
+            ],
+        );
+        Box::new(self.0.send_request(&request).and_then(|resp| {
+            // A null result indicates the output is spent or unknown
+            if resp.result.is_none() && resp.error.is_none() {
+                return Ok(None);
+            }
+            resp.into_result::<TxOutInfo>()
+                .map(|tx_out| Some((tx_out.value * SATS_PER_COIN).round() as u64))
+        }))
+    }
+
+    // Double spend proof involving a transaction, if the node has seen one
+    pub fn get_ds_proof(
+        &self,
+        tx_id: &str,
+    ) -> Box<dyn Future<Item = Option<Value>, Error = ClientError> + Send> {
+        let request = self.0.build_request(
+            "getdsproof".to_string(),
+            vec![Value::String(tx_id.to_string())],
+        );
+        Box::new(self.0.send_request(&request).and_then(|resp| {
+            // A null result indicates there is no proof
+            if resp.result.is_none() && resp.error.is_none() {
+                return Ok(None);
+            }
+            resp.into_result::<Value>().map(Some)
+        }))
+    }
+
+    pub fn send_to_address(
+        &self,
+        address: &str,
+        amount: u64,
+    ) -> Box<dyn Future<Item = String, Error = ClientError> + Send> {
+        let request = self.0.build_request(
+            "sendtoaddress".to_string(),
+            vec![
+                Value::String(address.to_string()),
+                json!(amount as f64 / SATS_PER_COIN),
+            ],
+        );
+        Box::new(
+            self.0
+                .send_request(&request)
+                .and_then(|resp| resp.into_result::<String>()),
+        )
+    }
+
+    pub fn get_tx_status(
+        &self,
+        tx_id: &str,
+    ) -> Box<dyn Future<Item = TxStatus, Error = ClientError> + Send> {
+        let request = self.0.build_request(
+            "getrawtransaction".to_string(),
+            vec![Value::String(tx_id.to_string()), Value::Bool(true)],
+        );
+        Box::new(
+            self.0
+                .send_request(&request)
+                .and_then(|resp| resp.into_result::<TxStatus>()),
+        )
+    }
+
+    pub fn get_block_header(
+        &self,
+        block_hash: &str,
+    ) -> Box<dyn Future<Item = BlockHeader, Error = ClientError> + Send> {
+        let request = self.0.build_request(
+            "getblockheader".to_string(),
+            vec![Value::String(block_hash.to_string()), Value::Bool(true)],
+        );
+        Box::new(
+            self.0
+                .send_request(&request)
+                .and_then(|resp| resp.into_result::<BlockHeader>()),
+        )
+    }
 }
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use bitcoin::Transaction;
+use futures::future::{self, Either, Future};
+
+use super::{client::BitcoinClient, rejection::TxRejection};
+
+// Look up the values of the outputs spent by a tx, outputs of the other txs in the payment are
+// taken from the payment itself as they may not have been broadcast yet
+pub fn input_values(
+    bitcoin_client: &BitcoinClient,
+    tx: &Transaction,
+    txs: &[Transaction],
+) -> impl Future<Item = Vec<u64>, Error = TxRejection> {
+    let payment_outputs: HashMap<_, u64> = txs
+        .iter()
+        .flat_map(|payment_tx| {
+            let tx_id = payment_tx.txid();
+            payment_tx
+                .output
+                .iter()
+                .enumerate()
+                .map(move |(vout, output)| ((tx_id, vout as u32), output.value))
+        })
+        .collect();
+
+    let values = tx.input.iter().map(|input| {
+        let outpoint = input.previous_output;
+        match payment_outputs.get(&(outpoint.txid, outpoint.vout)) {
+            Some(value) => Either::A(future::ok(*value)),
+            None => Either::B(
+                bitcoin_client
+                    .get_tx_out(&outpoint.txid.to_string(), outpoint.vout)
+                    .then(|result| match result {
+                        Ok(Some(value)) => Ok(value),
+                        Ok(None) => Err(TxRejection::MissingInputs),
+                        Err(e) => Err(TxRejection::from_client_error(&e)),
+                    }),
+            ),
+        }
+    });
+    future::join_all(values.collect::<Vec<_>>())
+}
+
+// Check a tx pays at least the given fee rate, in satoshis per byte
+pub fn check_fee_rate(
+    tx: &Transaction,
+    tx_size: usize,
+    input_values: &[u64],
+    min_fee_rate: f64,
+) -> Result<(), TxRejection> {
+    let input_value: u64 = input_values.iter().sum();
+    let output_value: u64 = tx.output.iter().map(|output| output.value).sum();
+    if input_value < output_value {
+        return Err(TxRejection::Other("outputs exceed inputs".to_string()));
+    }
+
+    let fee_rate = (input_value - output_value) as f64 / tx_size as f64;
+    if fee_rate < min_fee_rate {
+        Err(TxRejection::InsufficientFee)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::{OutPoint, Script, TxIn, TxOut};
+
+    fn tx(inputs: &[OutPoint], values: &[u64]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: inputs
+                .iter()
+                .map(|outpoint| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: Script::new(),
+                    sequence: 0xffff_ffff,
+                    witness: vec![],
+                })
+                .collect(),
+            output: values
+                .iter()
+                .map(|value| TxOut {
+                    value: *value,
+                    script_pubkey: Script::from(vec![0xaa; 25]),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_input_values_from_payment() {
+        // The node is never asked for outputs of txs in the payment
+        let bitcoin_client = BitcoinClient::new(
+            "http://127.0.0.1:1".to_string(),
+            String::new(),
+            String::new(),
+        );
+        let parent = tx(&[OutPoint::default()], &[7000, 3000]);
+        let outpoint = |vout| OutPoint {
+            txid: parent.txid(),
+            vout,
+        };
+        let child = tx(&[outpoint(1), outpoint(0)], &[9000]);
+        let txs = [parent.clone(), child.clone()];
+        assert_eq!(
+            input_values(&bitcoin_client, &child, &txs).wait(),
+            Ok(vec![3000, 7000])
+        );
+    }
+
+    #[test]
+    fn test_check_fee_rate() {
+        let tx = tx(&[OutPoint::default()], &[9000]);
+
+        // A fee of 250 over 250 bytes meets exactly 1 satoshi per byte
+        assert_eq!(check_fee_rate(&tx, 250, &[9250], 1.), Ok(()));
+        assert_eq!(
+            check_fee_rate(&tx, 250, &[9249], 1.),
+            Err(TxRejection::InsufficientFee)
+        );
+
+        // Fractional rates are not rounded
+        assert_eq!(check_fee_rate(&tx, 250, &[9375], 1.5), Ok(()));
+        assert_eq!(
+            check_fee_rate(&tx, 250, &[9374], 1.5),
+            Err(TxRejection::InsufficientFee)
+        );
+
+        // Values of several inputs are summed
+        assert_eq!(check_fee_rate(&tx, 250, &[4000, 5250], 1.), Ok(()));
+
+        assert_eq!(
+            check_fee_rate(&tx, 250, &[8000], 0.),
+            Err(TxRejection::Other("outputs exceed inputs".to_string()))
+        );
+    }
+}
@@ -1,14 +1,23 @@
+pub mod block_stream;
 mod client;
-// pub mod tx_stream;
+pub mod fees;
+mod rejection;
+pub mod script;
+pub mod tx_stream;
 
-use std::string::ToString;
+use std::{collections::HashSet, string::ToString};
 
 use bitcoin::{Transaction, TxOut};
 use serde::Deserialize;
 
-use crate::models::Output;
+use crate::{
+    crypto::{Address, HashType, Scheme},
+    models::Output,
+};
 
-pub use client::BitcoinClient;
+pub use client::{BitcoinClient, BlockHeader, MempoolAcceptance, TxStatus};
+pub use rejection::TxRejection;
+pub use script::{encode_pushes, extract_op_return, op_return_script, MAX_OP_RETURN_SIZE};
 
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub enum Network {
@@ -47,68 +56,108 @@ impl ToString for Network {
     }
 }
 
-pub fn check_p2pkh(output: &TxOut, expected_amount: u64, expected_pk_hash: &[u8]) -> bool {
-    // Check first output
-    if output.value != expected_amount {
-        return false;
+pub fn check_op_return(output: &TxOut, expected_pushes: &[Vec<u8>]) -> bool {
+    match extract_op_return(&output.script_pubkey[..]) {
+        Some(pushes) => pushes == expected_pushes,
+        None => false,
     }
+}
 
-    // Check p2pkh addr
-    let script = &output.script_pubkey[..];
-    if let Some(pubkey_hash) = extract_pubkey_hash(script) {
-        expected_pk_hash == &pubkey_hash[..]
-    } else {
-        false
-    }
+// Amount paid to one of the expected scripts across all transactions of a payment
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptTally {
+    pub script: Vec<u8>,
+    // Total of the expected outputs to the script
+    pub expected: u64,
+    // Total paid to the script
+    pub paid: u64,
 }
 
-pub fn check_op_return(output: &TxOut, expected_tx_data: &[u8]) -> bool {
-    let script = &output.script_pubkey[..];
-    if let Some(tx_data) = extract_op_return(script) {
-        tx_data == expected_tx_data
-    } else {
-        false
+// Amounts paid towards each expected script, in the order the scripts are first expected
+pub fn tally_outputs(txs: &[Transaction], expected_outputs: &[Output]) -> Vec<ScriptTally> {
+    let mut tally: Vec<ScriptTally> = Vec::with_capacity(expected_outputs.len());
+    for expected_output in expected_outputs {
+        let amount = expected_output.amount.unwrap_or(0);
+        match tally
+            .iter_mut()
+            .find(|script_tally| script_tally.script == expected_output.script)
+        {
+            Some(script_tally) => script_tally.expected += amount,
+            None => tally.push(ScriptTally {
+                script: expected_output.script.clone(),
+                expected: amount,
+                paid: 0,
+            }),
+        }
     }
-}
 
-pub fn check_outputs(
-    tx: &Transaction,
-    expected_amount: u64,
-    expected_pk_hash: &[u8],
-    opt_tx_data: Option<&Vec<u8>>,
-) -> bool {
-    if !tx
-        .output
-        .iter()
-        .any(|output| check_p2pkh(output, expected_amount, expected_pk_hash))
-    {
-        return false;
+    for output in txs.iter().flat_map(|tx| tx.output.iter()) {
+        let script = output.script_pubkey.as_bytes();
+        if let Some(script_tally) = tally
+            .iter_mut()
+            .find(|script_tally| script_tally.script == script)
+        {
+            script_tally.paid += output.value;
+        }
     }
+    tally
+}
 
+// The tx data is stored as the encoded pushes following OP_RETURN
+pub fn check_tx_data(txs: &[Transaction], opt_tx_data: Option<&Vec<u8>>) -> bool {
     if let Some(tx_data) = opt_tx_data {
-        tx.output
-            .iter()
-            .any(|output| check_op_return(output, &tx_data[..]))
+        let expected_pushes = match script::parse_pushes(tx_data) {
+            Some(expected_pushes) => expected_pushes,
+            None => return false,
+        };
+        txs.iter()
+            .flat_map(|tx| tx.output.iter())
+            .any(|output| check_op_return(output, &expected_pushes))
     } else {
         true
     }
 }
 
-pub fn extract_op_return(script: &[u8]) -> Option<Vec<u8>> {
-    if script[0] != 106 {
-        // Not op_return
-        return None;
-    }
+// Output spent by a transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpentOutput {
+    pub tx_id: String,
+    pub prev_tx_id: String,
+    pub prev_vout: u32,
+}
 
-    if script[1] as usize != script.len() - 2 {
-        // Not length
-        return None;
-    }
+pub fn spent_outputs(tx: &Transaction) -> Vec<SpentOutput> {
+    let tx_id = tx.txid().to_string();
+    tx.input
+        .iter()
+        .map(|input| SpentOutput {
+            tx_id: tx_id.clone(),
+            prev_tx_id: input.previous_output.txid.to_string(),
+            prev_vout: input.previous_output.vout,
+        })
+        .collect()
+}
 
-    Some(script[2..script.len()].to_vec())
+// Check the txs of a payment can be broadcast in the order given, parents must precede their
+// children and no output may be spent twice
+pub fn check_tx_order(txs: &[Transaction]) -> Result<(), TxRejection> {
+    let tx_ids: Vec<_> = txs.iter().map(Transaction::txid).collect();
+    let mut spent = HashSet::new();
+    for (idx, tx) in txs.iter().enumerate() {
+        for input in &tx.input {
+            let outpoint = input.previous_output;
+            if tx_ids[idx..].contains(&outpoint.txid) {
+                return Err(TxRejection::Other("tx precedes its parent".to_string()));
+            }
+            if !spent.insert(outpoint) {
+                return Err(TxRejection::Other("output spent twice".to_string()));
+            }
+        }
+    }
+    Ok(())
 }
 
-fn extract_pubkey_hash(raw_script: &[u8]) -> Option<Vec<u8>> {
+pub fn extract_pubkey_hash(raw_script: &[u8]) -> Option<Vec<u8>> {
     if raw_script.len() != 25 {
         return None;
     }
@@ -124,21 +173,161 @@ fn extract_pubkey_hash(raw_script: &[u8]) -> Option<Vec<u8>> {
     Some(raw_script[3..23].to_vec())
 }
 
-pub fn generate_outputs(pk_hash: &[u8], amount: u64, data: &[u8]) -> Vec<Output> {
-    // Generate p2pkh
+// Extract the script hash of a P2SH or P2SH32 script
+pub fn extract_script_hash(raw_script: &[u8]) -> Option<Vec<u8>> {
+    match raw_script.len() {
+        // OP_HASH160 <20 bytes> OP_EQUAL
+        23 if raw_script[0..2] == [169, 20] && raw_script[22] == 135 => {
+            Some(raw_script[2..22].to_vec())
+        }
+        // OP_HASH256 <32 bytes> OP_EQUAL
+        35 if raw_script[0..2] == [170, 32] && raw_script[34] == 135 => {
+            Some(raw_script[2..34].to_vec())
+        }
+        _ => None,
+    }
+}
+
+pub fn script_to_address(raw_script: &[u8], network: Network) -> Option<String> {
+    let (hash, hash_type) = match extract_pubkey_hash(raw_script) {
+        Some(pubkey_hash) => (pubkey_hash, HashType::Key),
+        None => (extract_script_hash(raw_script)?, HashType::Script),
+    };
+    Address::new(hash, Scheme::CashAddr, hash_type, network.into())
+        .encode()
+        .ok()
+}
+
+pub fn p2pkh_script(pk_hash: &[u8]) -> Vec<u8> {
     let p2pkh_script_pre: [u8; 3] = [118, 169, 20];
     let p2pkh_script_post: [u8; 2] = [136, 172];
-    let p2pkh_script = [&p2pkh_script_pre[..], &pk_hash[..], &p2pkh_script_post[..]].concat();
-    let p2pkh_output = Output {
-        amount: Some(amount),
-        script: p2pkh_script,
-    };
+    [&p2pkh_script_pre[..], &pk_hash[..], &p2pkh_script_post[..]].concat()
+}
 
-    let op_return_script = [&[106, 9 + 20 + data.len() as u8][..], &data].concat();
-    let op_return = Output {
-        amount: Some(0),
-        script: op_return_script,
-    };
+pub fn p2sh_script(script_hash: &[u8]) -> Vec<u8> {
+    let p2sh_script_pre: [u8; 2] = [169, 20];
+    let p2sh_script_post: [u8; 1] = [135];
+    [&p2sh_script_pre[..], script_hash, &p2sh_script_post[..]].concat()
+}
+
+pub fn p2sh32_script(script_hash: &[u8]) -> Vec<u8> {
+    let p2sh32_script_pre: [u8; 2] = [170, 32];
+    let p2sh32_script_post: [u8; 1] = [135];
+    [&p2sh32_script_pre[..], script_hash, &p2sh32_script_post[..]].concat()
+}
+
+pub fn address_to_script(addr: &Address) -> Option<Vec<u8>> {
+    let body = addr.as_body();
+    match (&addr.hash_type, body.len()) {
+        (HashType::Key, 20) => Some(p2pkh_script(body)),
+        (HashType::Script, 20) => Some(p2sh_script(body)),
+        (HashType::Script, 32) => Some(p2sh32_script(body)),
+        _ => None,
+    }
+}
+
+// Outputs carrying value, the invoice address is only paid if it is given an amount
+// or there are no other outputs
+pub fn invoice_outputs(script: &[u8], amount: u64, extra_outputs: &[Output]) -> Vec<Output> {
+    let mut outputs = Vec::with_capacity(extra_outputs.len() + 1);
+    if amount != 0 || extra_outputs.is_empty() {
+        outputs.push(Output {
+            amount: Some(amount),
+            script: script.to_vec(),
+        });
+    }
+    outputs.extend_from_slice(extra_outputs);
+    outputs
+}
+
+pub fn generate_outputs(
+    script: &[u8],
+    amount: u64,
+    extra_outputs: &[Output],
+    tx_data: Option<&[u8]>,
+) -> Vec<Output> {
+    let mut outputs = invoice_outputs(script, amount, extra_outputs);
+
+    if let Some(tx_data) = tx_data {
+        let op_return = Output {
+            amount: Some(0),
+            script: op_return_script(tx_data),
+        };
+        outputs.push(op_return);
+    }
+
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bitcoin::Script;
+
+    fn tx(outputs: &[(&[u8], u64)]) -> Transaction {
+        Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: outputs
+                .iter()
+                .map(|(script, value)| TxOut {
+                    value: *value,
+                    script_pubkey: Script::from(script.to_vec()),
+                })
+                .collect(),
+        }
+    }
+
+    fn output(script: &[u8], amount: u64) -> Output {
+        Output {
+            amount: Some(amount),
+            script: script.to_vec(),
+        }
+    }
 
-    vec![p2pkh_output, op_return]
+    #[test]
+    fn test_tally_outputs_per_script() {
+        let (seller, platform) = ([0xaa; 25], [0xbb; 25]);
+        let expected_outputs = vec![output(&seller, 900), output(&platform, 100)];
+
+        // Paying the invoice total to one script leaves the other unpaid
+        let txs = [tx(&[(&platform[..], 1000)])];
+        assert_eq!(
+            tally_outputs(&txs, &expected_outputs),
+            vec![
+                ScriptTally {
+                    script: seller.to_vec(),
+                    expected: 900,
+                    paid: 0,
+                },
+                ScriptTally {
+                    script: platform.to_vec(),
+                    expected: 100,
+                    paid: 1000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tally_outputs_summed() {
+        let (seller, other) = ([0xaa; 25], [0xcc; 25]);
+        let expected_outputs = vec![output(&seller, 600), output(&seller, 300)];
+
+        // Outputs to a script are summed across txs, outputs to other scripts are ignored
+        let txs = [
+            tx(&[(&seller[..], 500), (&other[..], 50)]),
+            tx(&[(&seller[..], 450)]),
+        ];
+        assert_eq!(
+            tally_outputs(&txs, &expected_outputs),
+            vec![ScriptTally {
+                script: seller.to_vec(),
+                expected: 900,
+                paid: 950,
+            }]
+        );
+    }
 }
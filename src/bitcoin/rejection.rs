@@ -0,0 +1,202 @@
+use std::fmt;
+
+use crate::net::jsonrpc_client::ClientError;
+
+// RPC error code for transactions already in the chain
+const RPC_VERIFY_ALREADY_IN_CHAIN: i64 = -27;
+
+// Reasons given by the node for not broadcasting a non-standard transaction
+const NON_STANDARD_REASONS: &[&str] = &[
+    "non-standard",
+    "nonstandard",
+    "version",
+    "tx-size",
+    "scriptsig-size",
+    "scriptsig-not-pushonly",
+    "scriptpubkey",
+    "bare-multisig",
+    "dust",
+    "multi-op-return",
+    "oversize-op-return",
+    "non-mandatory-script-verify-flag",
+];
+
+// Reason a transaction was refused by the node
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxRejection {
+    MissingInputs,
+    InsufficientFee,
+    AlreadyInChain,
+    // The node already has the transaction, as when the wallet broadcast it itself
+    AlreadyInMempool,
+    NonStandard(String),
+    Other(String),
+}
+
+// Reject reason without the code nodes give along with it, as in "64: dust" or
+// "dust (code 64)"
+fn strip_code(reason: &str) -> &str {
+    let reason = reason.trim();
+    let reason = match reason.find(": ") {
+        Some(idx) if reason[..idx].chars().all(|c| c.is_ascii_digit()) => &reason[idx + 2..],
+        _ => reason,
+    };
+    match reason.rfind(" (code ") {
+        Some(idx) => &reason[..idx],
+        None => reason,
+    }
+}
+
+impl TxRejection {
+    // Classify a reject reason as given by testmempoolaccept or sendrawtransaction
+    pub fn from_reason(reason: &str) -> Self {
+        let lower_reason = strip_code(reason).to_lowercase();
+        // Leading token of the reason, details may follow it
+        let token = lower_reason.split_whitespace().next().unwrap_or_default();
+        if lower_reason.contains("missing-inputs")
+            || lower_reason.contains("missing inputs")
+            || lower_reason.contains("inputs-missingorspent")
+        {
+            TxRejection::MissingInputs
+        } else if lower_reason.contains("fee not met")
+            || lower_reason.contains("insufficient fee")
+            || lower_reason.contains("insufficient priority")
+        {
+            TxRejection::InsufficientFee
+        } else if token == "txn-already-in-mempool" || token == "txn-already-known" {
+            TxRejection::AlreadyInMempool
+        } else if lower_reason.contains("already in block chain") {
+            TxRejection::AlreadyInChain
+        } else if NON_STANDARD_REASONS.contains(&token) {
+            TxRejection::NonStandard(reason.to_string())
+        } else {
+            TxRejection::Other(reason.to_string())
+        }
+    }
+
+    // Classify a failed RPC call
+    pub fn from_client_error(err: &ClientError) -> Self {
+        match err {
+            ClientError::Rpc(rpc_error) => {
+                let code = rpc_error.get("code").and_then(|code| code.as_i64());
+                let message = rpc_error
+                    .get("message")
+                    .and_then(|message| message.as_str())
+                    .unwrap_or_default();
+                match TxRejection::from_reason(message) {
+                    TxRejection::Other(_) if code == Some(RPC_VERIFY_ALREADY_IN_CHAIN) => {
+                        TxRejection::AlreadyInChain
+                    }
+                    rejection => rejection,
+                }
+            }
+            _ => TxRejection::Other("node unavailable".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TxRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxRejection::MissingInputs => write!(f, "tx inputs missing or spent"),
+            TxRejection::InsufficientFee => write!(f, "tx fee too low"),
+            TxRejection::AlreadyInChain => write!(f, "tx already in chain"),
+            TxRejection::AlreadyInMempool => write!(f, "tx already in mempool"),
+            TxRejection::NonStandard(reason) => write!(f, "non-standard tx: {}", reason),
+            TxRejection::Other(reason) => write!(f, "tx rejected: {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_already_known() {
+        assert_eq!(
+            TxRejection::from_reason("txn-already-in-mempool"),
+            TxRejection::AlreadyInMempool
+        );
+        assert_eq!(
+            TxRejection::from_reason("txn-already-known"),
+            TxRejection::AlreadyInMempool
+        );
+        assert_eq!(
+            TxRejection::from_reason("18: txn-already-known"),
+            TxRejection::AlreadyInMempool
+        );
+        assert_eq!(
+            TxRejection::from_reason("Transaction already in block chain"),
+            TxRejection::AlreadyInChain
+        );
+    }
+
+    #[test]
+    fn test_non_standard() {
+        assert_eq!(
+            TxRejection::from_reason("64: dust"),
+            TxRejection::NonStandard("64: dust".to_string())
+        );
+        assert_eq!(
+            TxRejection::from_reason("scriptpubkey (code 64)"),
+            TxRejection::NonStandard("scriptpubkey (code 64)".to_string())
+        );
+        assert_eq!(
+            TxRejection::from_reason("non-mandatory-script-verify-flag (Non-canonical DER)"),
+            TxRejection::NonStandard(
+                "non-mandatory-script-verify-flag (Non-canonical DER)".to_string()
+            )
+        );
+        // Reasons merely mentioning a non-standard reason are not classified as such
+        assert_eq!(
+            TxRejection::from_reason("bad-txns-version"),
+            TxRejection::Other("bad-txns-version".to_string())
+        );
+        assert_eq!(
+            TxRejection::from_reason("bad-txns-vout-dust-total"),
+            TxRejection::Other("bad-txns-vout-dust-total".to_string())
+        );
+        assert_eq!(
+            TxRejection::from_reason("mandatory-script-verify-flag-failed (scriptpubkey)"),
+            TxRejection::Other("mandatory-script-verify-flag-failed (scriptpubkey)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_missing_inputs_and_fee() {
+        assert_eq!(
+            TxRejection::from_reason("missing-inputs"),
+            TxRejection::MissingInputs
+        );
+        assert_eq!(
+            TxRejection::from_reason("bad-txns-inputs-missingorspent"),
+            TxRejection::MissingInputs
+        );
+        assert_eq!(
+            TxRejection::from_reason("66: min relay fee not met"),
+            TxRejection::InsufficientFee
+        );
+    }
+
+    #[test]
+    fn test_client_error() {
+        let in_chain = ClientError::Rpc(json!({"code": -27, "message": "txn-already-confirmed"}));
+        assert_eq!(
+            TxRejection::from_client_error(&in_chain),
+            TxRejection::AlreadyInChain
+        );
+        let in_mempool =
+            ClientError::Rpc(json!({"code": -26, "message": "txn-already-in-mempool"}));
+        assert_eq!(
+            TxRejection::from_client_error(&in_mempool),
+            TxRejection::AlreadyInMempool
+        );
+        assert_eq!(
+            TxRejection::from_client_error(&ClientError::NoErrorOrResult),
+            TxRejection::Other("node unavailable".to_string())
+        );
+    }
+}
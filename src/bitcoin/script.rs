@@ -0,0 +1,175 @@
+pub const OP_0: u8 = 0;
+pub const OP_PUSHDATA1: u8 = 76;
+pub const OP_PUSHDATA2: u8 = 77;
+pub const OP_PUSHDATA4: u8 = 78;
+pub const OP_1NEGATE: u8 = 79;
+pub const OP_1: u8 = 81;
+pub const OP_16: u8 = 96;
+pub const OP_RETURN: u8 = 106;
+
+// Largest OP_RETURN script relayed by standard nodes
+pub const MAX_OP_RETURN_SIZE: usize = 223;
+
+// Minimal push of data onto the stack
+pub fn push_data(data: &[u8]) -> Vec<u8> {
+    let len = data.len();
+    let mut raw = Vec::with_capacity(len + 5);
+    if len < OP_PUSHDATA1 as usize {
+        raw.push(len as u8);
+    } else if len <= 0xff {
+        raw.push(OP_PUSHDATA1);
+        raw.push(len as u8);
+    } else if len <= 0xffff {
+        raw.push(OP_PUSHDATA2);
+        raw.extend_from_slice(&(len as u16).to_le_bytes());
+    } else {
+        raw.push(OP_PUSHDATA4);
+        raw.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    raw.extend_from_slice(data);
+    raw
+}
+
+pub fn encode_pushes(pushes: &[Vec<u8>]) -> Vec<u8> {
+    pushes.iter().flat_map(|push| push_data(push)).collect()
+}
+
+// Parse a script consisting solely of data pushes, None if it contains any
+// other opcode or is truncated
+pub fn parse_pushes(raw_script: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut pushes = Vec::new();
+    let mut cursor = 0;
+    while cursor < raw_script.len() {
+        let opcode = raw_script[cursor];
+        cursor += 1;
+
+        // Small integers push their value
+        match opcode {
+            OP_1NEGATE => {
+                pushes.push(vec![0x81]);
+                continue;
+            }
+            OP_1..=OP_16 => {
+                pushes.push(vec![opcode - OP_1 + 1]);
+                continue;
+            }
+            _ => (),
+        }
+
+        let len = match opcode {
+            OP_0 => 0,
+            len if len < OP_PUSHDATA1 => len as usize,
+            OP_PUSHDATA1 => {
+                let len = *raw_script.get(cursor)? as usize;
+                cursor += 1;
+                len
+            }
+            OP_PUSHDATA2 => {
+                let raw_len = raw_script.get(cursor..cursor + 2)?;
+                cursor += 2;
+                u16::from_le_bytes([raw_len[0], raw_len[1]]) as usize
+            }
+            OP_PUSHDATA4 => {
+                let raw_len = raw_script.get(cursor..cursor + 4)?;
+                cursor += 4;
+                u32::from_le_bytes([raw_len[0], raw_len[1], raw_len[2], raw_len[3]]) as usize
+            }
+            _ => return None,
+        };
+        let end = cursor.checked_add(len)?;
+        pushes.push(raw_script.get(cursor..end)?.to_vec());
+        cursor = end;
+    }
+    Some(pushes)
+}
+
+// OP_RETURN followed by already encoded pushes
+pub fn op_return_script(encoded_pushes: &[u8]) -> Vec<u8> {
+    [&[OP_RETURN][..], encoded_pushes].concat()
+}
+
+// Pushes following OP_RETURN, None if the script is not a well formed OP_RETURN
+pub fn extract_op_return(raw_script: &[u8]) -> Option<Vec<Vec<u8>>> {
+    match raw_script.split_first() {
+        Some((&OP_RETURN, rest)) => parse_pushes(rest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(pushes: Vec<Vec<u8>>) {
+        let script = op_return_script(&encode_pushes(&pushes));
+        assert_eq!(extract_op_return(&script), Some(pushes))
+    }
+
+    #[test]
+    fn test_round_trip_direct() {
+        round_trip(vec![b"DEADBEEF".to_vec()]);
+        round_trip(vec![vec![0xab; 75]]);
+    }
+
+    #[test]
+    fn test_round_trip_pushdata() {
+        round_trip(vec![vec![0xab; 76]]);
+        round_trip(vec![vec![0xab; 255]]);
+        round_trip(vec![vec![0xab; 256]]);
+        round_trip(vec![vec![0xab; 0x10000]]);
+    }
+
+    #[test]
+    fn test_round_trip_multi_push() {
+        // memo post
+        round_trip(vec![vec![0x6d, 0x02], b"hello".to_vec()]);
+        // Bitcoin Files
+        round_trip(vec![b"BFP\0".to_vec(), vec![0x01], vec![], vec![0xab; 100]]);
+    }
+
+    #[test]
+    fn test_minimal_encoding() {
+        assert_eq!(push_data(&[]), vec![0]);
+        assert_eq!(push_data(&[1; 75])[0], 75);
+        assert_eq!(push_data(&[1; 76])[..2], [OP_PUSHDATA1, 76]);
+        assert_eq!(push_data(&[1; 256])[..3], [OP_PUSHDATA2, 0, 1]);
+    }
+
+    #[test]
+    fn test_non_minimal_push() {
+        let script = [OP_RETURN, OP_PUSHDATA1, 2, 0xde, 0xad];
+        assert_eq!(extract_op_return(&script), Some(vec![vec![0xde, 0xad]]))
+    }
+
+    #[test]
+    fn test_small_integers() {
+        let script = [OP_RETURN, OP_1, OP_16, OP_1NEGATE];
+        assert_eq!(
+            extract_op_return(&script),
+            Some(vec![vec![1], vec![16], vec![0x81]])
+        )
+    }
+
+    #[test]
+    fn test_empty_op_return() {
+        assert_eq!(extract_op_return(&[OP_RETURN]), Some(vec![]))
+    }
+
+    #[test]
+    fn test_malformed() {
+        // Empty
+        assert_eq!(extract_op_return(&[]), None);
+        // Not OP_RETURN
+        assert_eq!(extract_op_return(&[118, 1, 0]), None);
+        // Truncated direct push
+        assert_eq!(extract_op_return(&[OP_RETURN, 4, 0xde, 0xad]), None);
+        // Truncated length
+        assert_eq!(extract_op_return(&[OP_RETURN, OP_PUSHDATA2, 1]), None);
+        assert_eq!(
+            extract_op_return(&[OP_RETURN, OP_PUSHDATA4, 0xff, 0xff, 0xff, 0xff]),
+            None
+        );
+        // Non-push opcode
+        assert_eq!(extract_op_return(&[OP_RETURN, 1, 0xde, 136]), None);
+    }
+}
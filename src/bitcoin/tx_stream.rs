@@ -2,10 +2,6 @@ use bitcoin::{consensus::encode, util::psbt::serialize::Deserialize, Transaction
 use bitcoin_zmq::{errors::SubscriptionError, Topic, ZMQSubscriber};
 use futures::{Future, Stream};
 
-use crate::crypto::Address;
-
-use super::extract_op_return;
-
 #[derive(Debug)]
 pub enum StreamError {
     Subscription(SubscriptionError),
@@ -18,6 +14,7 @@ impl From<SubscriptionError> for StreamError {
     }
 }
 
+// Stream of transactions entering the mempool or mined in blocks
 pub fn get_tx_stream(
     node_addr: &str,
 ) -> (
@@ -33,15 +30,3 @@ pub fn get_tx_stream(
 
     (stream, broker.map_err(StreamError::Subscription))
 }
-
-// Extract peer address, bitcoin address and metadata digest from tx stream
-pub fn extract_details(
-    stream: impl Stream<Item = Transaction, Error = StreamError>,
-) -> impl Stream<Item = (String, Address), Error = StreamError> {
-    stream.filter_map(|tx| {
-        tx.output
-            .iter()
-            .map(|output| output.script_pubkey.as_bytes())
-            .find_map(extract_op_return)
-    })
-}
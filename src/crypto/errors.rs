@@ -5,6 +5,10 @@ pub enum CryptoError {
     PubkeyDeserialization,
     SigDeserialization,
     Verification,
+    CertLoad,
+    KeyLoad,
+    KeyMismatch,
+    Signing,
 }
 
 impl fmt::Display for CryptoError {
@@ -13,6 +17,10 @@ impl fmt::Display for CryptoError {
             CryptoError::PubkeyDeserialization => "invalid pubkey",
             CryptoError::SigDeserialization => "invalid signature",
             CryptoError::Verification => "verification failed",
+            CryptoError::CertLoad => "failed to load certificate chain",
+            CryptoError::KeyLoad => "failed to load private key",
+            CryptoError::KeyMismatch => "private key does not match the certificate",
+            CryptoError::Signing => "signing failed",
         };
         write!(f, "{}", printable)
     }
@@ -1,6 +1,7 @@
 pub mod ecdsa;
 pub mod errors;
 pub mod token;
+pub mod x509;
 
 use errors::CryptoError;
 
@@ -0,0 +1,141 @@
+use std::fs;
+
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+    x509::X509,
+};
+use prost::Message;
+
+use crate::models::{PaymentRequest, X509Certificates};
+
+use super::errors::CryptoError;
+
+pub const PKI_TYPE: &str = "x509+sha256";
+
+#[derive(Clone)]
+pub struct X509Signer {
+    certificates: Vec<Vec<u8>>,
+    key: PKey<Private>,
+}
+
+impl X509Signer {
+    // Load a PEM certificate chain (leaf first) and a PEM private key
+    pub fn from_files(cert_chain_path: &str, private_key_path: &str) -> Result<Self, CryptoError> {
+        let raw_chain = fs::read(cert_chain_path).map_err(|_| CryptoError::CertLoad)?;
+        let chain = X509::stack_from_pem(&raw_chain).map_err(|_| CryptoError::CertLoad)?;
+        let certificates = chain
+            .iter()
+            .map(|cert| cert.to_der())
+            .collect::<Result<Vec<Vec<u8>>, _>>()
+            .map_err(|_| CryptoError::CertLoad)?;
+        let leaf_key = chain
+            .first()
+            .ok_or(CryptoError::CertLoad)?
+            .public_key()
+            .map_err(|_| CryptoError::CertLoad)?;
+
+        let raw_key = fs::read(private_key_path).map_err(|_| CryptoError::KeyLoad)?;
+        let key = PKey::private_key_from_pem(&raw_key).map_err(|_| CryptoError::KeyLoad)?;
+
+        // Requests signed by any other key would fail verification by every wallet
+        if !leaf_key.public_eq(&key) {
+            return Err(CryptoError::KeyMismatch);
+        }
+
+        Ok(X509Signer { certificates, key })
+    }
+
+    // Sign a payment request as per BIP70
+    pub fn sign(&self, payment_request: &mut PaymentRequest) -> Result<(), CryptoError> {
+        let x509_certificates = X509Certificates {
+            certificate: self.certificates.clone(),
+        };
+        let mut pki_data = Vec::with_capacity(x509_certificates.encoded_len());
+        x509_certificates.encode(&mut pki_data).unwrap();
+
+        // The signature is calculated over the request with an empty signature field
+        payment_request.pki_type = Some(PKI_TYPE.to_string());
+        payment_request.pki_data = Some(pki_data);
+        payment_request.signature = Some(vec![]);
+        let mut unsigned_request = Vec::with_capacity(payment_request.encoded_len());
+        payment_request.encode(&mut unsigned_request).unwrap();
+
+        let mut signer =
+            Signer::new(MessageDigest::sha256(), &self.key).map_err(|_| CryptoError::Signing)?;
+        signer
+            .update(&unsigned_request)
+            .map_err(|_| CryptoError::Signing)?;
+        let signature = signer.sign_to_vec().map_err(|_| CryptoError::Signing)?;
+        payment_request.signature = Some(signature);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use openssl::sign::Verifier;
+
+    use crate::models::PaymentDetails;
+
+    const CERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/crypto/test_data/cert.pem");
+    const KEY_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/crypto/test_data/key.pem");
+    const OTHER_KEY_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/crypto/test_data/other_key.pem"
+    );
+
+    #[test]
+    fn test_sign_verifies() {
+        let signer = X509Signer::from_files(CERT_PATH, KEY_PATH).unwrap();
+        let payment_details = PaymentDetails {
+            time: 1_570_000_000,
+            memo: Some("order 42".to_string()),
+            ..Default::default()
+        };
+        let mut serialized_payment_details = Vec::with_capacity(payment_details.encoded_len());
+        payment_details
+            .encode(&mut serialized_payment_details)
+            .unwrap();
+        let mut payment_request = PaymentRequest {
+            payment_details_version: Some(1),
+            pki_type: Some("none".to_string()),
+            pki_data: None,
+            serialized_payment_details,
+            signature: None,
+        };
+        signer.sign(&mut payment_request).unwrap();
+        assert_eq!(payment_request.pki_type, Some(PKI_TYPE.to_string()));
+
+        // Wallets verify against the leaf certificate with the signature field emptied
+        let raw_pki_data = payment_request.pki_data.clone().unwrap();
+        let certificates = X509Certificates::decode(&raw_pki_data[..]).unwrap();
+        let leaf = X509::from_der(&certificates.certificate[0]).unwrap();
+        let signature = payment_request.signature.replace(vec![]).unwrap();
+        let mut unsigned_request = Vec::with_capacity(payment_request.encoded_len());
+        payment_request.encode(&mut unsigned_request).unwrap();
+
+        let public_key = leaf.public_key().unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).unwrap();
+        verifier.update(&unsigned_request).unwrap();
+        assert!(verifier.verify(&signature).unwrap());
+
+        // Tampering with the request breaks the signature
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).unwrap();
+        verifier.update(&unsigned_request[1..]).unwrap();
+        assert!(!verifier.verify(&signature).unwrap());
+    }
+
+    #[test]
+    fn test_mismatched_key() {
+        match X509Signer::from_files(CERT_PATH, OTHER_KEY_PATH) {
+            Err(CryptoError::KeyMismatch) => (),
+            Err(e) => panic!("expected key mismatch, got {}", e),
+            Ok(_) => panic!("expected key mismatch"),
+        }
+    }
+}
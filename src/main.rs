@@ -3,27 +3,36 @@ extern crate clap;
 #[macro_use]
 extern crate diesel;
 #[macro_use]
+extern crate diesel_migrations;
+#[macro_use]
 extern crate serde_derive;
 
+pub mod addresses;
 pub mod bitcoin;
 pub mod crypto;
+pub mod merchants;
 pub mod net;
+pub mod rates;
 pub mod settings;
 pub mod sql;
+pub mod tasks;
 
-use std::io;
+use std::{io, sync::Arc, time::Duration};
 
 use actix_http::HttpService;
 use actix_web::{dev::Server, middleware::Logger, web, App};
-use diesel::{
-    pg::PgConnection,
-    r2d2::{ConnectionManager, Pool},
-};
 use env_logger::Env;
+use futures::{sync::mpsc, Future};
 use lazy_static::lazy_static;
-use log::info;
+use log::{error, info};
 
-use crate::{bitcoin::BitcoinClient, net::*, settings::Settings};
+use crate::{
+    bitcoin::{block_stream, tx_stream, BitcoinClient},
+    crypto::x509::X509Signer,
+    merchants::Merchants,
+    net::*,
+    settings::{Command, Settings},
+};
 
 pub mod models {
     include!(concat!(env!("OUT_DIR"), "/models.rs"));
@@ -33,8 +42,6 @@ lazy_static! {
     pub static ref SETTINGS: Settings = Settings::new().expect("couldn't load config");
 }
 
-pub type ConnPool = Pool<ConnectionManager<PgConnection>>;
-
 fn main() -> io::Result<()> {
     let sys = actix_rt::System::new("bip70-server");
 
@@ -51,30 +58,91 @@ fn main() -> io::Result<()> {
         SETTINGS.rpc_password.clone(),
     );
 
-    // Init SQL connection
-    let url = format!(
-        "{}://{}:{}@{}:{}/{}",
-        SETTINGS.sql.prefix,
-        SETTINGS.sql.username,
-        SETTINGS.sql.password,
-        SETTINGS.sql.host,
-        SETTINGS.sql.port,
-        SETTINGS.sql.db
+    // Init payment store
+    let store = sql::from_settings(&SETTINGS.sql).expect("failed to open database");
+    if SETTINGS.command == Command::Migrate {
+        store.run_migrations().expect("failed to migrate database");
+        info!("database migrated");
+        return Ok(());
+    }
+    if SETTINGS.sql.run_migrations {
+        store.run_migrations().expect("failed to migrate database");
+    }
+    if let Err(err) = store.check_schema() {
+        error!("{}", err);
+        return Err(io::Error::new(io::ErrorKind::Other, err.to_string()));
+    }
+
+    // Init payment request signer
+    let signer = SETTINGS.pki.as_ref().map(|pki| {
+        info!("signing payment requests with {}", pki.cert_chain);
+        X509Signer::from_files(&pki.cert_chain, &pki.private_key)
+            .expect("failed to load pki settings")
+    });
+
+    // Init exchange rate provider
+    let rate_provider =
+        rates::from_settings(&SETTINGS.rates).expect("failed to load exchange rate settings");
+
+    // Init merchants along with their address sources and pools
+    let merchant_rows = merchants::from_settings(&SETTINGS).expect("failed to load merchants");
+    let merchant_ids: Vec<String> = merchant_rows.iter().map(|row| row.id.clone()).collect();
+    store
+        .revoke_api_keys(&merchant_ids)
+        .expect("failed to revoke API keys");
+    for merchant_row in &merchant_rows {
+        store
+            .upsert_merchant(merchant_row)
+            .expect("failed to store merchant");
+    }
+    let merchants = Arc::new(
+        Merchants::start(&SETTINGS, bitcoin_client.clone(), store.clone())
+            .expect("failed to start merchants"),
     );
-    let manager = ConnectionManager::<PgConnection>::new(url);
-    let pool = Pool::builder()
-        .build(manager)
-        .expect("failed to create pool");
 
     // Init ZMQ
-    // TODO: Check confirmations
-    // let (tx_stream, connection) =
-    //     tx_stream::get_tx_stream(&format!("tcp://{}:{}", SETTINGS.node_ip, SETTINGS.zmq_port));
-    // let key_stream = tx_stream::extract_details(tx_stream);
-    // actix_rt::Arbiter::current().send(connection.map_err(|e| error!("{:?}", e)));
+    let (block_stream, connection) = block_stream::get_block_stream(&format!(
+        "tcp://{}:{}",
+        SETTINGS.node_ip, SETTINGS.zmq_port
+    ));
+    actix_rt::spawn(connection.map_err(|e| error!("{:?}", e)));
+    let (tx_stream, connection) =
+        tx_stream::get_tx_stream(&format!("tcp://{}:{}", SETTINGS.node_ip, SETTINGS.zmq_port));
+    actix_rt::spawn(connection.map_err(|e| error!("{:?}", e)));
+
+    // Init confirmation watcher
+    actix_rt::spawn(tasks::confirmations::confirmation_watcher(
+        bitcoin_client.clone(),
+        store.clone(),
+        block_stream,
+        SETTINGS.confirmations,
+    ));
+
+    // Init double spend watcher
+    let (accepted_payments, accepted_receiver) = mpsc::unbounded();
+    actix_rt::spawn(tasks::double_spends::double_spend_watcher(
+        bitcoin_client.clone(),
+        store.clone(),
+        tx_stream,
+        accepted_receiver,
+        Duration::from_secs(SETTINGS.double_spend_interval),
+    ));
+
+    // Init expiry sweeper
+    actix_rt::spawn(tasks::expiry::expiry_sweeper(
+        store.clone(),
+        Duration::from_secs(SETTINGS.expiry_sweep_interval),
+    ));
+
+    // Init callback sender
+    actix_rt::spawn(tasks::callbacks::callback_sender(
+        store.clone(),
+        &SETTINGS.callback,
+    ));
 
     let bitcoin_client_inner = bitcoin_client.clone();
-    let pool_inner = pool.clone();
+    let store_inner = store.clone();
+    let merchants_inner = merchants.clone();
 
     Server::build()
         .bind("public", &SETTINGS.bind_public, move || {
@@ -86,7 +154,10 @@ fn main() -> io::Result<()> {
                     .service(
                         // Payment route
                         web::resource("/payment/{payment_id}")
-                            .data((bitcoin_client_inner.to_owned(), pool_inner.to_owned()))
+                            .data((bitcoin_client_inner.to_owned(), store_inner.to_owned()))
+                            .data(merchants_inner.to_owned())
+                            .data(accepted_payments.to_owned())
+                            .route(web::get().to_async(payment_request_handler))
                             .route(web::post().to_async(payment_handler)),
                     ),
             )
@@ -101,8 +172,39 @@ fn main() -> io::Result<()> {
                     .service(
                         // Create invoice route
                         web::resource("/invoice")
-                            .data((bitcoin_client.to_owned(), pool.to_owned()))
+                            .data((bitcoin_client.to_owned(), store.to_owned()))
+                            .data(merchants.to_owned())
+                            .data(signer.to_owned())
+                            .data(rate_provider.to_owned())
                             .route(web::post().to_async(generate_invoice)),
+                    )
+                    .service(
+                        // Invoice status route
+                        web::resource("/invoice/{payment_id}")
+                            .data((bitcoin_client.to_owned(), store.to_owned()))
+                            .data(merchants.to_owned())
+                            .route(web::get().to_async(invoice_status::invoice_status_handler)),
+                    )
+                    .service(
+                        // Payment URI and QR code route
+                        web::resource("/invoice/{payment_id}/qr")
+                            .data((bitcoin_client.to_owned(), store.to_owned()))
+                            .data(merchants.to_owned())
+                            .route(web::get().to_async(payment_uri::qr_handler)),
+                    )
+                    .service(
+                        // Metrics route
+                        web::resource("/metrics")
+                            .data(merchants.to_owned())
+                            .route(web::get().to(metrics::metrics_handler)),
+                    )
+                    .service(
+                        // Refund route
+                        web::resource("/invoice/{payment_id}/refund")
+                            .data((bitcoin_client.to_owned(), store.to_owned()))
+                            .data(merchants.to_owned())
+                            .route(web::get().to_async(get_refund))
+                            .route(web::post().to_async(refund_payment)),
                     ),
             )
         })
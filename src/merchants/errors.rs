@@ -0,0 +1,37 @@
+use std::fmt;
+
+use crate::{addresses::errors::AddressSourceError, sql::errors::StoreError};
+
+#[derive(Debug)]
+pub enum MerchantError {
+    DuplicateId,
+    DuplicateApiKey,
+    Unauthorized,
+    AddressSource(AddressSourceError),
+    Store(StoreError),
+}
+
+impl fmt::Display for MerchantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match self {
+            MerchantError::DuplicateId => "merchant ID configured twice",
+            MerchantError::DuplicateApiKey => "merchant API key configured twice",
+            MerchantError::Unauthorized => "unknown API key",
+            MerchantError::AddressSource(err) => return err.fmt(f),
+            MerchantError::Store(err) => return err.fmt(f),
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+impl From<AddressSourceError> for MerchantError {
+    fn from(err: AddressSourceError) -> Self {
+        MerchantError::AddressSource(err)
+    }
+}
+
+impl From<StoreError> for MerchantError {
+    fn from(err: StoreError) -> Self {
+        MerchantError::Store(err)
+    }
+}
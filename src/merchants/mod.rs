@@ -0,0 +1,200 @@
+pub mod errors;
+
+use std::{collections::HashMap, sync::Arc};
+
+use actix_web::{http::header::AUTHORIZATION, HttpRequest};
+use bitcoin_hashes::{sha256, Hash};
+use log::info;
+
+use crate::{
+    addresses::{
+        self,
+        pool::{PoolMetrics, PooledAddresses},
+        AddressProvider,
+    },
+    bitcoin::BitcoinClient,
+    settings::{Addresses, Settings},
+    sql::{models::MerchantRow, Store},
+    tasks,
+};
+
+use errors::MerchantError;
+
+// Merchant configured by the top-level settings, private API calls without an API key act as it
+// if enabled
+pub const DEFAULT_MERCHANT: &str = "default";
+
+pub type MerchantRegistry = Arc<Merchants>;
+
+// Merchant along with the clients serving its invoices
+pub struct Merchant {
+    pub row: MerchantRow,
+    // Client of the merchant's node wallet
+    pub bitcoin_client: BitcoinClient,
+    pub address_provider: AddressProvider,
+    pub pool_metrics: Arc<PoolMetrics>,
+}
+
+// API keys are stored hashed
+pub fn hash_api_key(api_key: &str) -> String {
+    hex::encode(&sha256::Hash::hash(api_key.as_bytes())[..])
+}
+
+// Rows of the default merchant and of the merchants in the config file
+pub fn from_settings(settings: &Settings) -> Result<Vec<MerchantRow>, MerchantError> {
+    let mut rows = vec![MerchantRow {
+        id: DEFAULT_MERCHANT.to_string(),
+        api_key_hash: settings
+            .api_key
+            .as_ref()
+            .map(|api_key| hash_api_key(api_key)),
+        secret: settings.secret.clone(),
+        address_source: settings.addresses.source.clone(),
+        wallet: None,
+        xpub: settings.addresses.xpub.clone(),
+        derivation_path: settings.addresses.path.clone(),
+        req_memo: None,
+        ack_memo: None,
+        expiry: None,
+        callback_url: None,
+    }];
+    for merchant in &settings.merchants {
+        let api_key_hash = Some(hash_api_key(&merchant.api_key));
+        if rows.iter().any(|row| row.id == merchant.id) {
+            return Err(MerchantError::DuplicateId);
+        }
+        if rows.iter().any(|row| row.api_key_hash == api_key_hash) {
+            return Err(MerchantError::DuplicateApiKey);
+        }
+        let addresses = merchant.addresses.as_ref();
+        rows.push(MerchantRow {
+            id: merchant.id.clone(),
+            api_key_hash,
+            secret: merchant.secret.clone(),
+            address_source: addresses
+                .map(|addresses| addresses.source.clone())
+                .unwrap_or_else(|| "node".to_string()),
+            wallet: merchant.wallet.clone(),
+            xpub: addresses.and_then(|addresses| addresses.xpub.clone()),
+            derivation_path: addresses
+                .map(|addresses| addresses.path.clone())
+                .unwrap_or_else(|| "m/0".to_string()),
+            req_memo: merchant.req_memo.clone(),
+            ack_memo: merchant.ack_memo.clone(),
+            expiry: merchant.expiry.map(|expiry| expiry as i64),
+            callback_url: merchant.callback_url.clone(),
+        });
+    }
+    Ok(rows)
+}
+
+#[derive(Default)]
+pub struct Merchants {
+    by_id: HashMap<String, Arc<Merchant>>,
+    by_api_key_hash: HashMap<String, Arc<Merchant>>,
+    anonymous_default: bool,
+}
+
+impl Merchants {
+    // Set up every merchant in the store, spawning an address pool refiller for each. Merchants
+    // no longer configured still serve their invoices but have had their API keys revoked
+    pub fn start(
+        settings: &'static Settings,
+        bitcoin_client: BitcoinClient,
+        store: Store,
+    ) -> Result<Self, MerchantError> {
+        let mut merchants = Merchants {
+            anonymous_default: settings.anonymous_default,
+            ..Merchants::default()
+        };
+        for row in store.get_merchants()? {
+            // Each wallet is served on its own RPC endpoint
+            let bitcoin_client = match &row.wallet {
+                Some(wallet) => BitcoinClient::new(
+                    format!(
+                        "http://{}:{}/wallet/{}",
+                        settings.node_ip, settings.rpc_port, wallet
+                    ),
+                    settings.rpc_username.clone(),
+                    settings.rpc_password.clone(),
+                ),
+                None => bitcoin_client.clone(),
+            };
+
+            let address_settings = Addresses {
+                source: row.address_source.clone(),
+                xpub: row.xpub.clone(),
+                path: row.derivation_path.clone(),
+            };
+            let address_source = addresses::from_settings(
+                &address_settings,
+                &settings.network,
+                bitcoin_client.clone(),
+                store.clone(),
+            )?;
+            info!(
+                "deriving invoice addresses of {} from {}",
+                row.id, row.address_source
+            );
+
+            let pool_metrics = Arc::new(PoolMetrics::default());
+            let address_provider: AddressProvider = if settings.address_pool.size > 0 {
+                actix_rt::spawn(tasks::address_pool::address_pool_refiller(
+                    row.id.clone(),
+                    address_source.clone(),
+                    store.clone(),
+                    pool_metrics.clone(),
+                    &settings.address_pool,
+                ));
+                Arc::new(PooledAddresses::new(
+                    row.id.clone(),
+                    address_source,
+                    store.clone(),
+                    pool_metrics.clone(),
+                ))
+            } else {
+                address_source
+            };
+
+            let merchant = Arc::new(Merchant {
+                row,
+                bitcoin_client,
+                address_provider,
+                pool_metrics,
+            });
+            if let Some(api_key_hash) = &merchant.row.api_key_hash {
+                merchants
+                    .by_api_key_hash
+                    .insert(api_key_hash.clone(), merchant.clone());
+            }
+            merchants.by_id.insert(merchant.row.id.clone(), merchant);
+        }
+        Ok(merchants)
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Merchant>> {
+        self.by_id.get(id).cloned()
+    }
+
+    // Merchant owning the bearer API key of a private API call, the default merchant if none is
+    // given and anonymous calls are enabled
+    pub fn authenticate(&self, req: &HttpRequest) -> Result<Arc<Merchant>, MerchantError> {
+        let authorization = match req.headers().get(AUTHORIZATION) {
+            Some(some) => some.to_str().map_err(|_| MerchantError::Unauthorized)?,
+            None if self.anonymous_default => {
+                return self
+                    .get(DEFAULT_MERCHANT)
+                    .ok_or(MerchantError::Unauthorized)
+            }
+            None => return Err(MerchantError::Unauthorized),
+        };
+        if !authorization.starts_with("Bearer ") {
+            return Err(MerchantError::Unauthorized);
+        }
+        let api_key_hash = hash_api_key(&authorization["Bearer ".len()..]);
+        self.by_api_key_hash
+            .get(&api_key_hash)
+            .cloned()
+            .ok_or(MerchantError::Unauthorized)
+    }
+}
@@ -3,20 +3,27 @@ use std::fmt;
 use actix_web::{error, HttpResponse};
 use bitcoin::consensus::encode::Error as TxDeserializeError;
 use bitcoincash_addr::AddressError;
-use diesel::result::Error as DieselError;
 use prost::DecodeError;
 
-use crate::crypto::errors::CryptoError;
+use crate::{
+    addresses::errors::AddressSourceError, bitcoin::TxRejection, crypto::errors::CryptoError,
+    merchants::errors::MerchantError, rates::errors::ExchangeRateError, sql::errors::StoreError,
+};
 
 #[derive(Debug)]
 pub enum ServerError {
     Crypto(CryptoError),
     NotFound,
     InvoiceRequestDecode,
+    RefundRequestDecode,
+    QrEncode,
     UnsupportedSigScheme,
     Payment(PaymentError),
     Address(AddressError),
-    Diesel(DieselError),
+    AddressSource(AddressSourceError),
+    Store(StoreError),
+    ExchangeRate(ExchangeRateError),
+    Merchant(MerchantError),
 }
 
 impl fmt::Display for ServerError {
@@ -25,10 +32,15 @@ impl fmt::Display for ServerError {
             ServerError::Crypto(err) => return err.fmt(f),
             ServerError::NotFound => "not found",
             ServerError::InvoiceRequestDecode => "invoice request decoding error",
+            ServerError::RefundRequestDecode => "refund request decoding error",
+            ServerError::QrEncode => "failed to encode QR code",
             ServerError::UnsupportedSigScheme => "signature scheme not supported",
             ServerError::Payment(err) => return err.fmt(f),
             ServerError::Address(err) => return err.fmt(f),
-            ServerError::Diesel(err) => return err.fmt(f),
+            ServerError::AddressSource(err) => return err.fmt(f),
+            ServerError::Store(err) => return err.fmt(f),
+            ServerError::ExchangeRate(err) => return err.fmt(f),
+            ServerError::Merchant(err) => return err.fmt(f),
         };
         write!(f, "{}", printable)
     }
@@ -52,9 +64,21 @@ impl From<DecodeError> for ServerError {
     }
 }
 
-impl From<DieselError> for ServerError {
-    fn from(err: DieselError) -> Self {
-        ServerError::Diesel(err)
+impl From<ExchangeRateError> for ServerError {
+    fn from(err: ExchangeRateError) -> Self {
+        ServerError::ExchangeRate(err)
+    }
+}
+
+impl From<StoreError> for ServerError {
+    fn from(err: StoreError) -> Self {
+        ServerError::Store(err)
+    }
+}
+
+impl From<MerchantError> for ServerError {
+    fn from(err: MerchantError) -> Self {
+        ServerError::Merchant(err)
     }
 }
 
@@ -64,6 +88,53 @@ impl error::ResponseError for CryptoError {
             CryptoError::PubkeyDeserialization => HttpResponse::BadRequest(),
             CryptoError::SigDeserialization => HttpResponse::BadRequest(),
             CryptoError::Verification => HttpResponse::BadRequest(),
+            CryptoError::CertLoad => HttpResponse::InternalServerError(),
+            CryptoError::KeyLoad => HttpResponse::InternalServerError(),
+            CryptoError::Signing => HttpResponse::InternalServerError(),
+        }
+        .body(self.to_string())
+    }
+}
+
+impl error::ResponseError for ExchangeRateError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ExchangeRateError::NoProvider => HttpResponse::BadRequest(),
+            ExchangeRateError::UnsupportedCurrency => HttpResponse::BadRequest(),
+            ExchangeRateError::InvalidAmount => HttpResponse::BadRequest(),
+            ExchangeRateError::Load => HttpResponse::InternalServerError(),
+            ExchangeRateError::Fetch => HttpResponse::BadGateway(),
+            ExchangeRateError::Parse => HttpResponse::BadGateway(),
+        }
+        .body(self.to_string())
+    }
+}
+
+impl error::ResponseError for AddressSourceError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AddressSourceError::Fetch => HttpResponse::BadGateway(),
+            _ => HttpResponse::InternalServerError(),
+        }
+        .body(self.to_string())
+    }
+}
+
+impl error::ResponseError for StoreError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            StoreError::NotFound => HttpResponse::NotFound(),
+            _ => HttpResponse::InternalServerError(),
+        }
+        .body(self.to_string())
+    }
+}
+
+impl error::ResponseError for MerchantError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            MerchantError::Unauthorized => HttpResponse::Unauthorized(),
+            _ => HttpResponse::InternalServerError(),
         }
         .body(self.to_string())
     }
@@ -75,11 +146,16 @@ impl error::ResponseError for ServerError {
             // Do not yield sensitive information to clients
             ServerError::NotFound => HttpResponse::NotFound().body(self.to_string()),
             ServerError::InvoiceRequestDecode => HttpResponse::BadRequest().body(self.to_string()),
+            ServerError::RefundRequestDecode => HttpResponse::BadRequest().body(self.to_string()),
+            ServerError::QrEncode => HttpResponse::InternalServerError().body(self.to_string()),
             ServerError::UnsupportedSigScheme => HttpResponse::BadRequest().body(self.to_string()),
             ServerError::Crypto(err) => err.error_response(),
             ServerError::Payment(err) => err.error_response(),
             ServerError::Address(err) => HttpResponse::BadRequest().body(err.to_string()),
-            ServerError::Diesel(err) => HttpResponse::BadRequest().body(err.to_string()),
+            ServerError::AddressSource(err) => err.error_response(),
+            ServerError::Store(err) => err.error_response(),
+            ServerError::ExchangeRate(err) => err.error_response(),
+            ServerError::Merchant(err) => err.error_response(),
         }
     }
 }
@@ -98,9 +174,22 @@ pub enum PaymentError {
     NoTx,
     TxDeserialize(TxDeserializeError),
     InvalidOutputs,
-    InvalidTx,
+    TxRejected(TxRejection),
     MismatchedNetwork,
-    AddrFetchFailed,
+    UnsupportedAddress,
+    InvalidTxData,
+    Expired,
+    UnsupportedCurrency,
+    NotPending,
+    NotPaid,
+    Underpaid,
+    Overpaid,
+    AlreadyRefunded,
+    RefundExceedsReceived,
+    NoRefundTo,
+    MultipleRefundTo,
+    UnsupportedRefundScript,
+    RefundFailed,
 }
 
 impl From<PaymentError> for ServerError {
@@ -130,9 +219,22 @@ impl fmt::Display for PaymentError {
             PaymentError::NoTx => "no payment tx",
             PaymentError::TxDeserialize(_) => "payment tx malformed",
             PaymentError::InvalidOutputs => "invalid outputs",
-            PaymentError::InvalidTx => "invalid tx",
-            PaymentError::AddrFetchFailed => "failed to fetch address",
+            PaymentError::TxRejected(rejection) => return rejection.fmt(f),
             PaymentError::MismatchedNetwork => "address mismatched with node network",
+            PaymentError::UnsupportedAddress => "unsupported address type",
+            PaymentError::InvalidTxData => "invalid tx data",
+            PaymentError::Expired => "payment request expired",
+            PaymentError::UnsupportedCurrency => "unsupported currency",
+            PaymentError::NotPending => "payment request no longer pending",
+            PaymentError::NotPaid => "payment not received",
+            PaymentError::Underpaid => "payment below amount due",
+            PaymentError::Overpaid => "payment exceeds amount due",
+            PaymentError::AlreadyRefunded => "payment already refunded",
+            PaymentError::RefundExceedsReceived => "refund exceeds amount received",
+            PaymentError::NoRefundTo => "no refund outputs",
+            PaymentError::MultipleRefundTo => "refunds to several outputs are not supported",
+            PaymentError::UnsupportedRefundScript => "unsupported refund script",
+            PaymentError::RefundFailed => "failed to send refund",
         };
         write!(f, "{}", printable)
     }
@@ -153,9 +255,23 @@ impl error::ResponseError for PaymentError {
             PaymentError::NoTx => HttpResponse::BadRequest(),
             PaymentError::TxDeserialize(_) => HttpResponse::BadRequest(),
             PaymentError::InvalidOutputs => HttpResponse::BadRequest(),
-            PaymentError::InvalidTx => HttpResponse::BadRequest(),
+            PaymentError::TxRejected(TxRejection::AlreadyInChain) => HttpResponse::Conflict(),
+            PaymentError::TxRejected(_) => HttpResponse::BadRequest(),
             PaymentError::MismatchedNetwork => HttpResponse::BadRequest(),
-            PaymentError::AddrFetchFailed => HttpResponse::InternalServerError(),
+            PaymentError::UnsupportedAddress => HttpResponse::InternalServerError(),
+            PaymentError::InvalidTxData => HttpResponse::BadRequest(),
+            PaymentError::Expired => HttpResponse::Gone(),
+            PaymentError::UnsupportedCurrency => HttpResponse::BadRequest(),
+            PaymentError::NotPending => HttpResponse::Conflict(),
+            PaymentError::NotPaid => HttpResponse::BadRequest(),
+            PaymentError::Underpaid => HttpResponse::BadRequest(),
+            PaymentError::Overpaid => HttpResponse::BadRequest(),
+            PaymentError::AlreadyRefunded => HttpResponse::Conflict(),
+            PaymentError::RefundExceedsReceived => HttpResponse::BadRequest(),
+            PaymentError::NoRefundTo => HttpResponse::BadRequest(),
+            PaymentError::MultipleRefundTo => HttpResponse::BadRequest(),
+            PaymentError::UnsupportedRefundScript => HttpResponse::BadRequest(),
+            PaymentError::RefundFailed => HttpResponse::InternalServerError(),
         }
         .body(self.to_string())
     }
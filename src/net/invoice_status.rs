@@ -0,0 +1,207 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::NaiveDateTime;
+use futures::future::{err, Future};
+use prost::Message;
+use serde::Serialize;
+
+use crate::{
+    bitcoin::BitcoinClient,
+    merchants::MerchantRegistry,
+    models::*,
+    sql::{
+        models::{CallbackStateEnum, PaymentRow, PaymentStateEnum, RefundOutputRow},
+        Store,
+    },
+};
+
+use super::{accepted_types, check_owner, errors::*, expected_outputs};
+
+#[derive(Debug, Serialize)]
+pub struct JsonRefundOutput {
+    pub amount: Option<u64>,
+    pub script: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonInvoiceStatus {
+    pub payment_id: String,
+    pub state: PaymentStateEnum,
+    pub time: NaiveDateTime,
+    pub expires: Option<NaiveDateTime>,
+    pub amount: u64,
+    pub payment_time: Option<NaiveDateTime>,
+    pub tx_ids: Vec<String>,
+    pub refund_to: Vec<JsonRefundOutput>,
+    pub refund_tx_id: Option<String>,
+    pub block_hash: Option<String>,
+    pub block_height: Option<i32>,
+    pub callback_state: Option<CallbackStateEnum>,
+    pub fiat_currency: Option<String>,
+    pub fiat_amount: Option<f64>,
+    pub exchange_rate: Option<f64>,
+    pub rejection_reason: Option<String>,
+    pub double_spend_tx_id: Option<String>,
+    pub amount_received: u64,
+    pub amount_due: u64,
+    pub overpaid_amount: u64,
+}
+
+struct InvoiceRecord {
+    payment_row: PaymentRow,
+    amount: u64,
+    tx_ids: Vec<String>,
+    refund_outputs: Vec<RefundOutputRow>,
+    callback_state: Option<CallbackStateEnum>,
+}
+
+fn payment_state_to_proto(payment_state: PaymentStateEnum) -> PaymentState {
+    match payment_state {
+        PaymentStateEnum::Pending => PaymentState::Pending,
+        PaymentStateEnum::Received => PaymentState::Received,
+        PaymentStateEnum::Confirmed => PaymentState::Confirmed,
+        PaymentStateEnum::Rejected => PaymentState::Rejected,
+        PaymentStateEnum::Expired => PaymentState::Expired,
+        PaymentStateEnum::DoubleSpent => PaymentState::DoubleSpent,
+        PaymentStateEnum::Underpaid => PaymentState::Underpaid,
+    }
+}
+
+fn callback_state_to_proto(callback_state: Option<CallbackStateEnum>) -> CallbackState {
+    match callback_state {
+        None => CallbackState::NoCallback,
+        Some(CallbackStateEnum::Pending) => CallbackState::CallbackPending,
+        Some(CallbackStateEnum::Delivered) => CallbackState::CallbackDelivered,
+        Some(CallbackStateEnum::Failed) => CallbackState::CallbackFailed,
+    }
+}
+
+fn to_timestamp(time: Option<NaiveDateTime>) -> u64 {
+    time.map(|time| time.timestamp() as u64).unwrap_or(0)
+}
+
+fn to_proto(record: InvoiceRecord) -> InvoiceStatus {
+    let payment_row = record.payment_row;
+    InvoiceStatus {
+        payment_id: payment_row.id.to_string(),
+        state: payment_state_to_proto(payment_row.payment_state) as i32,
+        time: to_timestamp(Some(payment_row.issue_time)),
+        expires: to_timestamp(payment_row.expiry_time),
+        amount: record.amount,
+        payment_time: to_timestamp(payment_row.payment_time),
+        tx_ids: record.tx_ids,
+        refund_to: record
+            .refund_outputs
+            .into_iter()
+            .map(|refund_output| Output {
+                amount: refund_output.amount.map(|amount| amount as u64),
+                script: refund_output.script,
+            })
+            .collect(),
+        refund_tx_id: payment_row.refund_tx_id.unwrap_or_default(),
+        block_hash: payment_row.block_hash.unwrap_or_default(),
+        block_height: payment_row.block_height.unwrap_or(0) as u32,
+        callback_state: callback_state_to_proto(record.callback_state) as i32,
+        fiat_currency: payment_row.fiat_currency.unwrap_or_default(),
+        fiat_amount: payment_row.fiat_amount.unwrap_or(0.),
+        exchange_rate: payment_row.exchange_rate.unwrap_or(0.),
+        rejection_reason: payment_row.rejection_reason.unwrap_or_default(),
+        double_spend_tx_id: payment_row.double_spend_tx_id.unwrap_or_default(),
+        amount_received: payment_row.amount_received as u64,
+        amount_due: payment_row.amount_due as u64,
+        overpaid_amount: payment_row.overpaid_amount as u64,
+    }
+}
+
+fn to_json(record: InvoiceRecord) -> JsonInvoiceStatus {
+    let payment_row = record.payment_row;
+    JsonInvoiceStatus {
+        payment_id: payment_row.id.to_string(),
+        state: payment_row.payment_state,
+        time: payment_row.issue_time,
+        expires: payment_row.expiry_time,
+        amount: record.amount,
+        payment_time: payment_row.payment_time,
+        tx_ids: record.tx_ids,
+        refund_to: record
+            .refund_outputs
+            .into_iter()
+            .map(|refund_output| JsonRefundOutput {
+                amount: refund_output.amount.map(|amount| amount as u64),
+                script: hex::encode(refund_output.script),
+            })
+            .collect(),
+        refund_tx_id: payment_row.refund_tx_id,
+        block_hash: payment_row.block_hash,
+        block_height: payment_row.block_height,
+        callback_state: record.callback_state,
+        fiat_currency: payment_row.fiat_currency,
+        fiat_amount: payment_row.fiat_amount,
+        exchange_rate: payment_row.exchange_rate,
+        rejection_reason: payment_row.rejection_reason,
+        double_spend_tx_id: payment_row.double_spend_tx_id,
+        amount_received: payment_row.amount_received as u64,
+        amount_due: payment_row.amount_due as u64,
+        overpaid_amount: payment_row.overpaid_amount as u64,
+    }
+}
+
+// Invoice status handler
+pub fn invoice_status_handler(
+    req: HttpRequest,
+    payment_id: web::Path<String>,
+    data: web::Data<(BitcoinClient, Store)>,
+    merchants: web::Data<MerchantRegistry>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    let store = data.1.to_owned();
+    let merchant = match merchants.authenticate(&req) {
+        Ok(ok) => ok,
+        Err(e) => return Box::new(err(e.into())),
+    };
+    // JSON is served if asked for ahead of protobuf
+    let json = accepted_types(&req)
+        .iter()
+        .find(|media_type| {
+            *media_type == "application/json" || *media_type == "application/x-protobuf"
+        })
+        .map(String::as_str)
+        == Some("application/json");
+
+    // Get payment row and related rows
+    let record = actix_web::web::block(move || {
+        let payment_row = store.get_payment(&payment_id)?;
+        let payment_outputs = store.get_payment_outputs(&payment_id)?;
+        let amount = expected_outputs(&payment_row, payment_outputs)
+            .iter()
+            .map(|output| output.amount.unwrap_or(0))
+            .sum();
+        Ok(InvoiceRecord {
+            payment_row,
+            amount,
+            tx_ids: store.get_payment_tx_ids(&payment_id)?,
+            refund_outputs: store.get_refund_outputs(&payment_id)?,
+            callback_state: store.get_callback_state(&payment_id)?,
+        })
+    })
+    .map_err(|err| match err {
+        actix_threadpool::BlockingError::Error(e) => ServerError::Store(e),
+        _ => unreachable!(),
+    });
+
+    let response = record.and_then(move |record| {
+        check_owner(&merchant, &record.payment_row)?;
+        Ok(if json {
+            HttpResponse::Ok()
+                .content_type("application/json")
+                .body(serde_json::to_string(&to_json(record)).unwrap())
+        } else {
+            let invoice_status = to_proto(record);
+            let mut raw_invoice_status = Vec::with_capacity(invoice_status.encoded_len());
+            invoice_status.encode(&mut raw_invoice_status).unwrap();
+            HttpResponse::Ok()
+                .content_type("application/x-protobuf")
+                .body(raw_invoice_status)
+        })
+    });
+
+    Box::new(response)
+}
@@ -0,0 +1,231 @@
+use actix_web::{web, HttpResponse};
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+use futures::{future::Future, stream::Stream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::*,
+    models::*,
+    sql::{models::PaymentStateEnum, Store},
+    tasks::double_spends::AcceptedPayments,
+    SETTINGS,
+};
+
+use super::{errors::*, expected_outputs, get_payment_rows, process_payment};
+
+pub const PAYMENT_REQUEST_CONTENT_TYPE: &str = "application/payment-request";
+pub const PAYMENT_CONTENT_TYPE: &str = "application/payment";
+pub const VERIFICATION_CONTENT_TYPE: &str = "application/payment-verification";
+pub const PAYMENT_ACK_CONTENT_TYPE: &str = "application/payment-ack";
+
+const CURRENCY: &str = "BCH";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonPaymentRequest {
+    pub network: String,
+    pub currency: String,
+    pub outputs: Vec<JsonOutput>,
+    pub time: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+    pub memo: Option<String>,
+    pub payment_url: String,
+    pub payment_id: String,
+    pub required_fee_rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonOutput {
+    pub amount: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    // Outputs which cannot be represented by an address, such as OP_RETURN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonPayment {
+    pub currency: String,
+    pub transactions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonPaymentVerification {
+    pub currency: String,
+    pub unsigned_transaction: String,
+    // Size of the transaction once signed
+    pub weighted_size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsonPaymentAck {
+    pub payment: JsonPayment,
+    pub memo: Option<String>,
+}
+
+fn network_name(network: &Network) -> String {
+    match network {
+        Network::Mainnet => "main".to_string(),
+        Network::Testnet => "test".to_string(),
+        Network::Regnet => "regtest".to_string(),
+    }
+}
+
+fn to_json_output(output: &Output) -> JsonOutput {
+    let amount = output.amount.unwrap_or(0);
+    match script_to_address(&output.script, SETTINGS.network.clone()) {
+        Some(address) => JsonOutput {
+            amount,
+            address: Some(address),
+            script: None,
+        },
+        None => JsonOutput {
+            amount,
+            address: None,
+            script: Some(hex::encode(&output.script)),
+        },
+    }
+}
+
+// Serve the payment request in JSON form
+pub fn payment_request_handler(
+    payment_id: web::Path<String>,
+    data: web::Data<(BitcoinClient, Store)>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    let store = data.1.to_owned();
+    let payment_id = payment_id.to_string();
+
+    let response = get_payment_rows(store, payment_id.clone()).and_then(
+        move |(payment_row, payment_outputs)| {
+            match payment_row.payment_state {
+                PaymentStateEnum::Pending => (),
+                PaymentStateEnum::Expired => return Err(PaymentError::Expired.into()),
+                _ => return Err(PaymentError::NotPending.into()),
+            }
+            if let Some(expiry_time) = payment_row.expiry_time {
+                if expiry_time < Utc::now().naive_utc() {
+                    return Err(PaymentError::Expired.into());
+                }
+            }
+
+            // Generate outputs
+            let mut outputs: Vec<JsonOutput> = expected_outputs(&payment_row, payment_outputs)
+                .iter()
+                .map(to_json_output)
+                .collect();
+            if let Some(tx_data) = &payment_row.tx_data {
+                outputs.push(to_json_output(&Output {
+                    amount: Some(0),
+                    script: op_return_script(tx_data),
+                }));
+            }
+
+            let payment_request = JsonPaymentRequest {
+                network: network_name(&SETTINGS.network),
+                currency: CURRENCY.to_string(),
+                outputs,
+                time: DateTime::from_utc(payment_row.issue_time, Utc),
+                expires: payment_row
+                    .expiry_time
+                    .map(|expiry_time| DateTime::from_utc(expiry_time, Utc)),
+                memo: payment_row.req_memo,
+                payment_url: format!("{}{}", SETTINGS.payment_url, payment_id),
+                payment_id,
+                required_fee_rate: payment_row.min_fee_rate.unwrap_or(SETTINGS.min_fee_rate),
+            };
+
+            Ok(HttpResponse::Ok()
+                .content_type(PAYMENT_REQUEST_CONTENT_TYPE)
+                .body(serde_json::to_string(&payment_request).unwrap()))
+        },
+    );
+
+    Box::new(response)
+}
+
+// Verify or accept a JSON payment
+pub fn payment_handler(
+    payment_id: web::Path<String>,
+    payload: web::Payload,
+    data: web::Data<(BitcoinClient, Store)>,
+    accepted_payments: AcceptedPayments,
+    verify_only: bool,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    let bitcoin_client = data.0.to_owned();
+    let store = data.1.to_owned();
+
+    // Read and parse payment
+    let body_raw =
+        payload
+            .map_err(|_| PaymentError::Payload)
+            .fold(BytesMut::new(), move |mut body, chunk| {
+                body.extend_from_slice(&chunk);
+                Ok::<_, PaymentError>(body)
+            });
+    let payment = body_raw.and_then(move |body| -> Result<_, PaymentError> {
+        let (currency, raw_txs, verification_size) = if verify_only {
+            let verification: JsonPaymentVerification =
+                serde_json::from_slice(&body[..]).map_err(|_| PaymentError::Decode)?;
+            (
+                verification.currency,
+                vec![verification.unsigned_transaction],
+                Some(verification.weighted_size as usize),
+            )
+        } else {
+            let payment: JsonPayment =
+                serde_json::from_slice(&body[..]).map_err(|_| PaymentError::Decode)?;
+            (payment.currency, payment.transactions, None)
+        };
+        if currency != CURRENCY {
+            return Err(PaymentError::UnsupportedCurrency);
+        }
+        let transactions = raw_txs
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<Vec<Vec<u8>>, _>>()
+            .map_err(|_| PaymentError::Decode)?;
+
+        let payment = Payment {
+            merchant_data: None,
+            transactions,
+            refund_to: vec![],
+            memo: None,
+        };
+        Ok((payment, verification_size))
+    });
+
+    // Verify and accept payment
+    let accept =
+        payment
+            .map_err(ServerError::Payment)
+            .and_then(move |(payment, verification_size)| {
+                process_payment(
+                    bitcoin_client,
+                    store,
+                    accepted_payments,
+                    payment_id.to_string(),
+                    payment,
+                    verification_size,
+                )
+            });
+
+    // Create response
+    let response = accept.and_then(|(ack, _)| {
+        let payment_ack = JsonPaymentAck {
+            payment: JsonPayment {
+                currency: CURRENCY.to_string(),
+                transactions: ack.payment.transactions.iter().map(hex::encode).collect(),
+            },
+            memo: ack.memo,
+        };
+
+        Ok(HttpResponse::Ok()
+            .content_type(PAYMENT_ACK_CONTENT_TYPE)
+            .body(serde_json::to_string(&payment_ack).unwrap()))
+    });
+
+    Box::new(response)
+}
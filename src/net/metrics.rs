@@ -0,0 +1,15 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::merchants::MerchantRegistry;
+
+use super::errors::ServerError;
+
+pub fn metrics_handler(
+    req: HttpRequest,
+    merchants: web::Data<MerchantRegistry>,
+) -> Result<HttpResponse, ServerError> {
+    let merchant = merchants.authenticate(&req)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(merchant.pool_metrics.render(&merchant.row.id)))
+}
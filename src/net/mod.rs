@@ -1,18 +1,23 @@
 pub mod errors;
+pub mod invoice_status;
+pub mod json_payment;
 pub mod jsonrpc_client;
+pub mod metrics;
+pub mod payment_uri;
 
-use std::str;
+use std::{collections::HashSet, str};
 
 use actix_web::{
-    http::header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, LOCATION, PRAGMA},
+    http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, LOCATION, PRAGMA},
     web, HttpRequest, HttpResponse,
 };
 use bitcoin::{util::psbt::serialize::Deserialize, Transaction};
 use bytes::BytesMut;
+use chrono::Utc;
 
 use futures::{
-    future::{err, ok, Either, Future},
-    stream::Stream,
+    future::{err, join_all, ok, Either, Future},
+    stream::{self, Stream},
 };
 use prost::Message;
 use url::Url;
@@ -20,38 +25,150 @@ use uuid::Uuid;
 
 use crate::{
     bitcoin::*,
-    crypto::{token::generate_token, Address, HashType},
+    crypto::{token::generate_token, x509::X509Signer, Address},
+    merchants::{Merchant, MerchantRegistry},
     models::*,
-    sql::postgresql::*,
-    ConnPool, SETTINGS,
+    rates::{self, RateProvider},
+    settings::Tolerance,
+    sql::{
+        models::{
+            PaymentOutputRow, PaymentRow, PaymentStateEnum, PaymentTally, ReceivedAmount,
+            RefundOutputRow,
+        },
+        Store,
+    },
+    tasks::double_spends::AcceptedPayments,
+    SETTINGS,
 };
 
 use errors::*;
+use jsonrpc_client::ClientError;
 
 pub const VALID_DURATION: u64 = 30;
 
+type AcceptFuture = Box<dyn Future<Item = (PaymentAck, PaymentRow), Error = ServerError>>;
+
+// Media type of a Content-Type or Accept entry, without its parameters
+fn media_type(value: &str) -> String {
+    value
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn content_type(req: &HttpRequest) -> String {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(media_type)
+        .unwrap_or_default()
+}
+
+// Media types listed by the Accept headers, in order
+pub(crate) fn accepted_types(req: &HttpRequest) -> Vec<String> {
+    req.headers()
+        .get_all(ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(media_type)
+        .collect()
+}
+
 // Payment handler
 pub fn payment_handler(
     req: HttpRequest,
     payment_id: web::Path<String>,
     payload: web::Payload,
-    data: web::Data<(BitcoinClient, ConnPool)>,
+    data: web::Data<(BitcoinClient, Store)>,
+    merchants: web::Data<MerchantRegistry>,
+    accepted_payments: web::Data<AcceptedPayments>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    // Dispatch on payment protocol
+    let accepted_payments = accepted_payments.get_ref().clone();
+    match content_type(&req).as_str() {
+        "application/bitcoincash-payment" => {
+            bip70_payment_handler(req, payment_id, payload, data, merchants, accepted_payments)
+        }
+        json_payment::PAYMENT_CONTENT_TYPE => {
+            json_payment::payment_handler(payment_id, payload, data, accepted_payments, false)
+        }
+        json_payment::VERIFICATION_CONTENT_TYPE => {
+            json_payment::payment_handler(payment_id, payload, data, accepted_payments, true)
+        }
+        _ => Box::new(err(PaymentError::Content.into())),
+    }
+}
+
+// Payment request handler
+pub fn payment_request_handler(
+    req: HttpRequest,
+    payment_id: web::Path<String>,
+    data: web::Data<(BitcoinClient, Store)>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    // Dispatch on the first accepted payment protocol
+    for media_type in accepted_types(&req) {
+        match media_type.as_str() {
+            "application/bitcoincash-paymentrequest" => {
+                return bip70_payment_request_handler(payment_id, data)
+            }
+            json_payment::PAYMENT_REQUEST_CONTENT_TYPE => {
+                return json_payment::payment_request_handler(payment_id, data)
+            }
+            _ => (),
+        }
+    }
+    Box::new(err(PaymentError::Accept.into()))
+}
+
+fn bip70_payment_request_handler(
+    payment_id: web::Path<String>,
+    data: web::Data<(BitcoinClient, Store)>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    let store = data.1.to_owned();
+
+    let response = get_payment_rows(store, payment_id.to_string()).and_then(|(payment_row, _)| {
+        match payment_row.payment_state {
+            PaymentStateEnum::Pending => (),
+            PaymentStateEnum::Expired => return Err(PaymentError::Expired.into()),
+            _ => return Err(PaymentError::NotPending.into()),
+        }
+        if let Some(expiry_time) = payment_row.expiry_time {
+            if expiry_time < Utc::now().naive_utc() {
+                return Err(PaymentError::Expired.into());
+            }
+        }
+
+        // Serve the request exactly as it was issued
+        let raw_payment_request = payment_row.payment_request.ok_or(ServerError::NotFound)?;
+        Ok(HttpResponse::Ok()
+            .content_type("application/bitcoincash-paymentrequest")
+            .header("Content-Transfer-Encoding", "binary")
+            .body(raw_payment_request))
+    });
+
+    Box::new(response)
+}
+
+fn bip70_payment_handler(
+    req: HttpRequest,
+    payment_id: web::Path<String>,
+    payload: web::Payload,
+    data: web::Data<(BitcoinClient, Store)>,
+    merchants: web::Data<MerchantRegistry>,
+    accepted_payments: AcceptedPayments,
 ) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
     let bitcoin_client = data.0.to_owned();
-    let pool = data.1.to_owned();
+    let store = data.1.to_owned();
 
     // Check headers
-    let headers = req.headers();
-    if headers.get(CONTENT_TYPE)
-        != Some(&HeaderValue::from_str("application/bitcoincash-payment").unwrap())
+    if !accepted_types(&req)
+        .iter()
+        .any(|media_type| media_type == "application/bitcoincash-paymentack")
     {
         return Box::new(err(PaymentError::Accept.into()));
     }
-    if headers.get(ACCEPT)
-        != Some(&HeaderValue::from_str("application/bitcoincash-paymentack").unwrap())
-    {
-        return Box::new(err(PaymentError::Content.into()));
-    }
 
     // Read and parse payment proto
     let body_raw =
@@ -62,144 +179,644 @@ pub fn payment_handler(
                 Ok::<_, PaymentError>(body)
             });
     let payment = body_raw
-        .and_then(|payment_raw| Payment::decode(payment_raw).map_err(|_| PaymentError::Decode));
-
-    // Check payment is valid
-    let pool_inner_a = pool.clone();
-    let pool_inner_b = pool.clone();
-    let check_payment = payment
-        .map_err(ServerError::Payment)
-        .and_then(move |payment| {
-            // Parse tx
-            let res_tx = payment.transactions.get(0);
-            let tx_raw = match res_tx {
-                Some(some) => some,
-                None => return Either::B(err(ServerError::Payment(PaymentError::NoTx))),
-            }; // Assume first tx
-            let tx = match Transaction::deserialize(tx_raw) {
-                Ok(ok) => ok,
-                Err(e) => return Either::B(err(ServerError::Payment(PaymentError::from(e)))),
-            };
+        .and_then(|payment_raw| Payment::decode(payment_raw).map_err(|_| PaymentError::Decode))
+        .map_err(ServerError::Payment);
+
+    // Tokens are generated with the secret of the merchant owning the invoice, resolve it
+    // before anything is broadcast so that an accepted payment always gets its PaymentACK
+    let payment_id = payment_id.to_string();
+    let owner_store = store.clone();
+    let owner_payment_id = payment_id.clone();
+    let secret = actix_web::web::block(move || owner_store.get_payment(&owner_payment_id))
+        .map_err(|err| match err {
+            actix_threadpool::BlockingError::Error(e) => ServerError::Store(e),
+            _ => unreachable!(),
+        })
+        .and_then(move |payment_row| {
+            merchants
+                .get(&payment_row.merchant_id)
+                .map(|merchant| merchant.row.secret.clone())
+                .ok_or(ServerError::NotFound)
+        });
+
+    // Verify and accept payment
+    let accept = secret.join(payment).and_then(move |(secret, payment)| {
+        process_payment(
+            bitcoin_client,
+            store,
+            accepted_payments,
+            payment_id,
+            payment,
+            None,
+        )
+        .map(|accepted| (accepted, secret))
+    });
+
+    // Create response
+    let response = accept.and_then(move |((ack, payment_row), secret)| {
+        generate_ack_response(ack, payment_row.tokenize, &secret)
+    });
+
+    Box::new(response)
+}
+
+// Fetch a payment row along with its additional outputs
+fn get_payment_rows(
+    store: Store,
+    payment_id: String,
+) -> impl Future<Item = (PaymentRow, Vec<PaymentOutputRow>), Error = ServerError> {
+    // Run on seperate thread
+    actix_web::web::block(move || {
+        let payment_row = store.get_payment(&payment_id)?;
+        let payment_outputs = store.get_payment_outputs(&payment_id)?;
+        Ok((payment_row, payment_outputs))
+    })
+    .map_err(|err| match err {
+        actix_threadpool::BlockingError::Error(e) => ServerError::Store(e),
+        _ => unreachable!(),
+    })
+}
+
+// Verify a payment against its invoice then broadcast and accept it. A verification carries the
+// size of the tx once signed, it is neither broadcast nor recorded
+pub(crate) fn process_payment(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    accepted_payments: AcceptedPayments,
+    payment_id: String,
+    payment: Payment,
+    verification_size: Option<usize>,
+) -> AcceptFuture {
+    let verify_only = verification_size.is_some();
+
+    // The invoice, its outputs and what each of them received so far
+    let rows_store = store.clone();
+    let rows = actix_web::web::block(move || {
+        let payment_row = rows_store.get_payment(&payment_id)?;
+        let payment_outputs = rows_store.get_payment_outputs(&payment_id)?;
+        let received_amounts = rows_store.get_received_amounts(&payment_id)?;
+        Ok((payment_row, payment_outputs, received_amounts))
+    })
+    .map_err(|err| match err {
+        actix_threadpool::BlockingError::Error(e) => ServerError::Store(e),
+        _ => unreachable!(),
+    });
+
+    let accept = rows.and_then(move |(payment_row, outputs, received)| -> AcceptFuture {
+        match payment_row.payment_state {
+            PaymentStateEnum::Pending => (),
+            PaymentStateEnum::Underpaid => {
+                // Replay the PaymentACK of the latest partial payment to an identical resubmission
+                if let Some(ack) = replay_ack(&payment, &payment_row).filter(|_| !verify_only) {
+                    return Box::new(ok((ack, payment_row)));
+                }
+            }
+            PaymentStateEnum::Received | PaymentStateEnum::Confirmed if !verify_only => {
+                // Replay the original PaymentACK to an identical resubmission
+                return Box::new(match replay_ack(&payment, &payment_row) {
+                    Some(ack) => ok((ack, payment_row)),
+                    None => err(PaymentError::NotPending.into()),
+                });
+            }
+            PaymentStateEnum::Expired => {
+                return Box::new(err(PaymentError::Expired.into()));
+            }
+            _ => return Box::new(err(PaymentError::NotPending.into())),
+        }
+
+        // Check expiry
+        if let Some(expiry_time) = payment_row.expiry_time {
+            if expiry_time < Utc::now().naive_utc() {
+                return Box::new(err(PaymentError::Expired.into()));
+            }
+        }
+
+        // Parse txs
+        if payment.transactions.is_empty() {
+            return Box::new(err(PaymentError::NoTx.into()));
+        }
+        let txs = match payment
+            .transactions
+            .iter()
+            .map(|tx_raw| Transaction::deserialize(tx_raw))
+            .collect::<Result<Vec<Transaction>, _>>()
+        {
+            Ok(ok) => ok,
+            Err(e) => return Box::new(err(PaymentError::from(e).into())),
+        };
+
+        // Verify payment
+        let expected_outputs = expected_outputs(&payment_row, outputs);
+        let script_tallies = tally_outputs(&txs, &expected_outputs);
+        let pays_invoice = script_tallies
+            .iter()
+            .all(|script_tally| script_tally.expected == 0)
+            || script_tallies
+                .iter()
+                .any(|script_tally| script_tally.paid > 0);
+        if !pays_invoice || !check_tx_data(&txs, payment_row.tx_data.as_ref()) {
+            // The invoice is left as is, anyone knowing its URL may submit a payment
+            return Box::new(err(PaymentError::InvalidOutputs.into()));
+        }
+        let previous = previously_received(
+            &script_tallies,
+            &received,
+            payment_row.amount_received as u64,
+        );
+        let tally = match assess_payment(&script_tallies, &previous, &SETTINGS.tolerance) {
+            Ok(ok) => ok,
+            Err(e) => return Box::new(err(e.into())),
+        };
+
+        // Check the node would accept the txs before acknowledging them
+        let payment_id = payment_row.id.to_string();
+        let rejection_store = store.clone();
+        let min_fee_rate = payment_row.min_fee_rate.unwrap_or(SETTINGS.min_fee_rate);
+        let tested = test_txs(
+            bitcoin_client.clone(),
+            &payment,
+            &txs,
+            min_fee_rate,
+            verification_size,
+        )
+        .or_else(
+            move |rejection| -> Box<dyn Future<Item = (), Error = ServerError>> {
+                if verify_only {
+                    Box::new(err(PaymentError::TxRejected(rejection).into()))
+                } else {
+                    Box::new(record_tx_rejection(
+                        rejection_store,
+                        payment_id,
+                        rejection,
+                        Vec::new(),
+                    ))
+                }
+            },
+        );
+
+        Box::new(tested.and_then(move |_| -> AcceptFuture {
+            if verify_only {
+                let memo = payment_row.ack_memo.clone();
+                return Box::new(ok((PaymentAck { payment, memo }, payment_row)));
+            }
+
+            Box::new(send_payment(
+                bitcoin_client,
+                store,
+                accepted_payments,
+                payment,
+                txs,
+                tally,
+                payment_row,
+            ))
+        }))
+    });
 
-            // Get payment row
-            Either::A(
-                // Run on seperate thread
-                actix_web::web::block(move || {
-                    let connection = pool_inner_a.get().unwrap();
-                    get_payment(&payment_id, &connection)
-                        .map(|payment_row| (payment_id.to_string(), payment_row))
+    Box::new(accept)
+}
+
+// Amounts received so far by each expected script. Invoices underpaid before amounts were
+// recorded per script have their total spread over the scripts in order
+fn previously_received(
+    script_tallies: &[ScriptTally],
+    received_amounts: &[ReceivedAmount],
+    amount_received: u64,
+) -> Vec<u64> {
+    if received_amounts.is_empty() {
+        let mut remaining = amount_received;
+        return script_tallies
+            .iter()
+            .map(|script_tally| {
+                let received = remaining.min(script_tally.expected);
+                remaining -= received;
+                received
+            })
+            .collect();
+    }
+    script_tallies
+        .iter()
+        .map(|script_tally| {
+            received_amounts
+                .iter()
+                .find(|received_amount| received_amount.script == script_tally.script)
+                .map(|received_amount| received_amount.amount as u64)
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+// Count a payment towards its invoice as per the tolerance settings. Every script must be paid
+// its own amount, overpaying one script does not make up for another
+fn assess_payment(
+    script_tallies: &[ScriptTally],
+    previously_received: &[u64],
+    tolerance: &Tolerance,
+) -> Result<PaymentTally, PaymentError> {
+    let mut received_amounts = Vec::with_capacity(script_tallies.len());
+    let (mut amount_received, mut short, mut excess) = (0, 0, 0);
+    for (script_tally, previous) in script_tallies.iter().zip(previously_received) {
+        let received = previous + script_tally.paid;
+        amount_received += received;
+        short += script_tally.expected.saturating_sub(received);
+        excess += received.saturating_sub(script_tally.expected);
+        received_amounts.push(ReceivedAmount {
+            script: script_tally.script.clone(),
+            amount: received as i64,
+        });
+    }
+
+    let amount_due = if short <= tolerance.underpayment {
+        // Paid in full, by a single payment or the last of several partial ones
+        0
+    } else if tolerance.partial {
+        // Accumulate partial payments towards the amount of each script
+        short
+    } else {
+        return Err(PaymentError::Underpaid);
+    };
+    if excess > 0 && !tolerance.overpayment {
+        return Err(PaymentError::Overpaid);
+    }
+    Ok(PaymentTally {
+        amount_received: amount_received as i64,
+        amount_due: amount_due as i64,
+        overpaid_amount: excess as i64,
+        received_amounts,
+    })
+}
+
+// Check every tx of a payment before any is broadcast. Txs the node already has in its mempool,
+// as when the wallet broadcast them itself, count as accepted. The order of the others, their
+// inputs and fee rates are checked for all of them, while only those whose parents are already
+// broadcast can be tested for mempool acceptance
+fn test_txs(
+    bitcoin_client: BitcoinClient,
+    payment: &Payment,
+    txs: &[Transaction],
+    min_fee_rate: f64,
+    verification_size: Option<usize>,
+) -> Box<dyn Future<Item = (), Error = TxRejection>> {
+    if let Err(rejection) = check_tx_order(txs) {
+        return Box::new(err(rejection));
+    }
+
+    // The node refuses unsigned txs, so a verification is not tested for mempool acceptance and
+    // its fee rate is taken at the size the wallet expects once signed
+    if let Some(tx_size) = verification_size {
+        let checks: Vec<_> = txs
+            .iter()
+            .map(|tx| check_tx_fee(&bitcoin_client, tx, tx_size, txs, min_fee_rate))
+            .collect();
+        return Box::new(join_all(checks).map(|_| ()));
+    }
+
+    let mempool_checks: Vec<_> = txs
+        .iter()
+        .map(|tx| bitcoin_client.in_mempool(&tx.txid().to_string()))
+        .collect();
+    let txs = txs.to_vec();
+    let raw_txs = payment.transactions.clone();
+    let tested = join_all(mempool_checks)
+        .map_err(|e| TxRejection::from_client_error(&e))
+        .and_then(move |in_mempool| {
+            let new_txs: Vec<(Transaction, Vec<u8>)> = txs
+                .iter()
+                .cloned()
+                .zip(raw_txs)
+                .zip(in_mempool)
+                .filter(|(_, in_mempool)| !*in_mempool)
+                .map(|(new_tx, _)| new_tx)
+                .collect();
+
+            // Inputs of children are resolved against their parents in the payment
+            let checks: Vec<_> = new_txs
+                .iter()
+                .map(|(tx, tx_raw)| {
+                    check_tx_fee(&bitcoin_client, tx, tx_raw.len(), &txs, min_fee_rate)
                 })
-                .map_err(|err| match err {
-                    actix_threadpool::BlockingError::Error(e) => e.into(),
-                    _ => unreachable!(),
+                .collect();
+
+            let new_tx_ids: HashSet<_> = new_txs.iter().map(|(tx, _)| tx.txid()).collect();
+            let independent_txs: Vec<Vec<u8>> = new_txs
+                .into_iter()
+                .filter(|(tx, _)| {
+                    tx.input
+                        .iter()
+                        .all(|input| !new_tx_ids.contains(&input.previous_output.txid))
                 })
-                .and_then(move |(payment_id, payment_row)| {
-                    // Verify payment
-                    let expected_pk_hash =
-                        Address::decode(&payment_row.address).unwrap().into_body();
-                    if !check_outputs(
-                        &tx,
-                        payment_row.amount as u64,
-                        &expected_pk_hash,
-                        payment_row.tx_data.as_ref(),
-                    ) {
-                        // Reject payment on seperate thread
-                        let rejection = actix_web::web::block(move || {
-                            let connection = pool_inner_b.get().unwrap();
-                            reject_payment(&payment_id, &connection)
-                        })
-                        .map_err(|err| match err {
-                            actix_threadpool::BlockingError::Error(e) => e.into(),
-                            _ => unreachable!(),
+                .map(|(_, tx_raw)| tx_raw)
+                .collect();
+
+            join_all(checks).and_then(move |_| {
+                stream::iter_ok(independent_txs).for_each(move |tx_raw| {
+                    bitcoin_client
+                        .test_mempool_accept(&tx_raw)
+                        .then(|result| match result {
+                            Ok(ref acceptance) if acceptance.allowed => Ok(()),
+                            Ok(acceptance) => match TxRejection::from_reason(
+                                &acceptance.reject_reason.unwrap_or_default(),
+                            ) {
+                                TxRejection::AlreadyInMempool => Ok(()),
+                                rejection => Err(rejection),
+                            },
+                            Err(e) => Err(TxRejection::from_client_error(&e)),
                         })
-                        .and_then(|_| Err(ServerError::Payment(PaymentError::InvalidTx)));
-                        return Either::A(rejection);
-                    }
-                    Either::B(ok((payment, payment_row)))
-                }),
-            )
+                })
+            })
         });
+    Box::new(tested)
+}
+
+// Check a tx pays the fee rate at the given size, resolving its inputs against the payment
+fn check_tx_fee(
+    bitcoin_client: &BitcoinClient,
+    tx: &Transaction,
+    tx_size: usize,
+    txs: &[Transaction],
+    min_fee_rate: f64,
+) -> impl Future<Item = (), Error = TxRejection> {
+    let tx = tx.clone();
+    fees::input_values(bitcoin_client, &tx, txs).and_then(move |input_values| {
+        fees::check_fee_rate(&tx, tx_size, &input_values, min_fee_rate)
+    })
+}
 
-    // Send payment to bitcoind
-    let send_payment = check_payment.and_then(move |(payment, payment_row)| {
-        // Parse tx
-        let tx_raw = payment.transactions.get(0).unwrap(); // This is safe from before
+// Record why the node refused a payment tx along with the txs broadcast before it, the payment
+// remains pending
+fn record_tx_rejection<T>(
+    store: Store,
+    payment_id: String,
+    rejection: TxRejection,
+    sent_tx_ids: Vec<String>,
+) -> impl Future<Item = T, Error = ServerError> {
+    let rejection_reason = rejection.to_string();
+    actix_web::web::block(move || {
+        store.record_rejection(&payment_id, &rejection_reason, &sent_tx_ids)
+    })
+    .map_err(|err| match err {
+        actix_threadpool::BlockingError::Error(e) => e.into(),
+        _ => unreachable!(),
+    })
+    .and_then(|_| Err(PaymentError::TxRejected(rejection).into()))
+}
 
-        // Send tx
-        bitcoin_client
-            .send_tx(tx_raw)
-            .and_then(|tx_id| Ok((tx_id, payment, payment_row)))
-            .map_err(|_| PaymentError::InvalidTx.into())
-    });
+// Decode the stored PaymentACK if it was issued for the given payment
+fn replay_ack(payment: &Payment, payment_row: &PaymentRow) -> Option<PaymentAck> {
+    let raw_ack = payment_row.payment_ack.as_ref()?;
+    let ack = PaymentAck::decode(&raw_ack[..]).ok()?;
+    if &ack.payment == payment {
+        Some(ack)
+    } else {
+        None
+    }
+}
+
+// Broadcast a verified payment and mark it as received
+fn send_payment(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    accepted_payments: AcceptedPayments,
+    payment: Payment,
+    txs: Vec<Transaction>,
+    tally: PaymentTally,
+    payment_row: PaymentRow,
+) -> impl Future<Item = (PaymentAck, PaymentRow), Error = ServerError> {
+    // Send txs in order so that parents are broadcast before their children, keeping track of
+    // those sent in case a later one is refused
+    let payment_id = payment_row.id.to_string();
+    let rejection_store = store.clone();
+    let to_send: Vec<_> = payment
+        .transactions
+        .iter()
+        .cloned()
+        .zip(txs.iter().map(|tx| tx.txid().to_string()))
+        .collect();
+    let send_txs = stream::iter_ok::<_, (TxRejection, Vec<String>)>(to_send)
+        .fold(Vec::new(), move |mut tx_ids, (tx_raw, tx_id)| {
+            bitcoin_client.send_tx(&tx_raw).then(move |result| {
+                match result.map_err(|e| TxRejection::from_client_error(&e)) {
+                    // Txs the node already has count as sent
+                    Ok(_) | Err(TxRejection::AlreadyInMempool) => {
+                        tx_ids.push(tx_id);
+                        Ok(tx_ids)
+                    }
+                    Err(rejection) => Err((rejection, tx_ids)),
+                }
+            })
+        })
+        .or_else(move |(rejection, sent_tx_ids)| {
+            record_tx_rejection(rejection_store, payment_id, rejection, sent_tx_ids)
+        });
 
     // Update row
-    let update_row = send_payment.and_then(move |(tx_id, payment, payment_row)| {
+    let replay_store = store.clone();
+    send_txs.and_then(move |tx_ids| {
+        // Create PaymentAck
+        let memo = payment_row.ack_memo.clone();
+        let ack = PaymentAck { payment, memo };
+        let mut raw_ack = Vec::with_capacity(ack.encoded_len());
+        ack.encode(&mut raw_ack).unwrap();
+
+        // Encode callback payload
+        let payment_id = payment_row.id.to_string();
+        let callback_payload = CallbackPayload {
+            payment_id: payment_id.clone(),
+            payment_ack: Some(ack.clone()),
+            state: if tally.amount_due == 0 {
+                PaymentState::Received as i32
+            } else {
+                PaymentState::Underpaid as i32
+            },
+        };
+        let mut raw_callback_payload = Vec::with_capacity(callback_payload.encoded_len());
+        callback_payload.encode(&mut raw_callback_payload).unwrap();
+
+        let inputs: Vec<SpentOutput> = txs.iter().flat_map(spent_outputs).collect();
+        let watched_inputs = inputs.clone();
         actix_web::web::block(move || {
-            let connection = pool.get().unwrap();
-            // TODO: Refund to
-            accept_payment(&payment_row.id.to_string(), &tx_id, None, &connection)
-                .map(|_| (tx_id, payment, payment_row))
+            let callback = payment_row
+                .callback_url
+                .as_ref()
+                .map(|url| (url.as_str(), &raw_callback_payload[..]));
+            store
+                .accept_payment(
+                    &payment_id,
+                    payment_row.amount_received,
+                    &tally,
+                    &tx_ids,
+                    &inputs,
+                    &ack.payment.refund_to,
+                    &raw_ack,
+                    callback,
+                )
+                .map(|accepted| (accepted, ack, payment_row))
         })
         .map_err(|err| match err {
             actix_threadpool::BlockingError::Error(e) => e.into(),
             _ => unreachable!(),
         })
-    });
+        .and_then(move |(accepted, ack, payment_row)| -> AcceptFuture {
+            if accepted {
+                // Watch the inputs for double spends right away
+                let _ = accepted_payments.unbounded_send((payment_row.id, watched_inputs));
+                return Box::new(ok((ack, payment_row)));
+            }
 
-    // Create response
-    let response = update_row.and_then(|(tx_id, payment, payment_row)| {
-        // Create PaymentAck
-        let memo = payment_row.ack_memo;
-        let ack = PaymentAck { payment, memo };
+            // Another submission was accepted concurrently, replay its PaymentACK if it was
+            // identical
+            let payment_id = payment_row.id.to_string();
+            let replay =
+                get_payment_rows(replay_store, payment_id).and_then(move |(payment_row, _)| {
+                    match replay_ack(&ack.payment, &payment_row) {
+                        Some(ack) => Ok((ack, payment_row)),
+                        None => Err(PaymentError::NotPending.into()),
+                    }
+                });
+            Box::new(replay)
+        })
+    })
+}
 
-        // Encode payment ack
-        let mut raw_ack = Vec::with_capacity(ack.encoded_len());
-        ack.encode(&mut raw_ack).unwrap();
+fn generate_ack_response(
+    ack: PaymentAck,
+    tokenize: bool,
+    secret: &str,
+) -> Result<HttpResponse, ServerError> {
+    // Encode payment ack
+    let mut raw_ack = Vec::with_capacity(ack.encoded_len());
+    ack.encode(&mut raw_ack).unwrap();
 
-        // Generate response
-        let http_response = if payment_row.tokenize {
-            // Get merchant data
-            let merchant_data = ack
-                .payment
-                .merchant_data
-                .ok_or(PaymentError::NoMerchantDat)?;
-            let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-            let token = base64::encode_config(
-                &generate_token(&merchant_data, SETTINGS.secret.as_bytes()),
-                url_safe_config,
-            );
+    // Generate response
+    let http_response = if tokenize {
+        // Get merchant data
+        let merchant_data = ack
+            .payment
+            .merchant_data
+            .ok_or(PaymentError::NoMerchantDat)?;
+        let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+        let token = base64::encode_config(
+            &generate_token(&merchant_data, secret.as_bytes()),
+            url_safe_config,
+        );
 
-            // Generate payment redirect
-            let mut redirect_url = Url::parse(
-                str::from_utf8(&merchant_data).map_err(|_| PaymentError::InvalidMerchantDat)?,
-            )
-            .map_err(|_| PaymentError::InvalidMerchantDat)?;
-            redirect_url.set_query(Some(&format!("code={}", token)));
-
-            HttpResponse::Found()
-                .header(PRAGMA, "no-cache")
-                .header(LOCATION, redirect_url.into_string())
-                .header(AUTHORIZATION, format!("POP {}", token))
-                .body(raw_ack)
-        } else {
-            HttpResponse::Found()
-                .header(PRAGMA, "no-cache")
-                .body(raw_ack)
-        };
+        // Generate payment redirect
+        let mut redirect_url = Url::parse(
+            str::from_utf8(&merchant_data).map_err(|_| PaymentError::InvalidMerchantDat)?,
+        )
+        .map_err(|_| PaymentError::InvalidMerchantDat)?;
+        redirect_url.set_query(Some(&format!("code={}", token)));
 
-        // Generate response
-        Ok(http_response)
-    });
+        HttpResponse::Found()
+            .header(PRAGMA, "no-cache")
+            .header(LOCATION, redirect_url.into_string())
+            .header(AUTHORIZATION, format!("POP {}", token))
+            .body(raw_ack)
+    } else {
+        HttpResponse::Found()
+            .header(PRAGMA, "no-cache")
+            .body(raw_ack)
+    };
 
-    Box::new(response)
+    // Generate response
+    Ok(http_response)
+}
+
+// Validate the additional outputs of an invoice request
+fn parse_invoice_outputs(invoice_outputs: &[InvoiceOutput]) -> Result<Vec<Output>, ServerError> {
+    invoice_outputs
+        .iter()
+        .map(|invoice_output| {
+            if invoice_output.amount == 0 {
+                return Err(PaymentError::InvalidOutputs.into());
+            }
+            let script = match &invoice_output.destination {
+                Some(invoice_output::Destination::Address(str_addr)) => {
+                    let addr = Address::decode(str_addr).map_err(ServerError::Address)?;
+                    let network: Network = addr.network.clone().into();
+                    if network != SETTINGS.network {
+                        return Err(PaymentError::MismatchedNetwork.into());
+                    }
+                    address_to_script(&addr).ok_or(PaymentError::InvalidOutputs)?
+                }
+                Some(invoice_output::Destination::Script(script)) => {
+                    // Wallets can only be expected to pay standard P2PKH, P2SH and P2SH32 scripts
+                    if extract_pubkey_hash(script).is_none()
+                        && extract_script_hash(script).is_none()
+                    {
+                        return Err(PaymentError::InvalidOutputs.into());
+                    }
+                    script.clone()
+                }
+                None => return Err(PaymentError::InvalidOutputs.into()),
+            };
+            Ok(Output {
+                amount: Some(invoice_output.amount),
+                script,
+            })
+        })
+        .collect()
+}
+
+// Encode the OP_RETURN pushes requested by an invoice
+fn parse_tx_data(invoice_request: &InvoiceRequest) -> Result<Option<Vec<u8>>, ServerError> {
+    let pushes = match (
+        invoice_request.tx_data.is_empty(),
+        invoice_request.tx_data_pushes.is_empty(),
+    ) {
+        (true, true) => return Ok(None),
+        (false, true) => vec![invoice_request.tx_data.clone()],
+        (true, false) => invoice_request.tx_data_pushes.clone(),
+        (false, false) => return Err(PaymentError::InvalidTxData.into()),
+    };
+    let tx_data = encode_pushes(&pushes);
+    if tx_data.len() + 1 > MAX_OP_RETURN_SIZE {
+        return Err(PaymentError::InvalidTxData.into());
+    }
+    Ok(Some(tx_data))
+}
+
+// Invoices of other merchants are reported as missing
+pub(crate) fn check_owner(
+    merchant: &Merchant,
+    payment_row: &PaymentRow,
+) -> Result<(), ServerError> {
+    if payment_row.merchant_id == merchant.row.id {
+        Ok(())
+    } else {
+        Err(ServerError::NotFound)
+    }
+}
+
+// Reconstruct the outputs an invoice must be paid to
+pub(crate) fn expected_outputs(
+    payment_row: &PaymentRow,
+    payment_outputs: Vec<PaymentOutputRow>,
+) -> Vec<Output> {
+    let addr = Address::decode(&payment_row.address).unwrap();
+    let script = address_to_script(&addr).unwrap();
+    let extra_outputs: Vec<Output> = payment_outputs
+        .into_iter()
+        .map(|payment_output| Output {
+            amount: Some(payment_output.amount as u64),
+            script: payment_output.script,
+        })
+        .collect();
+    invoice_outputs(&script, payment_row.amount as u64, &extra_outputs)
 }
 
 pub fn generate_invoice(
+    req: HttpRequest,
     payload: web::Payload,
-    data: web::Data<(BitcoinClient, ConnPool)>,
+    data: web::Data<(BitcoinClient, Store)>,
+    merchants: web::Data<MerchantRegistry>,
+    signer: web::Data<Option<X509Signer>>,
+    rate_provider: web::Data<Option<RateProvider>>,
 ) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
-    let mut bitcoin_client = data.0.to_owned();
-    let pool = data.1.to_owned();
+    let store = data.1.to_owned();
+    let merchant = match merchants.authenticate(&req) {
+        Ok(ok) => ok,
+        Err(e) => return Box::new(err(e.into())),
+    };
 
     // Decode metadata
     let body_raw = payload.map_err(|_| ServerError::InvoiceRequestDecode).fold(
@@ -209,39 +826,69 @@ pub fn generate_invoice(
             Ok::<_, ServerError>(body)
         },
     );
-    let fut_invoice_request = body_raw.and_then(|metadata_raw| {
-        InvoiceRequest::decode(metadata_raw).map_err(|_| ServerError::InvoiceRequestDecode)
-    });
+    let fut_invoice_request = body_raw
+        .and_then(|metadata_raw| {
+            InvoiceRequest::decode(metadata_raw).map_err(|_| ServerError::InvoiceRequestDecode)
+        })
+        .and_then(|invoice_request| {
+            let extra_outputs = parse_invoice_outputs(&invoice_request.outputs)?;
+            let tx_data = parse_tx_data(&invoice_request)?;
+            Ok::<_, ServerError>((invoice_request, extra_outputs, tx_data))
+        })
+        .and_then(move |(mut invoice_request, extra_outputs, tx_data)| {
+            // Derive the amount from the fiat amount at the current rate
+            if invoice_request.fiat_currency.is_empty() {
+                return Either::A(ok((invoice_request, extra_outputs, tx_data, None)));
+            }
+            let fiat_amount = rates::convert(
+                rate_provider.get_ref().as_ref(),
+                &invoice_request.fiat_currency,
+                invoice_request.fiat_amount,
+            )
+            .map_err(ServerError::ExchangeRate)
+            .map(move |fiat_amount| {
+                invoice_request.amount = fiat_amount.to_sats();
+                (invoice_request, extra_outputs, tx_data, Some(fiat_amount))
+            });
+            Either::B(fiat_amount)
+        });
 
-    // Get new addr and add to wallet
-    let new_addr = bitcoin_client
-        .get_new_addr()
-        .then(move |addr_opt| match addr_opt {
-            Ok(str_addr) => {
-                let addr = Address::decode(&str_addr).map_err(ServerError::Address)?;
-                let network: Network = addr.network.clone().into();
-                if network != SETTINGS.network || addr.hash_type != HashType::Key {
-                    return Err(ServerError::Payment(PaymentError::MismatchedNetwork))?; // TODO: Finer grained error here
-                }
-                Ok((addr.into_body(), str_addr))
+    // Get a fresh address from the merchant's source
+    let new_addr = merchant
+        .address_provider
+        .next_address()
+        .map_err(ServerError::AddressSource)
+        .and_then(move |str_addr| {
+            let addr = Address::decode(&str_addr).map_err(ServerError::Address)?;
+            let network: Network = addr.network.clone().into();
+            if network != SETTINGS.network {
+                return Err(ServerError::Payment(PaymentError::MismatchedNetwork))?;
             }
-            Err(_e) => Err(ServerError::Payment(PaymentError::AddrFetchFailed)),
+            let script = address_to_script(&addr).ok_or(PaymentError::UnsupportedAddress)?;
+            Ok((script, str_addr))
         });
 
     let generate = fut_invoice_request.join(new_addr).and_then(
-        move |(invoice_request, (raw_addr, str_addr))| {
+        move |((invoice_request, extra_outputs, tx_data, fiat_amount), (script, str_addr))| {
             // Generate outputs
-            let outputs =
-                generate_outputs(&raw_addr, invoice_request.amount, &invoice_request.tx_data);
+            let outputs = generate_outputs(
+                &script,
+                invoice_request.amount,
+                &extra_outputs,
+                tx_data.as_ref().map(|tx_data| &tx_data[..]),
+            );
 
-            // Generate payment details
+            // Generate payment details, falling back to the merchant's defaults
             let id = Uuid::new_v4();
             let expires = match invoice_request.expires {
-                0 => None,
+                0 => merchant
+                    .row
+                    .expiry
+                    .map(|expiry| invoice_request.time + expiry as u64),
                 some => Some(some),
             };
             let callback_url = match invoice_request.callback_url.as_str() {
-                "" => None,
+                "" => merchant.row.callback_url.as_ref().map(String::as_str),
                 value => Some(value),
             };
             let merchant_data = if invoice_request.merchant_data.is_empty() {
@@ -250,9 +897,14 @@ pub fn generate_invoice(
                 Some(invoice_request.merchant_data)
             };
             let req_memo = match invoice_request.req_memo.as_str() {
-                "" => None,
+                "" => merchant.row.req_memo.as_ref().map(String::as_str),
                 value => Some(value),
             };
+            let min_fee_rate = if invoice_request.min_fee_rate > 0. {
+                Some(invoice_request.min_fee_rate)
+            } else {
+                None
+            };
             let payment_details = PaymentDetails {
                 network: Some(SETTINGS.network.to_string()),
                 payment_url: Some(format!("{}{}", SETTINGS.payment_url, &id.to_string())),
@@ -263,66 +915,522 @@ pub fn generate_invoice(
                 outputs,
             };
 
+            // Generate payment invoice
+            let mut serialized_payment_details = Vec::with_capacity(payment_details.encoded_len());
+            payment_details
+                .encode(&mut serialized_payment_details)
+                .unwrap();
+            let pki_type = Some("none".to_string());
+            let mut payment_request = PaymentRequest {
+                pki_type,
+                pki_data: None,
+                payment_details_version: Some(1),
+                serialized_payment_details,
+                signature: None,
+            };
+
+            // Sign payment invoice
+            if let Some(signer) = signer.get_ref() {
+                signer.sign(&mut payment_request)?;
+            }
+
+            // Generate payment URI and QR codes
+            let payment_uri = payment_uri::generate_payment_uri(
+                &str_addr,
+                invoice_request.amount,
+                !extra_outputs.is_empty(),
+                &id.to_string(),
+            )?;
+
+            // Persist the exact bytes served to wallets
+            let mut raw_payment_request = Vec::with_capacity(payment_request.encoded_len());
+            payment_request.encode(&mut raw_payment_request).unwrap();
+
             // Add row to SQL table
             let ack_memo = match invoice_request.ack_memo.as_str() {
-                "" => None,
+                "" => merchant.row.ack_memo.as_ref().map(String::as_str),
                 value => Some(value),
             };
-            let tx_data = if invoice_request.tx_data.is_empty() {
-                None
-            } else {
-                Some(&invoice_request.tx_data[..])
-            };
-            let connection = pool.get().unwrap();
-            let fut_add_payment = add_payment(
+            let fut_add_payment = store.add_payment(
                 &payment_details,
                 &id,
+                &merchant.row.id,
                 &str_addr,
                 invoice_request.amount as i64,
                 req_memo,
                 ack_memo,
                 invoice_request.tokenize,
-                tx_data,
+                tx_data.as_ref().map(|tx_data| &tx_data[..]),
                 callback_url,
-                &connection,
+                &extra_outputs,
+                &raw_payment_request,
+                fiat_amount.as_ref(),
+                min_fee_rate,
             );
-            let mut serialized_payment_details = Vec::with_capacity(payment_details.encoded_len());
-            payment_details
-                .encode(&mut serialized_payment_details)
-                .unwrap();
-            actix_web::web::block(|| fut_add_payment)
+            let fut_add_payment = actix_web::web::block(|| fut_add_payment)
                 .map_err(|err| match err {
                     actix_threadpool::BlockingError::Error(e) => e.into(),
                     _ => unreachable!(),
                 })
-                .map(move |_| (id.to_string(), serialized_payment_details))
+                .map(move |_| (id.to_string(), payment_request, payment_uri));
+            Ok::<_, ServerError>(fut_add_payment)
         },
     );
 
-    let response = generate.and_then(|(payment_id, serialized_payment_details)| {
-        // Generate payment invoice
-        // TODO: Sign here
-        let pki_type = Some("none".to_string());
-        let payment_request = PaymentRequest {
-            pki_type,
-            pki_data: None,
-            payment_details_version: Some(1),
-            serialized_payment_details,
-            signature: None,
+    let response = generate.and_then(|fut_add_payment| fut_add_payment).map(
+        |(payment_id, payment_request, payment_uri)| {
+            let invoice_response = InvoiceResponse {
+                payment_id,
+                payment_request: Some(payment_request),
+                payment_uri: Some(payment_uri),
+            };
+            let mut raw_invoice_response = Vec::with_capacity(invoice_response.encoded_len());
+            invoice_response.encode(&mut raw_invoice_response).unwrap();
+
+            HttpResponse::PaymentRequired()
+                .content_type("application/bitcoincash-paymentrequest")
+                .header("Content-Transfer-Encoding", "binary")
+                .body(raw_invoice_response)
+        },
+    );
+
+    // Respond
+    Box::new(response)
+}
+
+fn refund_outputs_to_proto(refund_outputs: Vec<RefundOutputRow>) -> Vec<Output> {
+    refund_outputs
+        .into_iter()
+        .map(|refund_output| Output {
+            amount: refund_output.amount.map(|amount| amount as u64),
+            script: refund_output.script,
+        })
+        .collect()
+}
+
+pub fn get_refund(
+    req: HttpRequest,
+    payment_id: web::Path<String>,
+    data: web::Data<(BitcoinClient, Store)>,
+    merchants: web::Data<MerchantRegistry>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    let store = data.1.to_owned();
+    let merchant = match merchants.authenticate(&req) {
+        Ok(ok) => ok,
+        Err(e) => return Box::new(err(e.into())),
+    };
+
+    // Get payment row and refund outputs
+    let rows = actix_web::web::block(move || {
+        let payment_row = store.get_payment(&payment_id)?;
+        let refund_outputs = store.get_refund_outputs(&payment_id)?;
+        Ok((payment_row, refund_outputs))
+    })
+    .map_err(|err| match err {
+        actix_threadpool::BlockingError::Error(e) => ServerError::Store(e),
+        _ => unreachable!(),
+    });
+
+    let response = rows.and_then(move |(payment_row, refund_outputs)| {
+        check_owner(&merchant, &payment_row)?;
+        let refund = Refund {
+            refund_to: refund_outputs_to_proto(refund_outputs),
+            tx_id: payment_row.refund_tx_id.unwrap_or_default(),
         };
-        let invoice_response = InvoiceResponse {
-            payment_id,
-            payment_request: Some(payment_request),
+        let mut raw_refund = Vec::with_capacity(refund.encoded_len());
+        refund.encode(&mut raw_refund).unwrap();
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/x-protobuf")
+            .body(raw_refund))
+    });
+
+    Box::new(response)
+}
+
+pub fn refund_payment(
+    req: HttpRequest,
+    payment_id: web::Path<String>,
+    payload: web::Payload,
+    data: web::Data<(BitcoinClient, Store)>,
+    merchants: web::Data<MerchantRegistry>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    let store = data.1.to_owned();
+    let merchant = match merchants.authenticate(&req) {
+        Ok(ok) => ok,
+        Err(e) => return Box::new(err(e.into())),
+    };
+
+    // Decode refund request
+    let body_raw = payload.map_err(|_| ServerError::RefundRequestDecode).fold(
+        BytesMut::new(),
+        move |mut body, chunk| {
+            body.extend_from_slice(&chunk);
+            Ok::<_, ServerError>(body)
+        },
+    );
+    let fut_refund_request = body_raw.and_then(|refund_request_raw| {
+        RefundRequest::decode(refund_request_raw).map_err(|_| ServerError::RefundRequestDecode)
+    });
+
+    // Get payment row and refund outputs
+    let store_inner = store.clone();
+    let payment_id_inner = payment_id.to_string();
+    let rows = fut_refund_request.and_then(move |refund_request| {
+        actix_web::web::block(move || {
+            let payment_row = store_inner.get_payment(&payment_id_inner)?;
+            let refund_outputs = store_inner.get_refund_outputs(&payment_id_inner)?;
+            Ok((refund_request, payment_row, refund_outputs))
+        })
+        .map_err(|err| match err {
+            actix_threadpool::BlockingError::Error(e) => ServerError::Store(e),
+            _ => unreachable!(),
+        })
+    });
+
+    // Check the refund can be sent
+    let checked = rows.and_then(move |(refund_request, payment_row, refund_outputs)| {
+        check_owner(&merchant, &payment_row)?;
+        match payment_row.payment_state {
+            PaymentStateEnum::Received
+            | PaymentStateEnum::Confirmed
+            | PaymentStateEnum::Underpaid => (),
+            // Underpaid invoices expire along with what they received
+            PaymentStateEnum::Expired if payment_row.amount_received > 0 => (),
+            _ => return Err(PaymentError::NotPaid.into()),
+        }
+        if payment_row.refund_tx_id.is_some() {
+            return Err(PaymentError::AlreadyRefunded.into());
+        }
+
+        // The refund is sent to a single address, so payments asking for it to be split across
+        // several outputs are refused rather than refunded to only one of them
+        let refund_script = match refund_outputs.as_slice() {
+            [] => return Err(PaymentError::NoRefundTo.into()),
+            [refund_output] => &refund_output.script,
+            _ => return Err(PaymentError::MultipleRefundTo.into()),
         };
-        let mut raw_invoice_response = Vec::with_capacity(invoice_response.encoded_len());
-        invoice_response.encode(&mut raw_invoice_response).unwrap();
+        let refund_addr = script_to_address(refund_script, SETTINGS.network.clone())
+            .ok_or(PaymentError::UnsupportedRefundScript)?;
+        let amount = match refund_request.amount {
+            0 => payment_row.amount_received as u64,
+            some if some > payment_row.amount_received as u64 => {
+                return Err(PaymentError::RefundExceedsReceived.into())
+            }
+            some => some,
+        };
+        Ok((merchant, refund_addr, amount, refund_outputs))
+    });
 
-        Ok(HttpResponse::PaymentRequired()
-            .content_type("application/bitcoincash-paymentrequest")
-            .header("Content-Transfer-Encoding", "binary")
-            .body(raw_invoice_response))
+    // Claim the refund so that concurrent requests cannot send it twice
+    let claim_store = store.clone();
+    let claim_payment_id = payment_id.to_string();
+    let claimed = checked.and_then(move |refund| {
+        actix_web::web::block(move || claim_store.claim_refund(&claim_payment_id))
+            .map_err(|err| match err {
+                actix_threadpool::BlockingError::Error(e) => e.into(),
+                _ => unreachable!(),
+            })
+            .and_then(move |claimed| {
+                if claimed {
+                    Ok(refund)
+                } else {
+                    Err(PaymentError::AlreadyRefunded.into())
+                }
+            })
+    });
+
+    // Send refund from the merchant's node wallet
+    let release_store = store.clone();
+    let release_payment_id = payment_id.to_string();
+    let send_refund = claimed.and_then(move |(merchant, refund_addr, amount, refund_outputs)| {
+        merchant
+            .bitcoin_client
+            .send_to_address(&refund_addr, amount)
+            .or_else(
+                move |e| -> Box<dyn Future<Item = String, Error = ServerError>> {
+                    match e {
+                        // The node refused to send the refund, so it may be requested again
+                        ClientError::Rpc(_) => Box::new(
+                            actix_web::web::block(move || {
+                                release_store.release_refund(&release_payment_id)
+                            })
+                            .map_err(|err| match err {
+                                actix_threadpool::BlockingError::Error(e) => e.into(),
+                                _ => unreachable!(),
+                            })
+                            .and_then(|_| Err(PaymentError::RefundFailed.into())),
+                        ),
+                        // Otherwise the refund may have been sent, the claim is kept until resolved
+                        _ => Box::new(err(PaymentError::RefundFailed.into())),
+                    }
+                },
+            )
+            .map(|tx_id| (tx_id, refund_outputs))
+    });
+
+    // Update row
+    let update_row = send_refund.and_then(move |(tx_id, refund_outputs)| {
+        actix_web::web::block(move || {
+            store
+                .set_refund_tx_id(&payment_id, &tx_id)
+                .map(|_| (tx_id, refund_outputs))
+        })
+        .map_err(|err| match err {
+            actix_threadpool::BlockingError::Error(e) => e.into(),
+            _ => unreachable!(),
+        })
+    });
+
+    let response = update_row.and_then(|(tx_id, refund_outputs)| {
+        let refund = Refund {
+            refund_to: refund_outputs_to_proto(refund_outputs),
+            tx_id,
+        };
+        let mut raw_refund = Vec::with_capacity(refund.encoded_len());
+        refund.encode(&mut raw_refund).unwrap();
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/x-protobuf")
+            .body(raw_refund))
     });
 
-    // Respond
     Box::new(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use bitcoin::{util::psbt::serialize::Serialize, OutPoint, Script, TxIn, TxOut};
+    use serde_json::{json, Value};
+
+    use jsonrpc_client::Request;
+
+    fn tolerance(overpayment: bool, partial: bool, underpayment: u64) -> Tolerance {
+        Tolerance {
+            overpayment,
+            partial,
+            underpayment,
+        }
+    }
+
+    fn script_tally(script: u8, expected: u64, paid: u64) -> ScriptTally {
+        ScriptTally {
+            script: vec![script; 25],
+            expected,
+            paid,
+        }
+    }
+
+    // Amounts received, due and overpaid
+    fn amounts(tally: &PaymentTally) -> (i64, i64, i64) {
+        (
+            tally.amount_received,
+            tally.amount_due,
+            tally.overpaid_amount,
+        )
+    }
+
+    #[test]
+    fn test_assess_overpayment() {
+        let script_tallies = [script_tally(0xaa, 1000, 1200)];
+        let tally = assess_payment(&script_tallies, &[0], &tolerance(true, false, 0)).unwrap();
+        assert_eq!(amounts(&tally), (1200, 0, 200));
+
+        match assess_payment(&script_tallies, &[0], &tolerance(false, false, 0)) {
+            Err(PaymentError::Overpaid) => (),
+            other => panic!("expected overpaid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_assess_underpayment_tolerance() {
+        let tolerance = tolerance(true, false, 5);
+        let tally = assess_payment(&[script_tally(0xaa, 1000, 995)], &[0], &tolerance).unwrap();
+        assert_eq!(amounts(&tally), (995, 0, 0));
+
+        match assess_payment(&[script_tally(0xaa, 1000, 994)], &[0], &tolerance) {
+            Err(PaymentError::Underpaid) => (),
+            other => panic!("expected underpaid, got {:?}", other),
+        }
+
+        // The shortfalls of all scripts count towards the tolerance
+        let script_tallies = [script_tally(0xaa, 900, 897), script_tally(0xbb, 100, 97)];
+        match assess_payment(&script_tallies, &[0, 0], &tolerance) {
+            Err(PaymentError::Underpaid) => (),
+            other => panic!("expected underpaid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_assess_partial_payments() {
+        let tolerance = tolerance(true, true, 0);
+
+        // Overpaying the platform does not pay the seller
+        let script_tallies = [script_tally(0xaa, 900, 0), script_tally(0xbb, 100, 1000)];
+        let tally = assess_payment(&script_tallies, &[0, 0], &tolerance).unwrap();
+        assert_eq!(amounts(&tally), (1000, 900, 900));
+        assert_eq!(
+            tally.received_amounts,
+            vec![
+                ReceivedAmount {
+                    script: vec![0xaa; 25],
+                    amount: 0,
+                },
+                ReceivedAmount {
+                    script: vec![0xbb; 25],
+                    amount: 1000,
+                },
+            ]
+        );
+
+        // A later payment to the seller completes the invoice
+        let script_tallies = [script_tally(0xaa, 900, 900), script_tally(0xbb, 100, 0)];
+        let tally = assess_payment(&script_tallies, &[0, 1000], &tolerance).unwrap();
+        assert_eq!(amounts(&tally), (1900, 0, 900));
+
+        // Payments to each script accumulate
+        let script_tallies = [script_tally(0xaa, 900, 500), script_tally(0xbb, 100, 100)];
+        let tally = assess_payment(&script_tallies, &[0, 0], &tolerance).unwrap();
+        assert_eq!(amounts(&tally), (600, 400, 0));
+        let script_tallies = [script_tally(0xaa, 900, 400), script_tally(0xbb, 100, 0)];
+        let tally = assess_payment(&script_tallies, &[500, 100], &tolerance).unwrap();
+        assert_eq!(amounts(&tally), (1000, 0, 0));
+    }
+
+    #[test]
+    fn test_previously_received() {
+        let script_tallies = [script_tally(0xaa, 900, 0), script_tally(0xbb, 100, 0)];
+        let received_amounts = vec![ReceivedAmount {
+            script: vec![0xbb; 25],
+            amount: 1000,
+        }];
+        assert_eq!(
+            previously_received(&script_tallies, &received_amounts, 1000),
+            vec![0, 1000]
+        );
+
+        // Totals recorded before amounts were kept per script are spread over the scripts
+        assert_eq!(
+            previously_received(&script_tallies, &[], 950),
+            vec![900, 50]
+        );
+        assert_eq!(previously_received(&script_tallies, &[], 0), vec![0, 0]);
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(media_type("application/payment"), "application/payment");
+        assert_eq!(
+            media_type("Application/Payment; charset=utf-8"),
+            "application/payment"
+        );
+        assert_eq!(media_type(" image/png;q=0.9 "), "image/png");
+        assert_eq!(media_type(""), "");
+    }
+
+    // A node answering gettxout with the given value in coins and refusing everything else,
+    // along with the methods called on it
+    fn fake_node(tx_out_value: f64) -> (BitcoinClient, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let methods = Arc::new(Mutex::new(Vec::new()));
+        let called = methods.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = BufReader::new(stream.unwrap());
+                let called = called.clone();
+                thread::spawn(move || loop {
+                    // Read the headers then the JSON body of a request
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        if stream.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let line = line.trim().to_ascii_lowercase();
+                        if line.is_empty() {
+                            break;
+                        }
+                        let mut header = line.splitn(2, ':');
+                        if header.next() == Some("content-length") {
+                            content_length = header.next().unwrap().trim().parse().unwrap();
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    stream.read_exact(&mut body).unwrap();
+                    let request: Request = serde_json::from_slice(&body).unwrap();
+                    called.lock().unwrap().push(request.method.clone());
+
+                    let (result, error) = match request.method.as_str() {
+                        "gettxout" => (json!({ "value": tx_out_value }), Value::Null),
+                        _ => (Value::Null, json!({ "code": -1, "message": "unexpected" })),
+                    };
+                    let response =
+                        json!({ "result": result, "error": error, "id": request.id }).to_string();
+                    write!(
+                        stream.get_mut(),
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        response.len(),
+                        response
+                    )
+                    .unwrap();
+                });
+            }
+        });
+        let bitcoin_client = BitcoinClient::new(endpoint, String::new(), String::new());
+        (bitcoin_client, methods)
+    }
+
+    #[test]
+    fn test_verification_fee_rate() {
+        // An unsigned tx spending 10000 satoshis with a fee of 1000
+        let tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xffff_ffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 9000,
+                script_pubkey: Script::from(vec![0xaa; 25]),
+            }],
+        };
+        let payment = Payment {
+            merchant_data: None,
+            transactions: vec![tx.serialize()],
+            refund_to: vec![],
+            memo: None,
+        };
+        let (bitcoin_client, methods) = fake_node(0.0001);
+        let mut sys = actix_rt::System::new("test");
+
+        // The fee rate is taken at the signed size, the node is only asked for the input value
+        let tested = test_txs(
+            bitcoin_client.clone(),
+            &payment,
+            &[tx.clone()],
+            1.,
+            Some(500),
+        );
+        assert_eq!(sys.block_on(tested), Ok(()));
+        assert_eq!(*methods.lock().unwrap(), vec!["gettxout"]);
+
+        // The unsigned tx would pay enough, but not once signed
+        assert!(payment.transactions[0].len() < 100);
+        let tested = test_txs(bitcoin_client, &payment, &[tx], 1., Some(2000));
+        assert_eq!(sys.block_on(tested), Err(TxRejection::InsufficientFee));
+        assert_eq!(*methods.lock().unwrap(), vec!["gettxout", "gettxout"]);
+    }
+}
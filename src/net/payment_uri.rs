@@ -0,0 +1,172 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures::future::{err, Future};
+use png::{BitDepth, ColorType, Encoder};
+use prost::Message;
+use qrcode::{render::svg, Color, QrCode};
+use url::form_urlencoded;
+
+use crate::{
+    bitcoin::{BitcoinClient, Network},
+    merchants::MerchantRegistry,
+    models::PaymentUri,
+    sql::Store,
+    SETTINGS,
+};
+
+use super::{accepted_types, check_owner, errors::*, get_payment_rows};
+
+const SATS_PER_COIN: u64 = 100_000_000;
+
+// Pixels per QR module
+const MODULE_SIZE: usize = 8;
+// Width of the quiet zone around the QR code, in modules
+const QUIET_ZONE: usize = 4;
+
+fn uri_scheme(network: &Network) -> &'static str {
+    match network {
+        Network::Mainnet => "bitcoincash",
+        Network::Testnet => "bchtest",
+        Network::Regnet => "bchreg",
+    }
+}
+
+// Format satoshis as a decimal amount of coins, as required by BIP21
+fn format_amount(amount: u64) -> String {
+    let coins = format!("{}.{:08}", amount / SATS_PER_COIN, amount % SATS_PER_COIN);
+    coins
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+// Build a BIP21 URI with a BIP72 payment request URL
+pub fn payment_uri(address: Option<&str>, amount: u64, payment_url: &str) -> String {
+    let mut params = Vec::with_capacity(2);
+    if amount != 0 {
+        params.push(format!("amount={}", format_amount(amount)));
+    }
+    let encoded_url: String = form_urlencoded::byte_serialize(payment_url.as_bytes()).collect();
+    params.push(format!("r={}", encoded_url));
+
+    // The address is already prefixed with the URI scheme
+    let prefix = match address {
+        Some(address) => address.to_string(),
+        None => format!("{}:", uri_scheme(&SETTINGS.network)),
+    };
+    format!("{}?{}", prefix, params.join("&"))
+}
+
+fn render_png(code: &QrCode) -> Vec<u8> {
+    let modules = code.width();
+    let size = (modules + 2 * QUIET_ZONE) * MODULE_SIZE;
+    let colors = code.to_colors();
+
+    // Light background with dark modules
+    let mut pixels = vec![255u8; size * size];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let x = (index % modules + QUIET_ZONE) * MODULE_SIZE;
+        let y = (index / modules + QUIET_ZONE) * MODULE_SIZE;
+        for row in y..y + MODULE_SIZE {
+            let start = row * size + x;
+            for pixel in &mut pixels[start..start + MODULE_SIZE] {
+                *pixel = 0;
+            }
+        }
+    }
+
+    let mut raw_png = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut raw_png, size as u32, size as u32);
+        encoder.set_color(ColorType::Grayscale);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&pixels).unwrap();
+    }
+    raw_png
+}
+
+fn render_svg(code: &QrCode) -> String {
+    code.render::<svg::Color>()
+        .quiet_zone(true)
+        .min_dimensions(200, 200)
+        .build()
+}
+
+// Generate the payment URI of an invoice along with its QR codes
+pub fn generate_payment_uri(
+    address: &str,
+    amount: u64,
+    has_extra_outputs: bool,
+    payment_id: &str,
+) -> Result<PaymentUri, ServerError> {
+    // The address is only paid directly if it is one of the invoice outputs
+    let address = if amount != 0 || !has_extra_outputs {
+        Some(address)
+    } else {
+        None
+    };
+    let payment_url = format!("{}{}", SETTINGS.payment_url, payment_id);
+    let uri = payment_uri(address, amount, &payment_url);
+
+    let code = QrCode::new(uri.as_bytes()).map_err(|_| ServerError::QrEncode)?;
+    Ok(PaymentUri {
+        qr_png: render_png(&code),
+        qr_svg: render_svg(&code),
+        uri,
+    })
+}
+
+// Payment URI and QR code handler
+pub fn qr_handler(
+    req: HttpRequest,
+    payment_id: web::Path<String>,
+    data: web::Data<(BitcoinClient, Store)>,
+    merchants: web::Data<MerchantRegistry>,
+) -> Box<dyn Future<Item = HttpResponse, Error = ServerError>> {
+    let store = data.1.to_owned();
+    let merchant = match merchants.authenticate(&req) {
+        Ok(ok) => ok,
+        Err(e) => return Box::new(err(e.into())),
+    };
+    let accepted = accepted_types(&req);
+    let payment_id = payment_id.to_string();
+
+    let response = get_payment_rows(store, payment_id.clone()).and_then(
+        move |(payment_row, payment_outputs)| {
+            check_owner(&merchant, &payment_row)?;
+            let payment_uri = generate_payment_uri(
+                &payment_row.address,
+                payment_row.amount as u64,
+                !payment_outputs.is_empty(),
+                &payment_id,
+            )?;
+
+            // Serve a single rendering if asked for one
+            let rendering = accepted
+                .iter()
+                .map(String::as_str)
+                .find(|media_type| *media_type == "image/png" || *media_type == "image/svg+xml");
+            let response = match rendering {
+                Some("image/png") => HttpResponse::Ok()
+                    .content_type("image/png")
+                    .body(payment_uri.qr_png),
+                Some("image/svg+xml") => HttpResponse::Ok()
+                    .content_type("image/svg+xml")
+                    .body(payment_uri.qr_svg),
+                _ => {
+                    let mut raw_payment_uri = Vec::with_capacity(payment_uri.encoded_len());
+                    payment_uri.encode(&mut raw_payment_uri).unwrap();
+                    HttpResponse::Ok()
+                        .content_type("application/x-protobuf")
+                        .body(raw_payment_uri)
+                }
+            };
+            Ok(response)
+        },
+    );
+
+    Box::new(response)
+}
@@ -0,0 +1,25 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ExchangeRateError {
+    NoProvider,
+    UnsupportedCurrency,
+    InvalidAmount,
+    Load,
+    Fetch,
+    Parse,
+}
+
+impl fmt::Display for ExchangeRateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match *self {
+            ExchangeRateError::NoProvider => "no exchange rate provider configured",
+            ExchangeRateError::UnsupportedCurrency => "unsupported fiat currency",
+            ExchangeRateError::InvalidAmount => "invalid fiat amount",
+            ExchangeRateError::Load => "failed to load exchange rates",
+            ExchangeRateError::Fetch => "failed to fetch exchange rate",
+            ExchangeRateError::Parse => "failed to parse exchange rate",
+        };
+        write!(f, "{}", printable)
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use futures::future::Future;
+use reqwest::r#async::Client;
+use serde_json::Value;
+
+use super::{errors::ExchangeRateError, ExchangeRateProvider};
+
+// Exchange rates fetched from a JSON HTTP endpoint
+pub struct HttpRates {
+    client: Client,
+    url: String,
+    field: String,
+}
+
+impl HttpRates {
+    // The URL may contain a "{currency}" placeholder, the rate is read from the given field
+    // of the JSON response
+    pub fn new(url: String, field: String, timeout: Duration) -> Self {
+        let client = Client::builder().timeout(timeout).build().unwrap();
+        HttpRates { client, url, field }
+    }
+}
+
+impl ExchangeRateProvider for HttpRates {
+    fn get_rate(&self, currency: &str) -> Box<dyn Future<Item = f64, Error = ExchangeRateError>> {
+        let url = self.url.replace("{currency}", currency);
+        let field = self.field.clone();
+        let rate = self
+            .client
+            .get(&url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|mut response| response.json::<Value>())
+            .map_err(|_| ExchangeRateError::Fetch)
+            .and_then(move |body| {
+                body.get(&field)
+                    .and_then(|value| match value {
+                        Value::String(value) => value.parse().ok(),
+                        value => value.as_f64(),
+                    })
+                    .ok_or(ExchangeRateError::Parse)
+            });
+        Box::new(rate)
+    }
+}
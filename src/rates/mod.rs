@@ -0,0 +1,80 @@
+pub mod errors;
+pub mod http;
+pub mod static_rates;
+
+use std::{sync::Arc, time::Duration};
+
+use futures::future::{self, Future};
+
+use crate::settings::ExchangeRates;
+
+use errors::ExchangeRateError;
+
+const SATS_PER_COIN: f64 = 100_000_000.;
+
+pub type RateProvider = Arc<dyn ExchangeRateProvider + Send + Sync>;
+
+pub trait ExchangeRateProvider {
+    // Price of one coin in the given fiat currency
+    fn get_rate(&self, currency: &str) -> Box<dyn Future<Item = f64, Error = ExchangeRateError>>;
+}
+
+// Fiat amount of an invoice along with the rate used to convert it
+#[derive(Clone, Debug, PartialEq)]
+pub struct FiatAmount {
+    pub currency: String,
+    pub amount: f64,
+    pub rate: f64,
+}
+
+impl FiatAmount {
+    // Equivalent amount in satoshis
+    pub fn to_sats(&self) -> u64 {
+        (self.amount / self.rate * SATS_PER_COIN).round() as u64
+    }
+}
+
+// Construct the provider described by the settings, an HTTP endpoint takes precedence over a file
+pub fn from_settings(settings: &ExchangeRates) -> Result<Option<RateProvider>, ExchangeRateError> {
+    if let Some(url) = &settings.url {
+        let provider = http::HttpRates::new(
+            url.clone(),
+            settings.field.clone(),
+            Duration::from_secs(settings.timeout),
+        );
+        return Ok(Some(Arc::new(provider)));
+    }
+    match &settings.file {
+        Some(path) => Ok(Some(Arc::new(static_rates::StaticRates::from_file(path)?))),
+        None => Ok(None),
+    }
+}
+
+// Snapshot the current rate for a fiat amount
+pub fn convert(
+    provider: Option<&RateProvider>,
+    currency: &str,
+    amount: f64,
+) -> Box<dyn Future<Item = FiatAmount, Error = ExchangeRateError>> {
+    let provider = match provider {
+        Some(some) => some,
+        None => return Box::new(future::err(ExchangeRateError::NoProvider)),
+    };
+    if !amount.is_finite() || amount <= 0. {
+        return Box::new(future::err(ExchangeRateError::InvalidAmount));
+    }
+
+    let currency = currency.to_uppercase();
+    let fiat_amount = provider.get_rate(&currency).and_then(move |rate| {
+        if rate.is_finite() && rate > 0. {
+            Ok(FiatAmount {
+                currency,
+                amount,
+                rate,
+            })
+        } else {
+            Err(ExchangeRateError::Parse)
+        }
+    });
+    Box::new(fiat_amount)
+}
@@ -0,0 +1,38 @@
+use std::{collections::HashMap, fs};
+
+use futures::future::{self, Future};
+
+use super::{errors::ExchangeRateError, ExchangeRateProvider};
+
+// Fixed exchange rates, keyed by currency code
+pub struct StaticRates {
+    rates: HashMap<String, f64>,
+}
+
+impl StaticRates {
+    pub fn new(rates: HashMap<String, f64>) -> Self {
+        let rates = rates
+            .into_iter()
+            .map(|(currency, rate)| (currency.to_uppercase(), rate))
+            .collect();
+        StaticRates { rates }
+    }
+
+    // Load rates from a JSON object mapping currency codes to rates
+    pub fn from_file(path: &str) -> Result<Self, ExchangeRateError> {
+        let raw_rates = fs::read(path).map_err(|_| ExchangeRateError::Load)?;
+        let rates = serde_json::from_slice(&raw_rates).map_err(|_| ExchangeRateError::Load)?;
+        Ok(StaticRates::new(rates))
+    }
+}
+
+impl ExchangeRateProvider for StaticRates {
+    fn get_rate(&self, currency: &str) -> Box<dyn Future<Item = f64, Error = ExchangeRateError>> {
+        let rate = self
+            .rates
+            .get(currency)
+            .cloned()
+            .ok_or(ExchangeRateError::UnsupportedCurrency);
+        Box::new(future::result(rate))
+    }
+}
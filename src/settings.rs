@@ -4,8 +4,16 @@ use serde_derive::Deserialize;
 
 use crate::bitcoin::Network;
 
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Command {
+    Serve,
+    Migrate,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Settings {
+    pub command: Command,
     pub bind_public: String,
     pub bind_private: String,
     pub payment_url: String,
@@ -14,9 +22,24 @@ pub struct Settings {
     pub rpc_username: String,
     pub rpc_password: String,
     pub zmq_port: u16,
+    pub confirmations: u32,
+    pub min_fee_rate: f64,
+    pub expiry_sweep_interval: u64,
+    pub double_spend_interval: u64,
     pub secret: String,
+    // Authenticates private API calls of the default merchant
+    pub api_key: Option<String>,
+    // Private API calls without an API key act as the default merchant
+    pub anonymous_default: bool,
     pub sql: Sql,
     pub network: Network,
+    pub pki: Option<Pki>,
+    pub callback: Callback,
+    pub rates: ExchangeRates,
+    pub tolerance: Tolerance,
+    pub addresses: Addresses,
+    pub address_pool: AddressPool,
+    pub merchants: Vec<Merchant>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +50,73 @@ pub struct Sql {
     pub username: String,
     pub password: String,
     pub db: String,
+    pub run_migrations: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Callback {
+    pub interval: u64,
+    pub timeout: u64,
+    pub base_delay: u64,
+    pub max_delay: u64,
+    pub max_attempts: i32,
+    pub concurrency: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Tolerance {
+    pub overpayment: bool,
+    pub partial: bool,
+    pub underpayment: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Addresses {
+    pub source: String,
+    pub xpub: Option<String>,
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    "m/0".to_string()
+}
+
+// Merchant hosted alongside the default one, authenticated by its API key
+#[derive(Debug, Deserialize)]
+pub struct Merchant {
+    pub id: String,
+    pub api_key: String,
+    pub secret: String,
+    // Node wallet to fetch addresses from and send refunds with
+    pub wallet: Option<String>,
+    pub addresses: Option<Addresses>,
+    pub req_memo: Option<String>,
+    pub ack_memo: Option<String>,
+    // Seconds until invoices expire unless the request sets an expiry
+    pub expiry: Option<u64>,
+    pub callback_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddressPool {
+    pub size: u64,
+    pub low_water: u64,
+    pub interval: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExchangeRates {
+    pub url: Option<String>,
+    pub file: Option<String>,
+    pub field: String,
+    pub timeout: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pki {
+    pub cert_chain: String,
+    pub private_key: String,
 }
 
 impl Settings {
@@ -40,6 +130,7 @@ impl Settings {
             Some(some) => some,
             None => return Err(ConfigError::Message("no home directory".to_string())),
         };
+        s.set_default("command", "serve").unwrap();
         s.set_default("bind_public", "127.0.0.1:8081").unwrap();
         s.set_default("bind_private", "127.0.0.1:8900").unwrap();
         s.set_default("payment_url", "http://127.0.0.1:8081/payment/")
@@ -49,14 +140,37 @@ impl Settings {
         s.set_default("rpc_username", "username").unwrap();
         s.set_default("rpc_password", "password").unwrap();
         s.set_default("zmq_port", "28332").unwrap();
+        s.set_default("confirmations", "1").unwrap();
+        s.set_default("min_fee_rate", "1.0").unwrap();
+        s.set_default("expiry_sweep_interval", "60").unwrap();
+        s.set_default("double_spend_interval", "10").unwrap();
         s.set_default("secret", "secret").unwrap();
+        s.set_default("anonymous_default", "false").unwrap();
         s.set_default("sql.prefix", "postgresql").unwrap();
         s.set_default("sql.host", "127.0.0.1").unwrap();
         s.set_default("sql.username", "postgres").unwrap();
         s.set_default("sql.password", "password").unwrap();
         s.set_default("sql.port", "5432").unwrap();
         s.set_default("sql.db", "postgres").unwrap();
+        s.set_default("sql.run_migrations", "false").unwrap();
         s.set_default("network", "regnet").unwrap();
+        s.set_default("callback.interval", "5").unwrap();
+        s.set_default("callback.timeout", "10").unwrap();
+        s.set_default("callback.base_delay", "10").unwrap();
+        s.set_default("callback.max_delay", "3600").unwrap();
+        s.set_default("callback.max_attempts", "20").unwrap();
+        s.set_default("callback.concurrency", "16").unwrap();
+        s.set_default("tolerance.overpayment", "true").unwrap();
+        s.set_default("tolerance.partial", "false").unwrap();
+        s.set_default("tolerance.underpayment", "0").unwrap();
+        s.set_default("rates.field", "rate").unwrap();
+        s.set_default("rates.timeout", "10").unwrap();
+        s.set_default("addresses.source", "node").unwrap();
+        s.set_default("addresses.path", "m/0").unwrap();
+        s.set_default("address_pool.size", "0").unwrap();
+        s.set_default("address_pool.low_water", "10").unwrap();
+        s.set_default("address_pool.interval", "10").unwrap();
+        s.set_default("merchants", Vec::<String>::new()).unwrap();
 
         // Load config from file
         let mut default_config = home_dir.clone();
@@ -105,11 +219,71 @@ impl Settings {
             s.set("zmq_port", node_zmq_port)?;
         }
 
+        // Set confirmation threshold from cmd line
+        if let Ok(confirmations) = value_t!(matches, "confirmations", i64) {
+            s.set("confirmations", confirmations)?;
+        }
+
+        // Set minimum fee rate from cmd line
+        if let Ok(min_fee_rate) = value_t!(matches, "min-fee-rate", f64) {
+            s.set("min_fee_rate", min_fee_rate)?;
+        }
+
         // Set secret from cmd line
         if let Some(secret) = matches.value_of("secret") {
             s.set("secret", secret)?;
         }
 
+        // Set certificate chain from cmd line
+        if let Some(cert_chain) = matches.value_of("cert-chain") {
+            s.set("pki.cert_chain", cert_chain)?;
+        }
+
+        // Set private key from cmd line
+        if let Some(private_key) = matches.value_of("private-key") {
+            s.set("pki.private_key", private_key)?;
+        }
+
+        // Set exchange rate endpoint from cmd line
+        if let Some(rates_url) = matches.value_of("rates-url") {
+            s.set("rates.url", rates_url)?;
+        }
+
+        // Set exchange rate file from cmd line
+        if let Some(rates_file) = matches.value_of("rates-file") {
+            s.set("rates.file", rates_file)?;
+        }
+
+        // Set address source from cmd line
+        if let Some(address_source) = matches.value_of("address-source") {
+            s.set("addresses.source", address_source)?;
+        }
+
+        // Set xpub from cmd line
+        if let Some(xpub) = matches.value_of("xpub") {
+            s.set("addresses.xpub", xpub)?;
+        }
+
+        // Set derivation path from cmd line
+        if let Some(derivation_path) = matches.value_of("derivation-path") {
+            s.set("addresses.path", derivation_path)?;
+        }
+
+        // Set address pool size from cmd line
+        if let Ok(address_pool_size) = value_t!(matches, "address-pool-size", i64) {
+            s.set("address_pool.size", address_pool_size)?;
+        }
+
+        // Apply migrations on startup
+        if matches.is_present("run-migrations") {
+            s.set("sql.run_migrations", true)?;
+        }
+
+        // Only migrate the database
+        if matches.subcommand_matches("migrate").is_some() {
+            s.set("command", "migrate")?;
+        }
+
         // TODO: Database from commandline
 
         s.try_into()
@@ -0,0 +1,49 @@
+use std::fmt;
+
+use diesel::result::Error as DieselError;
+use diesel_migrations::RunMigrationsError;
+
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound,
+    UnknownBackend,
+    Connection,
+    Query(DieselError),
+    Corrupt,
+    Migration(RunMigrationsError),
+    Schema(DieselError),
+    Nullability(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match self {
+            StoreError::NotFound => "not found",
+            StoreError::UnknownBackend => "unknown database backend",
+            StoreError::Connection => "failed to connect to database",
+            StoreError::Query(err) => return err.fmt(f),
+            StoreError::Corrupt => "unexpected value in database",
+            StoreError::Migration(err) => return write!(f, "migration failed: {}", err),
+            StoreError::Schema(err) => {
+                return write!(f, "database schema does not match, run migrations: {}", err)
+            }
+            StoreError::Nullability(column) => {
+                return write!(
+                    f,
+                    "database schema does not match, run migrations: nullability of {} differs",
+                    column
+                )
+            }
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+impl From<DieselError> for StoreError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => StoreError::NotFound,
+            err => StoreError::Query(err),
+        }
+    }
+}
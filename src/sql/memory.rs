@@ -0,0 +1,572 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{NaiveDateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    bitcoin::SpentOutput,
+    models::{Output, PaymentDetails},
+    rates::FiatAmount,
+};
+
+use super::{
+    errors::StoreError,
+    models::{
+        CallbackRow, CallbackStateEnum, MerchantRow, PaymentInputRow, PaymentOutputRow, PaymentRow,
+        PaymentStateEnum, PaymentTally, ReceivedAmount, RefundOutputRow,
+    },
+    PaymentStore, REFUND_PENDING,
+};
+
+#[derive(Default)]
+struct Tables {
+    merchants: HashMap<String, MerchantRow>,
+    payments: HashMap<Uuid, PaymentRow>,
+    payment_outputs: Vec<PaymentOutputRow>,
+    received_amounts: HashMap<Uuid, Vec<ReceivedAmount>>,
+    payment_transactions: Vec<(Uuid, String)>,
+    payment_inputs: Vec<PaymentInputRow>,
+    refund_outputs: Vec<RefundOutputRow>,
+    callbacks: Vec<CallbackRow>,
+    derivation_indices: HashMap<(String, String), i32>,
+    // Pairs of merchant ID and address, oldest first
+    address_pool: VecDeque<(String, String)>,
+}
+
+impl Tables {
+    fn payment_mut(&mut self, payment_id: &Uuid) -> Result<&mut PaymentRow, StoreError> {
+        self.payments
+            .get_mut(payment_id)
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn queue_callback(&mut self, payment_id: &Uuid, url: &str, payload: &[u8]) {
+        let id = self.callbacks.len() as i64 + 1;
+        self.callbacks.push(CallbackRow {
+            id,
+            payment_id: *payment_id,
+            url: url.to_string(),
+            payload: payload.to_vec(),
+            callback_state: CallbackStateEnum::Pending,
+            attempts: 0,
+            next_attempt: Utc::now().naive_utc(),
+            last_attempt: None,
+            last_error: None,
+        });
+    }
+
+    fn add_payment_tx(&mut self, payment_id: &Uuid, tx_id: &str) {
+        let payment_tx = (*payment_id, tx_id.to_string());
+        if !self.payment_transactions.contains(&payment_tx) {
+            self.payment_transactions.push(payment_tx);
+        }
+    }
+
+    fn callback_mut(&mut self, callback_id: i64) -> Result<&mut CallbackRow, StoreError> {
+        self.callbacks
+            .iter_mut()
+            .find(|callback| callback.id == callback_id)
+            .ok_or(StoreError::NotFound)
+    }
+}
+
+// Store kept in process memory, for tests and throwaway deployments
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStore {
+    fn tables(&self) -> MutexGuard<Tables> {
+        self.tables.lock().unwrap()
+    }
+}
+
+fn parse_payment_id(payment_id: &str) -> Result<Uuid, StoreError> {
+    Uuid::parse_str(payment_id).map_err(|_| StoreError::NotFound)
+}
+
+impl PaymentStore for MemoryStore {
+    fn upsert_merchant(&self, merchant: &MerchantRow) -> Result<(), StoreError> {
+        self.tables()
+            .merchants
+            .insert(merchant.id.clone(), merchant.clone());
+        Ok(())
+    }
+
+    fn get_merchants(&self) -> Result<Vec<MerchantRow>, StoreError> {
+        Ok(self.tables().merchants.values().cloned().collect())
+    }
+
+    fn revoke_api_keys(&self, configured_ids: &[String]) -> Result<(), StoreError> {
+        for merchant in self.tables().merchants.values_mut() {
+            if !configured_ids.contains(&merchant.id) {
+                merchant.api_key_hash = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_payment(
+        &self,
+        payment_details: &PaymentDetails,
+        id: &Uuid,
+        merchant_id: &str,
+        address: &str,
+        amount: i64,
+        req_memo: Option<&str>,
+        ack_memo: Option<&str>,
+        tokenize: bool,
+        tx_data: Option<&[u8]>,
+        callback_url: Option<&str>,
+        outputs: &[Output],
+        payment_request: &[u8],
+        fiat_amount: Option<&FiatAmount>,
+        min_fee_rate: Option<f64>,
+    ) -> Result<Uuid, StoreError> {
+        let amount_due = amount
+            + outputs
+                .iter()
+                .map(|output| output.amount.unwrap_or(0) as i64)
+                .sum::<i64>();
+        let payment_row = PaymentRow {
+            id: *id,
+            issue_time: NaiveDateTime::from_timestamp(payment_details.time as i64, 0),
+            amount,
+            address: address.to_string(),
+            expiry_time: payment_details
+                .expires
+                .map(|value| NaiveDateTime::from_timestamp(value as i64, 0)),
+            req_memo: req_memo.map(str::to_string),
+            merchant_data: payment_details.merchant_data.clone(),
+            ack_memo: ack_memo.map(str::to_string),
+            tokenize,
+            tx_data: tx_data.map(<[u8]>::to_vec),
+            payment_state: PaymentStateEnum::Pending,
+            payment_time: None,
+            callback_url: callback_url.map(str::to_string),
+            refund_tx_id: None,
+            block_hash: None,
+            block_height: None,
+            payment_ack: None,
+            payment_request: Some(payment_request.to_vec()),
+            fiat_currency: fiat_amount.map(|fiat_amount| fiat_amount.currency.clone()),
+            fiat_amount: fiat_amount.map(|fiat_amount| fiat_amount.amount),
+            exchange_rate: fiat_amount.map(|fiat_amount| fiat_amount.rate),
+            rejection_reason: None,
+            min_fee_rate,
+            double_spend_tx_id: None,
+            amount_received: 0,
+            amount_due,
+            overpaid_amount: 0,
+            merchant_id: merchant_id.to_string(),
+        };
+
+        let mut tables = self.tables();
+        if tables.payments.contains_key(id) {
+            return Err(StoreError::Corrupt);
+        }
+        tables.payments.insert(*id, payment_row);
+        for (idx, output) in outputs.iter().enumerate() {
+            tables.payment_outputs.push(PaymentOutputRow {
+                payment_id: *id,
+                idx: idx as i32,
+                amount: output.amount.unwrap_or(0) as i64,
+                script: output.script.clone(),
+            });
+        }
+        Ok(*id)
+    }
+
+    fn get_payment_outputs(&self, payment_id: &str) -> Result<Vec<PaymentOutputRow>, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        let mut payment_outputs: Vec<PaymentOutputRow> = self
+            .tables()
+            .payment_outputs
+            .iter()
+            .filter(|payment_output| payment_output.payment_id == uuid_payment_id)
+            .cloned()
+            .collect();
+        payment_outputs.sort_by_key(|payment_output| payment_output.idx);
+        Ok(payment_outputs)
+    }
+
+    fn get_payment(&self, payment_id: &str) -> Result<PaymentRow, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        self.tables()
+            .payments
+            .get(&uuid_payment_id)
+            .cloned()
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn record_rejection(
+        &self,
+        payment_id: &str,
+        rejection_reason: &str,
+        sent_tx_ids: &[String],
+    ) -> Result<(), StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        let mut tables = self.tables();
+        tables.payment_mut(&uuid_payment_id)?.rejection_reason = Some(rejection_reason.to_string());
+        for tx_id in sent_tx_ids {
+            tables.add_payment_tx(&uuid_payment_id, tx_id);
+        }
+        Ok(())
+    }
+
+    fn expire_payments(&self) -> Result<usize, StoreError> {
+        let now = Utc::now().naive_utc();
+        let mut n_expired = 0;
+        for payment_row in self.tables().payments.values_mut() {
+            let overdue = payment_row
+                .expiry_time
+                .map(|expiry_time| expiry_time < now)
+                .unwrap_or(false);
+            let open = match payment_row.payment_state {
+                PaymentStateEnum::Pending | PaymentStateEnum::Underpaid => true,
+                _ => false,
+            };
+            if open && overdue {
+                payment_row.payment_state = PaymentStateEnum::Expired;
+                n_expired += 1;
+            }
+        }
+        Ok(n_expired)
+    }
+
+    fn accept_payment(
+        &self,
+        payment_id: &str,
+        previous_amount_received: i64,
+        tally: &PaymentTally,
+        tx_ids: &[String],
+        inputs: &[SpentOutput],
+        refund_to: &[Output],
+        payment_ack: &[u8],
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        let mut tables = self.tables();
+
+        // Only pending or underpaid payments may be accepted, and only if no other payment was
+        // counted towards them in the meantime
+        let payment_row = match tables.payments.get_mut(&uuid_payment_id) {
+            Some(some) => some,
+            None => return Ok(false),
+        };
+        let acceptable = match payment_row.payment_state {
+            PaymentStateEnum::Pending | PaymentStateEnum::Underpaid => true,
+            _ => false,
+        };
+        if !acceptable || payment_row.amount_received != previous_amount_received {
+            return Ok(false);
+        }
+        payment_row.payment_state = if tally.amount_due == 0 {
+            PaymentStateEnum::Received
+        } else {
+            PaymentStateEnum::Underpaid
+        };
+        payment_row.payment_time = Some(Utc::now().naive_utc());
+        payment_row.payment_ack = Some(payment_ack.to_vec());
+        payment_row.amount_received = tally.amount_received;
+        payment_row.amount_due = tally.amount_due;
+        payment_row.overpaid_amount = tally.overpaid_amount;
+
+        // The totals of each script replace those of the previous payment
+        tables
+            .received_amounts
+            .insert(uuid_payment_id, tally.received_amounts.clone());
+
+        // Refunds go to the outputs given with the latest payment
+        tables
+            .refund_outputs
+            .retain(|refund_output| refund_output.payment_id != uuid_payment_id);
+
+        for tx_id in tx_ids {
+            tables.add_payment_tx(&uuid_payment_id, tx_id);
+        }
+        for input in inputs {
+            tables.payment_inputs.push(PaymentInputRow {
+                payment_id: uuid_payment_id,
+                tx_id: input.tx_id.clone(),
+                prev_tx_id: input.prev_tx_id.clone(),
+                prev_vout: input.prev_vout as i32,
+            });
+        }
+        for (idx, output) in refund_to.iter().enumerate() {
+            tables.refund_outputs.push(RefundOutputRow {
+                payment_id: uuid_payment_id,
+                idx: idx as i32,
+                amount: output.amount.map(|amount| amount as i64),
+                script: output.script.clone(),
+            });
+        }
+        if let Some((url, payload)) = callback {
+            tables.queue_callback(&uuid_payment_id, url, payload);
+        }
+        Ok(true)
+    }
+
+    fn get_received_amounts(&self, payment_id: &str) -> Result<Vec<ReceivedAmount>, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        Ok(self
+            .tables()
+            .received_amounts
+            .get(&uuid_payment_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn get_payment_tx_ids(&self, payment_id: &str) -> Result<Vec<String>, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        Ok(self
+            .tables()
+            .payment_transactions
+            .iter()
+            .filter(|(tx_payment_id, _)| *tx_payment_id == uuid_payment_id)
+            .map(|(_, tx_id)| tx_id.clone())
+            .collect())
+    }
+
+    fn get_unconfirmed_txs(&self) -> Result<Vec<(Uuid, String)>, StoreError> {
+        let tables = self.tables();
+        Ok(tables
+            .payment_transactions
+            .iter()
+            .filter(
+                |(payment_id, _)| match tables.payments[payment_id].payment_state {
+                    PaymentStateEnum::Received | PaymentStateEnum::DoubleSpent => true,
+                    _ => false,
+                },
+            )
+            .cloned()
+            .collect())
+    }
+
+    fn get_watched_inputs(&self) -> Result<Vec<PaymentInputRow>, StoreError> {
+        let tables = self.tables();
+        Ok(tables
+            .payment_inputs
+            .iter()
+            .filter(|payment_input| {
+                match tables.payments[&payment_input.payment_id].payment_state {
+                    PaymentStateEnum::Received | PaymentStateEnum::Underpaid => true,
+                    _ => false,
+                }
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn mark_double_spent(
+        &self,
+        payment_id: &Uuid,
+        double_spend_tx_id: Option<&str>,
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError> {
+        let mut tables = self.tables();
+
+        // Only unconfirmed payments may be double spent
+        let payment_row = match tables.payments.get_mut(payment_id) {
+            Some(some) => some,
+            None => return Ok(false),
+        };
+        match payment_row.payment_state {
+            PaymentStateEnum::Received | PaymentStateEnum::Underpaid => (),
+            _ => return Ok(false),
+        }
+        payment_row.payment_state = PaymentStateEnum::DoubleSpent;
+        payment_row.double_spend_tx_id = double_spend_tx_id.map(str::to_string);
+
+        if let Some((url, payload)) = callback {
+            tables.queue_callback(payment_id, url, payload);
+        }
+        Ok(true)
+    }
+
+    fn confirm_payment(
+        &self,
+        payment_id: &Uuid,
+        block_hash: &str,
+        block_height: i32,
+    ) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+
+        // A double spent payment may still be the one to confirm
+        if let Some(payment_row) = tables.payments.get_mut(payment_id) {
+            match payment_row.payment_state {
+                PaymentStateEnum::Received | PaymentStateEnum::DoubleSpent => {
+                    payment_row.payment_state = PaymentStateEnum::Confirmed;
+                    payment_row.block_hash = Some(block_hash.to_string());
+                    payment_row.block_height = Some(block_height);
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn get_refund_outputs(&self, payment_id: &str) -> Result<Vec<RefundOutputRow>, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        let mut refund_outputs: Vec<RefundOutputRow> = self
+            .tables()
+            .refund_outputs
+            .iter()
+            .filter(|refund_output| refund_output.payment_id == uuid_payment_id)
+            .cloned()
+            .collect();
+        refund_outputs.sort_by_key(|refund_output| refund_output.idx);
+        Ok(refund_outputs)
+    }
+
+    fn claim_refund(&self, payment_id: &str) -> Result<bool, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        let mut tables = self.tables();
+        let payment_row = tables.payment_mut(&uuid_payment_id)?;
+        if payment_row.refund_tx_id.is_some() {
+            return Ok(false);
+        }
+        payment_row.refund_tx_id = Some(REFUND_PENDING.to_string());
+        Ok(true)
+    }
+
+    fn release_refund(&self, payment_id: &str) -> Result<(), StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        let mut tables = self.tables();
+        let payment_row = tables.payment_mut(&uuid_payment_id)?;
+        if payment_row.refund_tx_id.as_ref().map(String::as_str) == Some(REFUND_PENDING) {
+            payment_row.refund_tx_id = None;
+        }
+        Ok(())
+    }
+
+    fn set_refund_tx_id(&self, payment_id: &str, tx_id: &str) -> Result<(), StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        self.tables().payment_mut(&uuid_payment_id)?.refund_tx_id = Some(tx_id.to_string());
+        Ok(())
+    }
+
+    fn get_due_callbacks(&self, limit: i64) -> Result<Vec<CallbackRow>, StoreError> {
+        let now = Utc::now().naive_utc();
+        let mut due_callbacks: Vec<CallbackRow> = self
+            .tables()
+            .callbacks
+            .iter()
+            .filter(|callback| {
+                callback.callback_state == CallbackStateEnum::Pending
+                    && callback.next_attempt <= now
+            })
+            .cloned()
+            .collect();
+        due_callbacks.sort_by_key(|callback| callback.next_attempt);
+        due_callbacks.truncate(limit.max(0) as usize);
+        Ok(due_callbacks)
+    }
+
+    fn callback_delivered(&self, callback_id: i64) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        let callback = tables.callback_mut(callback_id)?;
+        callback.callback_state = CallbackStateEnum::Delivered;
+        callback.attempts += 1;
+        callback.last_attempt = Some(Utc::now().naive_utc());
+        callback.last_error = None;
+        Ok(())
+    }
+
+    fn callback_failed(
+        &self,
+        callback_id: i64,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        let callback = tables.callback_mut(callback_id)?;
+        callback.attempts += 1;
+        callback.last_attempt = Some(Utc::now().naive_utc());
+        callback.last_error = Some(error.to_string());
+        match retry_at {
+            Some(retry_at) => callback.next_attempt = retry_at,
+            None => callback.callback_state = CallbackStateEnum::Failed,
+        }
+        Ok(())
+    }
+
+    fn get_callback_state(
+        &self,
+        payment_id: &str,
+    ) -> Result<Option<CallbackStateEnum>, StoreError> {
+        let uuid_payment_id = parse_payment_id(payment_id)?;
+        Ok(self
+            .tables()
+            .callbacks
+            .iter()
+            .filter(|callback| callback.payment_id == uuid_payment_id)
+            .max_by_key(|callback| callback.id)
+            .map(|callback| callback.callback_state))
+    }
+
+    fn reserve_derivation_index(&self, xpub: &str, path: &str) -> Result<i32, StoreError> {
+        let mut tables = self.tables();
+        let next_index = tables
+            .derivation_indices
+            .entry((xpub.to_string(), path.to_string()))
+            .or_insert(0);
+        let reserved = *next_index;
+        *next_index += 1;
+        Ok(reserved)
+    }
+
+    fn add_pooled_addresses(
+        &self,
+        merchant_id: &str,
+        addresses: &[String],
+    ) -> Result<usize, StoreError> {
+        let mut tables = self.tables();
+        let mut n_added = 0;
+        for address in addresses {
+            if !tables
+                .address_pool
+                .iter()
+                .any(|(_, pooled)| pooled == address)
+            {
+                tables
+                    .address_pool
+                    .push_back((merchant_id.to_string(), address.clone()));
+                n_added += 1;
+            }
+        }
+        Ok(n_added)
+    }
+
+    fn count_pooled_addresses(&self, merchant_id: &str) -> Result<i64, StoreError> {
+        let tables = self.tables();
+        let pooled = tables
+            .address_pool
+            .iter()
+            .filter(|(pooled_merchant_id, _)| pooled_merchant_id == merchant_id);
+        Ok(pooled.count() as i64)
+    }
+
+    fn claim_pooled_address(&self, merchant_id: &str) -> Result<Option<String>, StoreError> {
+        let mut tables = self.tables();
+        let position = tables
+            .address_pool
+            .iter()
+            .position(|(pooled_merchant_id, _)| pooled_merchant_id == merchant_id);
+        Ok(position
+            .and_then(|position| tables.address_pool.remove(position))
+            .map(|(_, address)| address))
+    }
+
+    fn run_migrations(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn check_schema(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
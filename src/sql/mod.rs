@@ -1,2 +1,254 @@
+pub mod errors;
+pub mod memory;
+pub mod models;
+pub mod mysql;
 pub mod postgresql;
-// TODO: MySQL
+pub mod sqlite;
+#[cfg(test)]
+mod tests;
+
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use crate::{
+    bitcoin::SpentOutput,
+    models::{Output, PaymentDetails},
+    rates::FiatAmount,
+    settings::Sql,
+};
+
+use errors::StoreError;
+use models::{
+    CallbackRow, CallbackStateEnum, ColumnInfo, MerchantRow, PaymentInputRow, PaymentOutputRow,
+    PaymentRow, PaymentTally, ReceivedAmount, RefundOutputRow,
+};
+
+pub type Store = Arc<dyn PaymentStore>;
+
+// Refund TX ID of a payment whose refund is being sent
+pub const REFUND_PENDING: &str = "pending";
+
+// Tables of the schema with the columns declared nullable by schema.rs, all other columns are
+// NOT NULL
+const NULLABLE_COLUMNS: &[(&str, &[&str])] = &[
+    (
+        "payments",
+        &[
+            "expiry_time",
+            "req_memo",
+            "merchant_data",
+            "ack_memo",
+            "tx_data",
+            "payment_time",
+            "callback_url",
+            "refund_tx_id",
+            "block_hash",
+            "block_height",
+            "payment_ack",
+            "payment_request",
+            "fiat_currency",
+            "fiat_amount",
+            "exchange_rate",
+            "rejection_reason",
+            "min_fee_rate",
+            "double_spend_tx_id",
+        ],
+    ),
+    ("payment_outputs", &[]),
+    ("received_amounts", &[]),
+    ("payment_transactions", &[]),
+    ("payment_inputs", &[]),
+    ("refund_outputs", &["amount"]),
+    ("callbacks", &["last_attempt", "last_error"]),
+    ("derivation_indices", &[]),
+    ("address_pool", &[]),
+    (
+        "merchants",
+        &[
+            "api_key_hash",
+            "wallet",
+            "xpub",
+            "req_memo",
+            "ack_memo",
+            "expiry",
+            "callback_url",
+        ],
+    ),
+];
+
+// Check the columns of the database are nullable exactly where schema.rs expects, columns of
+// other tables are ignored
+pub(crate) fn check_nullability(columns: &[ColumnInfo]) -> Result<(), StoreError> {
+    for column in columns {
+        let nullable_columns = match NULLABLE_COLUMNS
+            .iter()
+            .find(|(table, _)| *table == column.table_name)
+        {
+            Some((_, nullable_columns)) => nullable_columns,
+            None => continue,
+        };
+        if nullable_columns.contains(&column.column_name.as_str()) != column.nullable {
+            return Err(StoreError::Nullability(format!(
+                "{}.{}",
+                column.table_name, column.column_name
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Persistence of invoices, payments, callbacks and addresses. Calls block, so handlers
+// should run them on the thread pool
+pub trait PaymentStore: Send + Sync {
+    // Insert a merchant or replace the configuration of an existing one
+    fn upsert_merchant(&self, merchant: &MerchantRow) -> Result<(), StoreError>;
+
+    fn get_merchants(&self) -> Result<Vec<MerchantRow>, StoreError>;
+
+    // Revoke the API keys of merchants no longer in the configuration, their invoices are kept
+    fn revoke_api_keys(&self, configured_ids: &[String]) -> Result<(), StoreError>;
+
+    fn add_payment(
+        &self,
+        payment_details: &PaymentDetails,
+        id: &Uuid,
+        merchant_id: &str,
+        address: &str,
+        amount: i64,
+        req_memo: Option<&str>,
+        ack_memo: Option<&str>,
+        tokenize: bool,
+        tx_data: Option<&[u8]>,
+        callback_url: Option<&str>,
+        outputs: &[Output],
+        payment_request: &[u8],
+        fiat_amount: Option<&FiatAmount>,
+        min_fee_rate: Option<f64>,
+    ) -> Result<Uuid, StoreError>;
+
+    fn get_payment_outputs(&self, payment_id: &str) -> Result<Vec<PaymentOutputRow>, StoreError>;
+
+    fn get_payment(&self, payment_id: &str) -> Result<PaymentRow, StoreError>;
+
+    // Record why the node refused a payment along with the txs of it already broadcast, the
+    // payment remains pending
+    fn record_rejection(
+        &self,
+        payment_id: &str,
+        rejection_reason: &str,
+        sent_tx_ids: &[String],
+    ) -> Result<(), StoreError>;
+
+    // Mark overdue pending or underpaid payments as expired, underpaid ones keep their received
+    // amount so it can be refunded
+    fn expire_payments(&self) -> Result<usize, StoreError>;
+
+    // Record a payment towards a pending or underpaid invoice, false if the invoice is no longer
+    // in that state or another payment was counted towards it in the meantime
+    fn accept_payment(
+        &self,
+        payment_id: &str,
+        previous_amount_received: i64,
+        tally: &PaymentTally,
+        tx_ids: &[String],
+        inputs: &[SpentOutput],
+        refund_to: &[Output],
+        payment_ack: &[u8],
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError>;
+
+    // Totals received so far by each script of an invoice
+    fn get_received_amounts(&self, payment_id: &str) -> Result<Vec<ReceivedAmount>, StoreError>;
+
+    fn get_payment_tx_ids(&self, payment_id: &str) -> Result<Vec<String>, StoreError>;
+
+    // Transactions of all received or double spent payments awaiting confirmation
+    fn get_unconfirmed_txs(&self) -> Result<Vec<(Uuid, String)>, StoreError>;
+
+    // Outputs spent by all received or underpaid payments
+    fn get_watched_inputs(&self) -> Result<Vec<PaymentInputRow>, StoreError>;
+
+    fn mark_double_spent(
+        &self,
+        payment_id: &Uuid,
+        double_spend_tx_id: Option<&str>,
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError>;
+
+    fn confirm_payment(
+        &self,
+        payment_id: &Uuid,
+        block_hash: &str,
+        block_height: i32,
+    ) -> Result<(), StoreError>;
+
+    fn get_refund_outputs(&self, payment_id: &str) -> Result<Vec<RefundOutputRow>, StoreError>;
+
+    // Mark a payment as being refunded, false if it was refunded or claimed for a refund already
+    fn claim_refund(&self, payment_id: &str) -> Result<bool, StoreError>;
+
+    // Give up a refund claim after the node refused to send the refund
+    fn release_refund(&self, payment_id: &str) -> Result<(), StoreError>;
+
+    fn set_refund_tx_id(&self, payment_id: &str, tx_id: &str) -> Result<(), StoreError>;
+
+    fn get_due_callbacks(&self, limit: i64) -> Result<Vec<CallbackRow>, StoreError>;
+
+    fn callback_delivered(&self, callback_id: i64) -> Result<(), StoreError>;
+
+    // Record a failed delivery, the callback is abandoned if no next attempt is given
+    fn callback_failed(
+        &self,
+        callback_id: i64,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<(), StoreError>;
+
+    fn get_callback_state(&self, payment_id: &str)
+        -> Result<Option<CallbackStateEnum>, StoreError>;
+
+    // Reserve the next child index of an xpub chain, starting from zero
+    fn reserve_derivation_index(&self, xpub: &str, path: &str) -> Result<i32, StoreError>;
+
+    fn add_pooled_addresses(
+        &self,
+        merchant_id: &str,
+        addresses: &[String],
+    ) -> Result<usize, StoreError>;
+
+    fn count_pooled_addresses(&self, merchant_id: &str) -> Result<i64, StoreError>;
+
+    // Remove and return the oldest address pooled for the merchant, never handing out an
+    // address twice
+    fn claim_pooled_address(&self, merchant_id: &str) -> Result<Option<String>, StoreError>;
+
+    // Apply pending migrations embedded in the binary
+    fn run_migrations(&self) -> Result<(), StoreError>;
+
+    // Probe every table, column and enum type the store relies on and the nullability of the
+    // columns
+    fn check_schema(&self) -> Result<(), StoreError>;
+}
+
+// Construct the store selected by the SQL prefix
+pub fn from_settings(settings: &Sql) -> Result<Store, StoreError> {
+    let url = format!(
+        "{}://{}:{}@{}:{}/{}",
+        settings.prefix,
+        settings.username,
+        settings.password,
+        settings.host,
+        settings.port,
+        settings.db
+    );
+    match settings.prefix.as_str() {
+        "postgresql" | "postgres" => Ok(Arc::new(postgresql::PostgresStore::new(&url)?)),
+        "mysql" => Ok(Arc::new(mysql::MysqlStore::new(&url)?)),
+        // The database is a file path
+        "sqlite" => Ok(Arc::new(sqlite::SqliteStore::new(&settings.db)?)),
+        "memory" => Ok(Arc::new(memory::MemoryStore::default())),
+        _ => Err(StoreError::UnknownBackend),
+    }
+}
@@ -0,0 +1,200 @@
+use std::{fmt, str::FromStr};
+
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+pub use super::postgresql::schema::{CallbackStateEnum, PaymentStateEnum};
+
+#[derive(Clone, PartialEq, Debug, Serialize, Queryable, Deserialize)]
+pub struct PaymentRow {
+    pub id: Uuid,
+    pub issue_time: NaiveDateTime,
+    pub amount: i64,
+    pub address: String,
+    pub expiry_time: Option<NaiveDateTime>,
+    pub req_memo: Option<String>,
+    pub merchant_data: Option<Vec<u8>>,
+    pub ack_memo: Option<String>,
+    pub tokenize: bool,
+    pub tx_data: Option<Vec<u8>>,
+    pub payment_state: PaymentStateEnum,
+    pub payment_time: Option<NaiveDateTime>,
+    pub callback_url: Option<String>,
+    pub refund_tx_id: Option<String>,
+    pub block_hash: Option<String>,
+    pub block_height: Option<i32>,
+    pub payment_ack: Option<Vec<u8>>,
+    pub payment_request: Option<Vec<u8>>,
+    pub fiat_currency: Option<String>,
+    pub fiat_amount: Option<f64>,
+    pub exchange_rate: Option<f64>,
+    pub rejection_reason: Option<String>,
+    pub min_fee_rate: Option<f64>,
+    pub double_spend_tx_id: Option<String>,
+    pub amount_received: i64,
+    pub amount_due: i64,
+    pub overpaid_amount: i64,
+    pub merchant_id: String,
+}
+
+// Amounts of an invoice after accepting a payment
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentTally {
+    pub amount_received: i64,
+    pub amount_due: i64,
+    pub overpaid_amount: i64,
+    // Totals received by each expected script
+    pub received_amounts: Vec<ReceivedAmount>,
+}
+
+// Total received by one of the scripts of an invoice
+#[derive(Clone, PartialEq, Debug, Serialize, Queryable, Deserialize)]
+pub struct ReceivedAmount {
+    pub script: Vec<u8>,
+    pub amount: i64,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Queryable, Deserialize)]
+pub struct PaymentInputRow {
+    pub payment_id: Uuid,
+    pub tx_id: String,
+    pub prev_tx_id: String,
+    pub prev_vout: i32,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Queryable, Deserialize)]
+pub struct PaymentOutputRow {
+    pub payment_id: Uuid,
+    pub idx: i32,
+    pub amount: i64,
+    pub script: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Queryable, Deserialize)]
+pub struct RefundOutputRow {
+    pub payment_id: Uuid,
+    pub idx: i32,
+    pub amount: Option<i64>,
+    pub script: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Queryable, Deserialize)]
+pub struct CallbackRow {
+    pub id: i64,
+    pub payment_id: Uuid,
+    pub url: String,
+    pub payload: Vec<u8>,
+    pub callback_state: CallbackStateEnum,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub last_attempt: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Queryable, Deserialize)]
+pub struct MerchantRow {
+    pub id: String,
+    pub api_key_hash: Option<String>,
+    pub secret: String,
+    pub address_source: String,
+    pub wallet: Option<String>,
+    pub xpub: Option<String>,
+    pub derivation_path: String,
+    pub req_memo: Option<String>,
+    pub ack_memo: Option<String>,
+    pub expiry: Option<i64>,
+    pub callback_url: Option<String>,
+}
+
+impl PaymentStateEnum {
+    pub const ALL: [PaymentStateEnum; 7] = [
+        PaymentStateEnum::Pending,
+        PaymentStateEnum::Received,
+        PaymentStateEnum::Confirmed,
+        PaymentStateEnum::Rejected,
+        PaymentStateEnum::Expired,
+        PaymentStateEnum::DoubleSpent,
+        PaymentStateEnum::Underpaid,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PaymentStateEnum::Pending => "pending",
+            PaymentStateEnum::Received => "received",
+            PaymentStateEnum::Confirmed => "confirmed",
+            PaymentStateEnum::Rejected => "rejected",
+            PaymentStateEnum::Expired => "expired",
+            PaymentStateEnum::DoubleSpent => "double_spent",
+            PaymentStateEnum::Underpaid => "underpaid",
+        }
+    }
+}
+
+impl FromStr for PaymentStateEnum {
+    type Err = UnknownVariant;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(PaymentStateEnum::Pending),
+            "received" => Ok(PaymentStateEnum::Received),
+            "confirmed" => Ok(PaymentStateEnum::Confirmed),
+            "rejected" => Ok(PaymentStateEnum::Rejected),
+            "expired" => Ok(PaymentStateEnum::Expired),
+            "double_spent" => Ok(PaymentStateEnum::DoubleSpent),
+            "underpaid" => Ok(PaymentStateEnum::Underpaid),
+            _ => Err(UnknownVariant),
+        }
+    }
+}
+
+impl CallbackStateEnum {
+    pub const ALL: [CallbackStateEnum; 3] = [
+        CallbackStateEnum::Pending,
+        CallbackStateEnum::Delivered,
+        CallbackStateEnum::Failed,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CallbackStateEnum::Pending => "pending",
+            CallbackStateEnum::Delivered => "delivered",
+            CallbackStateEnum::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for CallbackStateEnum {
+    type Err = UnknownVariant;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(CallbackStateEnum::Pending),
+            "delivered" => Ok(CallbackStateEnum::Delivered),
+            "failed" => Ok(CallbackStateEnum::Failed),
+            _ => Err(UnknownVariant),
+        }
+    }
+}
+
+// Column of a table as described by the database
+#[derive(Debug, QueryableByName)]
+pub struct ColumnInfo {
+    #[sql_type = "diesel::sql_types::Text"]
+    pub table_name: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub column_name: String,
+    #[sql_type = "diesel::sql_types::Bool"]
+    pub nullable: bool,
+}
+
+// Stored enum value not known to this version
+#[derive(Debug)]
+pub struct UnknownVariant;
+
+impl fmt::Display for UnknownVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unrecognized enum variant")
+    }
+}
+
+impl std::error::Error for UnknownVariant {}
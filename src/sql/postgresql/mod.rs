@@ -5,24 +5,92 @@ use chrono::{NaiveDateTime, Utc};
 use diesel::{
     pg::PgConnection,
     prelude::*,
-    r2d2::{ConnectionManager, PooledConnection},
+    r2d2::{ConnectionManager, Pool, PooledConnection},
     result::Error,
 };
 use uuid::Uuid;
 
 use crate::{
+    bitcoin::SpentOutput,
     models::*,
-    sql::postgresql::{
-        models::{NewPayment, PaymentRow},
-        schema::PaymentStateEnum,
+    rates::FiatAmount,
+    sql::{
+        check_nullability,
+        errors::StoreError,
+        models::{
+            CallbackRow, CallbackStateEnum, ColumnInfo, MerchantRow, PaymentInputRow,
+            PaymentOutputRow, PaymentRow, PaymentStateEnum, PaymentTally, ReceivedAmount,
+            RefundOutputRow,
+        },
+        postgresql::models::{
+            NewCallback, NewDerivationIndex, NewMerchant, NewPayment, NewPaymentInput,
+            NewPaymentOutput, NewPaymentTransaction, NewPooledAddress, NewReceivedAmount,
+            NewRefundOutput,
+        },
+        PaymentStore, REFUND_PENDING,
     },
 };
 
-use schema::payments::dsl::{self, payments};
+use schema::{
+    address_pool::dsl::address_pool,
+    callbacks::dsl::callbacks,
+    derivation_indices::dsl::derivation_indices,
+    merchants::dsl::merchants,
+    payment_inputs::dsl::payment_inputs,
+    payment_outputs::dsl::payment_outputs,
+    payment_transactions::dsl::payment_transactions,
+    payments::dsl::{self, payments},
+    received_amounts::dsl::received_amounts,
+    refund_outputs::dsl::refund_outputs,
+};
+
+pub type ConnPool = Pool<ConnectionManager<PgConnection>>;
+
+embed_migrations!("migrations");
+
+// Payment ids come from request paths, one that is not a UUID matches no payment
+fn parse_payment_id(payment_id: &str) -> Result<Uuid, Error> {
+    Uuid::parse_str(payment_id).map_err(|_| Error::NotFound)
+}
+
+pub fn upsert_merchant(
+    merchant: &MerchantRow,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), Error> {
+    use schema::merchants::dsl::id;
+
+    let new_merchant = NewMerchant::from(merchant);
+    diesel::insert_into(merchants)
+        .values(&new_merchant)
+        .on_conflict(id)
+        .do_update()
+        .set(&new_merchant)
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn get_merchants(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<MerchantRow>, Error> {
+    merchants.load(conn)
+}
+
+pub fn revoke_api_keys(
+    configured_ids: &[String],
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), Error> {
+    use schema::merchants::dsl::{api_key_hash, id};
+
+    diesel::update(merchants.filter(diesel::dsl::not(id.eq_any(configured_ids))))
+        .set(api_key_hash.eq(None::<String>))
+        .execute(conn)?;
+    Ok(())
+}
 
 pub fn add_payment(
     payment_details: &PaymentDetails,
     id: &Uuid,
+    merchant_id: &str,
     address: &str,
     amount: i64,
     req_memo: Option<&str>,
@@ -30,9 +98,13 @@ pub fn add_payment(
     tokenize: bool,
     tx_data: Option<&[u8]>,
     callback_url: Option<&str>,
+    outputs: &[Output],
+    payment_request: &[u8],
+    fiat_amount: Option<&FiatAmount>,
+    min_fee_rate: Option<f64>,
     conn: &PooledConnection<ConnectionManager<PgConnection>>,
 ) -> Result<Uuid, Error> {
-    use schema::{payments::dsl::id as dsl_id};
+    use schema::payments::dsl::id as dsl_id;
 
     let issue_time = &NaiveDateTime::from_timestamp(payment_details.time as i64, 0);
     let expiry_time = payment_details
@@ -42,6 +114,11 @@ pub fn add_payment(
         .merchant_data
         .as_ref()
         .map(|value| &value[..]);
+    let amount_due = amount
+        + outputs
+            .iter()
+            .map(|output| output.amount.unwrap_or(0) as i64)
+            .sum::<i64>();
 
     // Construct row
     let new_payment = NewPayment {
@@ -57,11 +134,47 @@ pub fn add_payment(
         tx_data,
         payment_state: &PaymentStateEnum::Pending,
         callback_url,
+        payment_request,
+        fiat_currency: fiat_amount.map(|fiat_amount| &fiat_amount.currency[..]),
+        fiat_amount: fiat_amount.map(|fiat_amount| fiat_amount.amount),
+        exchange_rate: fiat_amount.map(|fiat_amount| fiat_amount.rate),
+        min_fee_rate,
+        amount_due,
+        merchant_id,
     };
-    diesel::insert_into(payments)
-        .values(&new_payment)
-        .returning(dsl_id)
-        .get_result(conn)
+    let new_payment_outputs: Vec<NewPaymentOutput> = outputs
+        .iter()
+        .enumerate()
+        .map(|(idx, output)| NewPaymentOutput {
+            payment_id: id,
+            idx: idx as i32,
+            amount: output.amount.unwrap_or(0) as i64,
+            script: &output.script[..],
+        })
+        .collect();
+    conn.transaction(|| {
+        let id = diesel::insert_into(payments)
+            .values(&new_payment)
+            .returning(dsl_id)
+            .get_result(conn)?;
+        diesel::insert_into(payment_outputs)
+            .values(&new_payment_outputs)
+            .execute(conn)?;
+        Ok(id)
+    })
+}
+
+pub fn get_payment_outputs(
+    payment_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<PaymentOutputRow>, Error> {
+    use schema::payment_outputs::dsl::{idx, payment_id as dsl_payment_id};
+
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    payment_outputs
+        .filter(dsl_payment_id.eq(uuid_payment_id))
+        .order(idx.asc())
+        .load(conn)
 }
 
 pub fn get_payment(
@@ -70,38 +183,777 @@ pub fn get_payment(
 ) -> Result<PaymentRow, Error> {
     use schema::payments::dsl::id as dsl_id;
 
-    let uuid_payment_id = Uuid::parse_str(&payment_id).unwrap();
+    let uuid_payment_id = parse_payment_id(payment_id)?;
     payments
         .filter(dsl_id.eq(uuid_payment_id))
-        .first::<models::PaymentRow>(conn)
+        .first::<PaymentRow>(conn)
 }
 
-pub fn reject_payment(
+pub fn record_rejection(
     payment_id: &str,
+    rejection_reason: &str,
+    sent_tx_ids: &[String],
     conn: &PooledConnection<ConnectionManager<PgConnection>>,
 ) -> Result<(), Error> {
-    let uuid_payment_id = Uuid::parse_str(&payment_id).unwrap();
-    diesel::update(payments.find(uuid_payment_id))
-        .set(dsl::payment_state.eq(PaymentStateEnum::Rejected))
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    let new_payment_txs: Vec<NewPaymentTransaction> = sent_tx_ids
+        .iter()
+        .map(|tx_id| NewPaymentTransaction {
+            payment_id: &uuid_payment_id,
+            tx_id: tx_id.as_str(),
+        })
+        .collect();
+    conn.transaction(|| {
+        diesel::update(payments.find(uuid_payment_id))
+            .set(dsl::rejection_reason.eq(rejection_reason))
+            .execute(conn)?;
+        diesel::insert_into(payment_transactions)
+            .values(&new_payment_txs)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+pub fn expire_payments(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<usize, Error> {
+    let now = Utc::now().naive_utc();
+    diesel::update(
+        payments
+            .filter(
+                dsl::payment_state
+                    .eq_any(vec![PaymentStateEnum::Pending, PaymentStateEnum::Underpaid]),
+            )
+            .filter(dsl::expiry_time.lt(now)),
+    )
+    .set(dsl::payment_state.eq(PaymentStateEnum::Expired))
+    .execute(conn)
+}
+
+pub fn accept_payment(
+    payment_id: &str,
+    previous_amount_received: i64,
+    tally: &PaymentTally,
+    tx_ids: &[String],
+    inputs: &[SpentOutput],
+    refund_to: &[Output],
+    payment_ack: &[u8],
+    callback: Option<(&str, &[u8])>,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<bool, Error> {
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    let gen_accept_time = Utc::now().naive_utc();
+    let new_payment_txs: Vec<NewPaymentTransaction> = tx_ids
+        .iter()
+        .map(|tx_id| NewPaymentTransaction {
+            payment_id: &uuid_payment_id,
+            tx_id: tx_id.as_str(),
+        })
+        .collect();
+    let new_payment_inputs: Vec<NewPaymentInput> = inputs
+        .iter()
+        .map(|input| NewPaymentInput {
+            payment_id: &uuid_payment_id,
+            tx_id: &input.tx_id,
+            prev_tx_id: &input.prev_tx_id,
+            prev_vout: input.prev_vout as i32,
+        })
+        .collect();
+    let new_refund_outputs: Vec<NewRefundOutput> = refund_to
+        .iter()
+        .enumerate()
+        .map(|(idx, output)| NewRefundOutput {
+            payment_id: &uuid_payment_id,
+            idx: idx as i32,
+            amount: output.amount.map(|amount| amount as i64),
+            script: &output.script[..],
+        })
+        .collect();
+    let new_received_amounts: Vec<NewReceivedAmount> = tally
+        .received_amounts
+        .iter()
+        .map(|received_amount| NewReceivedAmount {
+            payment_id: &uuid_payment_id,
+            script: &received_amount.script[..],
+            amount: received_amount.amount,
+        })
+        .collect();
+    let payment_state = if tally.amount_due == 0 {
+        PaymentStateEnum::Received
+    } else {
+        PaymentStateEnum::Underpaid
+    };
+    conn.transaction(|| {
+        // Only pending or underpaid payments may be accepted, and only if no other payment was
+        // counted towards them in the meantime
+        let updated = diesel::update(
+            payments
+                .find(uuid_payment_id)
+                .filter(
+                    dsl::payment_state
+                        .eq_any(vec![PaymentStateEnum::Pending, PaymentStateEnum::Underpaid]),
+                )
+                .filter(dsl::amount_received.eq(previous_amount_received)),
+        )
+        .set((
+            dsl::payment_state.eq(payment_state),
+            dsl::payment_time.eq(gen_accept_time),
+            dsl::payment_ack.eq(payment_ack),
+            dsl::amount_received.eq(tally.amount_received),
+            dsl::amount_due.eq(tally.amount_due),
+            dsl::overpaid_amount.eq(tally.overpaid_amount),
+        ))
+        .execute(conn)?;
+        if updated == 0 {
+            return Ok(false);
+        }
+
+        // Refunds go to the outputs given with the latest payment
+        diesel::delete(
+            refund_outputs.filter(schema::refund_outputs::dsl::payment_id.eq(uuid_payment_id)),
+        )
         .execute(conn)?;
+
+        // The totals of each script replace those of the previous payment
+        diesel::delete(
+            received_amounts.filter(schema::received_amounts::dsl::payment_id.eq(uuid_payment_id)),
+        )
+        .execute(conn)?;
+        diesel::insert_into(received_amounts)
+            .values(&new_received_amounts)
+            .execute(conn)?;
+
+        // Txs broadcast by an earlier, partially sent submission may already be recorded
+        diesel::insert_into(payment_transactions)
+            .values(&new_payment_txs)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        diesel::insert_into(payment_inputs)
+            .values(&new_payment_inputs)
+            .execute(conn)?;
+        diesel::insert_into(refund_outputs)
+            .values(&new_refund_outputs)
+            .execute(conn)?;
+        if let Some((url, payload)) = callback {
+            // Queue callback for delivery
+            let new_callback = NewCallback {
+                payment_id: &uuid_payment_id,
+                url,
+                payload,
+                callback_state: &CallbackStateEnum::Pending,
+                next_attempt: &gen_accept_time,
+            };
+            diesel::insert_into(callbacks)
+                .values(&new_callback)
+                .execute(conn)?;
+        }
+        Ok(true)
+    })
+}
+
+pub fn get_received_amounts(
+    payment_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<ReceivedAmount>, Error> {
+    use schema::received_amounts::dsl::{amount, payment_id as dsl_payment_id, script};
+
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    received_amounts
+        .filter(dsl_payment_id.eq(uuid_payment_id))
+        .select((script, amount))
+        .load(conn)
+}
+
+pub fn get_payment_tx_ids(
+    payment_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<String>, Error> {
+    use schema::payment_transactions::dsl::{payment_id as dsl_payment_id, tx_id as dsl_tx_id};
+
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    payment_transactions
+        .filter(dsl_payment_id.eq(uuid_payment_id))
+        .select(dsl_tx_id)
+        .load(conn)
+}
+
+// Get the transactions of all received or double spent payments awaiting confirmation
+pub fn get_unconfirmed_txs(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<(Uuid, String)>, Error> {
+    use schema::payment_transactions::dsl::tx_id;
+
+    payments
+        .inner_join(payment_transactions)
+        .filter(dsl::payment_state.eq_any(vec![
+            PaymentStateEnum::Received,
+            PaymentStateEnum::DoubleSpent,
+        ]))
+        .select((dsl::id, tx_id))
+        .load(conn)
+}
+
+// Get the outputs spent by all received or underpaid payments
+pub fn get_watched_inputs(
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<PaymentInputRow>, Error> {
+    use schema::payment_inputs::all_columns;
+
+    payment_inputs
+        .inner_join(payments)
+        .filter(dsl::payment_state.eq_any(vec![
+            PaymentStateEnum::Received,
+            PaymentStateEnum::Underpaid,
+        ]))
+        .select(all_columns)
+        .load(conn)
+}
+
+pub fn mark_double_spent(
+    payment_id: &Uuid,
+    double_spend_tx_id: Option<&str>,
+    callback: Option<(&str, &[u8])>,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<bool, Error> {
+    let gen_detect_time = Utc::now().naive_utc();
+    conn.transaction(|| {
+        // Only unconfirmed payments may be double spent
+        let updated = diesel::update(payments.find(*payment_id).filter(dsl::payment_state.eq_any(
+            vec![PaymentStateEnum::Received, PaymentStateEnum::Underpaid],
+        )))
+        .set((
+            dsl::payment_state.eq(PaymentStateEnum::DoubleSpent),
+            dsl::double_spend_tx_id.eq(double_spend_tx_id),
+        ))
+        .execute(conn)?;
+        if updated == 0 {
+            return Ok(false);
+        }
+
+        if let Some((url, payload)) = callback {
+            // Queue callback for delivery
+            let new_callback = NewCallback {
+                payment_id,
+                url,
+                payload,
+                callback_state: &CallbackStateEnum::Pending,
+                next_attempt: &gen_detect_time,
+            };
+            diesel::insert_into(callbacks)
+                .values(&new_callback)
+                .execute(conn)?;
+        }
+        Ok(true)
+    })
+}
+
+pub fn confirm_payment(
+    payment_id: &Uuid,
+    block_hash: &str,
+    block_height: i32,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), Error> {
+    // A double spent payment may still be the one to confirm
+    diesel::update(
+        payments
+            .find(*payment_id)
+            .filter(dsl::payment_state.eq_any(vec![
+                PaymentStateEnum::Received,
+                PaymentStateEnum::DoubleSpent,
+            ])),
+    )
+    .set((
+        dsl::payment_state.eq(PaymentStateEnum::Confirmed),
+        dsl::block_hash.eq(block_hash),
+        dsl::block_height.eq(block_height),
+    ))
+    .execute(conn)?;
     Ok(())
 }
 
-pub fn accept_payment(
+pub fn get_refund_outputs(
+    payment_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<RefundOutputRow>, Error> {
+    use schema::refund_outputs::dsl::{idx, payment_id as dsl_payment_id};
+
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    refund_outputs
+        .filter(dsl_payment_id.eq(uuid_payment_id))
+        .order(idx.asc())
+        .load(conn)
+}
+
+pub fn claim_refund(
+    payment_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<bool, Error> {
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    let claimed = diesel::update(
+        payments
+            .find(uuid_payment_id)
+            .filter(dsl::refund_tx_id.is_null()),
+    )
+    .set(dsl::refund_tx_id.eq(REFUND_PENDING))
+    .execute(conn)?;
+    Ok(claimed == 1)
+}
+
+pub fn release_refund(
+    payment_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), Error> {
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    diesel::update(
+        payments
+            .find(uuid_payment_id)
+            .filter(dsl::refund_tx_id.eq(REFUND_PENDING)),
+    )
+    .set(dsl::refund_tx_id.eq(None::<String>))
+    .execute(conn)?;
+    Ok(())
+}
+
+pub fn set_refund_tx_id(
     payment_id: &str,
     tx_id: &str,
-    reund_to: Option<&str>,
     conn: &PooledConnection<ConnectionManager<PgConnection>>,
 ) -> Result<(), Error> {
-    let uuid_payment_id = Uuid::parse_str(&payment_id).unwrap();
-    let gen_accept_time = Utc::now().naive_utc();
+    let uuid_payment_id = parse_payment_id(payment_id)?;
     diesel::update(payments.find(uuid_payment_id))
+        .set(dsl::refund_tx_id.eq(tx_id))
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn get_due_callbacks(
+    limit: i64,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Vec<CallbackRow>, Error> {
+    use schema::callbacks::dsl::{callback_state, next_attempt};
+
+    let now = Utc::now().naive_utc();
+    callbacks
+        .filter(callback_state.eq(CallbackStateEnum::Pending))
+        .filter(next_attempt.le(now))
+        .order(next_attempt.asc())
+        .limit(limit)
+        .load(conn)
+}
+
+pub fn callback_delivered(
+    callback_id: i64,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), Error> {
+    use schema::callbacks::dsl::{attempts, callback_state, last_attempt, last_error};
+
+    let now = Utc::now().naive_utc();
+    diesel::update(callbacks.find(callback_id))
         .set((
-            dsl::payment_state.eq(PaymentStateEnum::Received),
-            dsl::payment_time.eq(gen_accept_time),
-            dsl::refund_to.eq(reund_to),
-            dsl::tx_id.eq(tx_id),
+            callback_state.eq(CallbackStateEnum::Delivered),
+            attempts.eq(attempts + 1),
+            last_attempt.eq(now),
+            last_error.eq(None::<String>),
         ))
         .execute(conn)?;
     Ok(())
 }
+
+// Record a failed delivery, the callback is abandoned if no next attempt is given
+pub fn callback_failed(
+    callback_id: i64,
+    error: &str,
+    retry_at: Option<NaiveDateTime>,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), Error> {
+    use schema::callbacks::dsl::{
+        attempts, callback_state, last_attempt, last_error, next_attempt,
+    };
+
+    let now = Utc::now().naive_utc();
+    let target = callbacks.find(callback_id);
+    let values = (
+        attempts.eq(attempts + 1),
+        last_attempt.eq(now),
+        last_error.eq(error),
+    );
+    match retry_at {
+        Some(retry_at) => diesel::update(target)
+            .set((values, next_attempt.eq(retry_at)))
+            .execute(conn)?,
+        None => diesel::update(target)
+            .set((values, callback_state.eq(CallbackStateEnum::Failed)))
+            .execute(conn)?,
+    };
+    Ok(())
+}
+
+pub fn get_callback_state(
+    payment_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Option<CallbackStateEnum>, Error> {
+    use schema::callbacks::dsl::{callback_state, id, payment_id as dsl_payment_id};
+
+    let uuid_payment_id = parse_payment_id(payment_id)?;
+    callbacks
+        .filter(dsl_payment_id.eq(uuid_payment_id))
+        .order(id.desc())
+        .select(callback_state)
+        .first(conn)
+        .optional()
+}
+
+// Reserve the next child index of an xpub chain, starting from zero
+pub fn reserve_derivation_index(
+    xpub: &str,
+    path: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<i32, Error> {
+    use schema::derivation_indices::dsl::{next_index, path as dsl_path, xpub as dsl_xpub};
+
+    let new_derivation_index = NewDerivationIndex {
+        xpub,
+        path,
+        next_index: 1,
+    };
+    let reserved = diesel::insert_into(derivation_indices)
+        .values(&new_derivation_index)
+        .on_conflict((dsl_xpub, dsl_path))
+        .do_update()
+        .set(next_index.eq(next_index + 1))
+        .returning(next_index)
+        .get_result::<i32>(conn)?;
+    Ok(reserved - 1)
+}
+
+pub fn add_pooled_addresses(
+    merchant_id: &str,
+    addresses: &[String],
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<usize, Error> {
+    let now = Utc::now().naive_utc();
+    let new_pooled_addresses: Vec<NewPooledAddress> = addresses
+        .iter()
+        .map(|address| NewPooledAddress {
+            address,
+            added: &now,
+            merchant_id,
+        })
+        .collect();
+    diesel::insert_into(address_pool)
+        .values(&new_pooled_addresses)
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+pub fn count_pooled_addresses(
+    merchant_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<i64, Error> {
+    use schema::address_pool::dsl::merchant_id as dsl_merchant_id;
+
+    address_pool
+        .filter(dsl_merchant_id.eq(merchant_id))
+        .count()
+        .get_result(conn)
+}
+
+// Remove and return the oldest address pooled for the merchant, concurrent claims skip
+// locked rows so an address is never handed out twice
+pub fn claim_pooled_address(
+    merchant_id: &str,
+    conn: &PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<Option<String>, Error> {
+    use schema::address_pool::dsl::{added, address, merchant_id as dsl_merchant_id};
+
+    conn.transaction(|| {
+        let claimed = address_pool
+            .select(address)
+            .filter(dsl_merchant_id.eq(merchant_id))
+            .order(added.asc())
+            .for_update()
+            .skip_locked()
+            .first::<String>(conn)
+            .optional()?;
+        if let Some(claimed) = &claimed {
+            diesel::delete(address_pool.find(claimed)).execute(conn)?;
+        }
+        Ok(claimed)
+    })
+}
+
+// Select nothing from every table, failing if a table or column is missing or an enum
+// type or value is unknown to the database
+pub fn check_schema(conn: &PooledConnection<ConnectionManager<PgConnection>>) -> Result<(), Error> {
+    use schema::callbacks::dsl::callback_state;
+
+    payments
+        .filter(dsl::payment_state.eq_any(&PaymentStateEnum::ALL[..]))
+        .limit(0)
+        .load::<PaymentRow>(conn)?;
+    payment_outputs.limit(0).load::<PaymentOutputRow>(conn)?;
+    received_amounts
+        .limit(0)
+        .load::<(Uuid, Vec<u8>, i64)>(conn)?;
+    payment_transactions.limit(0).load::<(Uuid, String)>(conn)?;
+    payment_inputs.limit(0).load::<PaymentInputRow>(conn)?;
+    refund_outputs.limit(0).load::<RefundOutputRow>(conn)?;
+    callbacks
+        .filter(callback_state.eq_any(&CallbackStateEnum::ALL[..]))
+        .limit(0)
+        .load::<CallbackRow>(conn)?;
+    derivation_indices
+        .limit(0)
+        .load::<(String, String, i32)>(conn)?;
+    address_pool
+        .limit(0)
+        .load::<(String, NaiveDateTime, String)>(conn)?;
+    merchants.limit(0).load::<MerchantRow>(conn)?;
+    Ok(())
+}
+
+pub struct PostgresStore {
+    pool: ConnPool,
+}
+
+impl PostgresStore {
+    pub fn new(url: &str) -> Result<Self, StoreError> {
+        let manager = ConnectionManager::<PgConnection>::new(url);
+        let pool = Pool::builder()
+            .build(manager)
+            .map_err(|_| StoreError::Connection)?;
+        Ok(PostgresStore { pool })
+    }
+
+    fn conn(&self) -> Result<PooledConnection<ConnectionManager<PgConnection>>, StoreError> {
+        self.pool.get().map_err(|_| StoreError::Connection)
+    }
+}
+
+impl PaymentStore for PostgresStore {
+    fn upsert_merchant(&self, merchant: &MerchantRow) -> Result<(), StoreError> {
+        Ok(upsert_merchant(merchant, &self.conn()?)?)
+    }
+
+    fn get_merchants(&self) -> Result<Vec<MerchantRow>, StoreError> {
+        Ok(get_merchants(&self.conn()?)?)
+    }
+
+    fn revoke_api_keys(&self, configured_ids: &[String]) -> Result<(), StoreError> {
+        Ok(revoke_api_keys(configured_ids, &self.conn()?)?)
+    }
+
+    fn add_payment(
+        &self,
+        payment_details: &PaymentDetails,
+        id: &Uuid,
+        merchant_id: &str,
+        address: &str,
+        amount: i64,
+        req_memo: Option<&str>,
+        ack_memo: Option<&str>,
+        tokenize: bool,
+        tx_data: Option<&[u8]>,
+        callback_url: Option<&str>,
+        outputs: &[Output],
+        payment_request: &[u8],
+        fiat_amount: Option<&FiatAmount>,
+        min_fee_rate: Option<f64>,
+    ) -> Result<Uuid, StoreError> {
+        Ok(add_payment(
+            payment_details,
+            id,
+            merchant_id,
+            address,
+            amount,
+            req_memo,
+            ack_memo,
+            tokenize,
+            tx_data,
+            callback_url,
+            outputs,
+            payment_request,
+            fiat_amount,
+            min_fee_rate,
+            &self.conn()?,
+        )?)
+    }
+
+    fn get_payment_outputs(&self, payment_id: &str) -> Result<Vec<PaymentOutputRow>, StoreError> {
+        Ok(get_payment_outputs(payment_id, &self.conn()?)?)
+    }
+
+    fn get_payment(&self, payment_id: &str) -> Result<PaymentRow, StoreError> {
+        Ok(get_payment(payment_id, &self.conn()?)?)
+    }
+
+    fn record_rejection(
+        &self,
+        payment_id: &str,
+        rejection_reason: &str,
+        sent_tx_ids: &[String],
+    ) -> Result<(), StoreError> {
+        Ok(record_rejection(
+            payment_id,
+            rejection_reason,
+            sent_tx_ids,
+            &self.conn()?,
+        )?)
+    }
+
+    fn expire_payments(&self) -> Result<usize, StoreError> {
+        Ok(expire_payments(&self.conn()?)?)
+    }
+
+    fn accept_payment(
+        &self,
+        payment_id: &str,
+        previous_amount_received: i64,
+        tally: &PaymentTally,
+        tx_ids: &[String],
+        inputs: &[SpentOutput],
+        refund_to: &[Output],
+        payment_ack: &[u8],
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError> {
+        Ok(accept_payment(
+            payment_id,
+            previous_amount_received,
+            tally,
+            tx_ids,
+            inputs,
+            refund_to,
+            payment_ack,
+            callback,
+            &self.conn()?,
+        )?)
+    }
+
+    fn get_received_amounts(&self, payment_id: &str) -> Result<Vec<ReceivedAmount>, StoreError> {
+        Ok(get_received_amounts(payment_id, &self.conn()?)?)
+    }
+
+    fn get_payment_tx_ids(&self, payment_id: &str) -> Result<Vec<String>, StoreError> {
+        Ok(get_payment_tx_ids(payment_id, &self.conn()?)?)
+    }
+
+    fn get_unconfirmed_txs(&self) -> Result<Vec<(Uuid, String)>, StoreError> {
+        Ok(get_unconfirmed_txs(&self.conn()?)?)
+    }
+
+    fn get_watched_inputs(&self) -> Result<Vec<PaymentInputRow>, StoreError> {
+        Ok(get_watched_inputs(&self.conn()?)?)
+    }
+
+    fn mark_double_spent(
+        &self,
+        payment_id: &Uuid,
+        double_spend_tx_id: Option<&str>,
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError> {
+        Ok(mark_double_spent(
+            payment_id,
+            double_spend_tx_id,
+            callback,
+            &self.conn()?,
+        )?)
+    }
+
+    fn confirm_payment(
+        &self,
+        payment_id: &Uuid,
+        block_hash: &str,
+        block_height: i32,
+    ) -> Result<(), StoreError> {
+        Ok(confirm_payment(
+            payment_id,
+            block_hash,
+            block_height,
+            &self.conn()?,
+        )?)
+    }
+
+    fn get_refund_outputs(&self, payment_id: &str) -> Result<Vec<RefundOutputRow>, StoreError> {
+        Ok(get_refund_outputs(payment_id, &self.conn()?)?)
+    }
+
+    fn claim_refund(&self, payment_id: &str) -> Result<bool, StoreError> {
+        Ok(claim_refund(payment_id, &self.conn()?)?)
+    }
+
+    fn release_refund(&self, payment_id: &str) -> Result<(), StoreError> {
+        Ok(release_refund(payment_id, &self.conn()?)?)
+    }
+
+    fn set_refund_tx_id(&self, payment_id: &str, tx_id: &str) -> Result<(), StoreError> {
+        Ok(set_refund_tx_id(payment_id, tx_id, &self.conn()?)?)
+    }
+
+    fn get_due_callbacks(&self, limit: i64) -> Result<Vec<CallbackRow>, StoreError> {
+        Ok(get_due_callbacks(limit, &self.conn()?)?)
+    }
+
+    fn callback_delivered(&self, callback_id: i64) -> Result<(), StoreError> {
+        Ok(callback_delivered(callback_id, &self.conn()?)?)
+    }
+
+    fn callback_failed(
+        &self,
+        callback_id: i64,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<(), StoreError> {
+        Ok(callback_failed(
+            callback_id,
+            error,
+            retry_at,
+            &self.conn()?,
+        )?)
+    }
+
+    fn get_callback_state(
+        &self,
+        payment_id: &str,
+    ) -> Result<Option<CallbackStateEnum>, StoreError> {
+        Ok(get_callback_state(payment_id, &self.conn()?)?)
+    }
+
+    fn reserve_derivation_index(&self, xpub: &str, path: &str) -> Result<i32, StoreError> {
+        Ok(reserve_derivation_index(xpub, path, &self.conn()?)?)
+    }
+
+    fn add_pooled_addresses(
+        &self,
+        merchant_id: &str,
+        addresses: &[String],
+    ) -> Result<usize, StoreError> {
+        Ok(add_pooled_addresses(merchant_id, addresses, &self.conn()?)?)
+    }
+
+    fn count_pooled_addresses(&self, merchant_id: &str) -> Result<i64, StoreError> {
+        Ok(count_pooled_addresses(merchant_id, &self.conn()?)?)
+    }
+
+    fn claim_pooled_address(&self, merchant_id: &str) -> Result<Option<String>, StoreError> {
+        Ok(claim_pooled_address(merchant_id, &self.conn()?)?)
+    }
+
+    fn run_migrations(&self) -> Result<(), StoreError> {
+        embedded_migrations::run(&self.conn()?).map_err(StoreError::Migration)
+    }
+
+    fn check_schema(&self) -> Result<(), StoreError> {
+        let conn = self.conn()?;
+        check_schema(&conn).map_err(StoreError::Schema)?;
+
+        let columns = diesel::sql_query(
+            "SELECT table_name::text AS table_name, column_name::text AS column_name, \
+             is_nullable = 'YES' AS nullable \
+             FROM information_schema.columns WHERE table_schema = 'public'",
+        )
+        .load::<ColumnInfo>(&conn)
+        .map_err(StoreError::Schema)?;
+        check_nullability(&columns)
+    }
+}
@@ -1,27 +1,13 @@
-use super::schema::{payments, PaymentStateEnum};
+use super::schema::{
+    address_pool, callbacks, derivation_indices, merchants, payment_inputs, payment_outputs,
+    payment_transactions, payments, received_amounts, refund_outputs, CallbackStateEnum,
+    PaymentStateEnum,
+};
+use crate::sql::models::MerchantRow;
 use chrono::NaiveDateTime;
 use diesel::*;
 use uuid::Uuid;
 
-#[derive(PartialEq, Debug, Serialize, Queryable, Deserialize)]
-pub struct PaymentRow {
-    pub id: Uuid,
-    pub issue_time: NaiveDateTime,
-    pub amount: i64,
-    pub address: String,
-    pub expiry_time: Option<NaiveDateTime>,
-    pub req_memo: Option<String>,
-    pub merchant_data: Option<Vec<u8>>,
-    pub ack_memo: Option<String>,
-    pub tokenize: bool,
-    pub tx_data: Option<Vec<u8>>,
-    pub payment_state: PaymentStateEnum,
-    pub payment_time: Option<NaiveDateTime>,
-    pub tx_id: Option<String>,
-    pub refund_to: Option<String>,
-    pub callback_url: Option<String>,
-}
-
 #[derive(Insertable, Queryable, Debug, PartialEq)]
 #[table_name = "payments"]
 pub struct NewPayment<'a> {
@@ -37,4 +23,114 @@ pub struct NewPayment<'a> {
     pub payment_state: &'a PaymentStateEnum,
     pub tokenize: bool,
     pub callback_url: Option<&'a str>,
+    pub payment_request: &'a [u8],
+    pub fiat_currency: Option<&'a str>,
+    pub fiat_amount: Option<f64>,
+    pub exchange_rate: Option<f64>,
+    pub min_fee_rate: Option<f64>,
+    pub amount_due: i64,
+    pub merchant_id: &'a str,
+}
+
+#[derive(Insertable, Queryable, Debug, PartialEq)]
+#[table_name = "payment_inputs"]
+pub struct NewPaymentInput<'a> {
+    pub payment_id: &'a Uuid,
+    pub tx_id: &'a str,
+    pub prev_tx_id: &'a str,
+    pub prev_vout: i32,
+}
+
+#[derive(Insertable, Queryable, Debug, PartialEq)]
+#[table_name = "payment_outputs"]
+pub struct NewPaymentOutput<'a> {
+    pub payment_id: &'a Uuid,
+    pub idx: i32,
+    pub amount: i64,
+    pub script: &'a [u8],
+}
+
+#[derive(Insertable, Queryable, Debug, PartialEq)]
+#[table_name = "received_amounts"]
+pub struct NewReceivedAmount<'a> {
+    pub payment_id: &'a Uuid,
+    pub script: &'a [u8],
+    pub amount: i64,
+}
+
+#[derive(Insertable, Queryable, Debug, PartialEq)]
+#[table_name = "payment_transactions"]
+pub struct NewPaymentTransaction<'a> {
+    pub payment_id: &'a Uuid,
+    pub tx_id: &'a str,
+}
+
+#[derive(Insertable, Queryable, Debug, PartialEq)]
+#[table_name = "refund_outputs"]
+pub struct NewRefundOutput<'a> {
+    pub payment_id: &'a Uuid,
+    pub idx: i32,
+    pub amount: Option<i64>,
+    pub script: &'a [u8],
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[table_name = "callbacks"]
+pub struct NewCallback<'a> {
+    pub payment_id: &'a Uuid,
+    pub url: &'a str,
+    pub payload: &'a [u8],
+    pub callback_state: &'a CallbackStateEnum,
+    pub next_attempt: &'a NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[table_name = "derivation_indices"]
+pub struct NewDerivationIndex<'a> {
+    pub xpub: &'a str,
+    pub path: &'a str,
+    pub next_index: i32,
+}
+
+#[derive(Insertable, Debug, PartialEq)]
+#[table_name = "address_pool"]
+pub struct NewPooledAddress<'a> {
+    pub address: &'a str,
+    pub added: &'a NaiveDateTime,
+    pub merchant_id: &'a str,
+}
+
+#[derive(Insertable, AsChangeset, Debug, PartialEq)]
+#[table_name = "merchants"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewMerchant<'a> {
+    pub id: &'a str,
+    pub api_key_hash: Option<&'a str>,
+    pub secret: &'a str,
+    pub address_source: &'a str,
+    pub wallet: Option<&'a str>,
+    pub xpub: Option<&'a str>,
+    pub derivation_path: &'a str,
+    pub req_memo: Option<&'a str>,
+    pub ack_memo: Option<&'a str>,
+    pub expiry: Option<i64>,
+    pub callback_url: Option<&'a str>,
+}
+
+impl<'a> From<&'a MerchantRow> for NewMerchant<'a> {
+    fn from(merchant: &'a MerchantRow) -> Self {
+        NewMerchant {
+            id: &merchant.id,
+            api_key_hash: merchant.api_key_hash.as_ref().map(String::as_str),
+            secret: &merchant.secret,
+            address_source: &merchant.address_source,
+            wallet: merchant.wallet.as_ref().map(String::as_str),
+            xpub: merchant.xpub.as_ref().map(String::as_str),
+            derivation_path: &merchant.derivation_path,
+            req_memo: merchant.req_memo.as_ref().map(String::as_str),
+            ack_memo: merchant.ack_memo.as_ref().map(String::as_str),
+            expiry: merchant.expiry,
+            callback_url: merchant.callback_url.as_ref().map(String::as_str),
+        }
+    }
 }
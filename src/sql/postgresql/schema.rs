@@ -7,17 +7,20 @@ use diesel::{
 use std::io::Write;
 
 #[derive(SqlType)]
-#[postgres(type_name = "payment_state_type")]
+#[postgres(type_name = "payment_state_enum")]
 pub struct PaymentStateType;
 
 #[derive(Debug, Copy, Clone, PartialEq, FromSqlRow, AsExpression, Deserialize, Serialize)]
 #[sql_type = "PaymentStateType"]
+#[serde(rename_all = "snake_case")]
 pub enum PaymentStateEnum {
     Pending,
     Received,
     Confirmed,
     Rejected,
     Expired,
+    DoubleSpent,
+    Underpaid,
 }
 
 impl ToSql<PaymentStateType, Pg> for PaymentStateEnum {
@@ -28,6 +31,8 @@ impl ToSql<PaymentStateType, Pg> for PaymentStateEnum {
             Self::Confirmed => out.write_all(b"confirmed")?,
             Self::Rejected => out.write_all(b"rejected")?,
             Self::Expired => out.write_all(b"expired")?,
+            Self::DoubleSpent => out.write_all(b"double_spent")?,
+            Self::Underpaid => out.write_all(b"underpaid")?,
         }
         Ok(IsNull::No)
     }
@@ -41,6 +46,43 @@ impl FromSql<PaymentStateType, Pg> for PaymentStateEnum {
             b"confirmed" => Ok(Self::Confirmed),
             b"rejected" => Ok(Self::Rejected),
             b"expired" => Ok(Self::Expired),
+            b"double_spent" => Ok(Self::DoubleSpent),
+            b"underpaid" => Ok(Self::Underpaid),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+#[derive(SqlType)]
+#[postgres(type_name = "callback_state_enum")]
+pub struct CallbackStateType;
+
+#[derive(Debug, Copy, Clone, PartialEq, FromSqlRow, AsExpression, Deserialize, Serialize)]
+#[sql_type = "CallbackStateType"]
+#[serde(rename_all = "snake_case")]
+pub enum CallbackStateEnum {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl ToSql<CallbackStateType, Pg> for CallbackStateEnum {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        match *self {
+            Self::Pending => out.write_all(b"pending")?,
+            Self::Delivered => out.write_all(b"delivered")?,
+            Self::Failed => out.write_all(b"failed")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<CallbackStateType, Pg> for CallbackStateEnum {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"pending" => Ok(Self::Pending),
+            b"delivered" => Ok(Self::Delivered),
+            b"failed" => Ok(Self::Failed),
             _ => Err("Unrecognized enum variant".into()),
         }
     }
@@ -59,11 +101,136 @@ table! {
         merchant_data -> Nullable<Blob>, // Merchant data
         ack_memo -> Nullable<Text>, // Memo to be included in the request
         tokenize -> Bool, // Assume merchant data is URL and append token
-        tx_data -> Nullable<Blob>, // Data required inside OP_RETURN
+        tx_data -> Nullable<Blob>, // Encoded pushes required after OP_RETURN
         payment_state -> PaymentStateType, // Payment state
         payment_time -> Nullable<Timestamp>, // Time payment was completed
-        tx_id -> Nullable<Text>, // Transaction ID of the payment
-        refund_to -> Nullable<Text>, // Refund address
         callback_url -> Nullable<Text>, // Callback URL
+        refund_tx_id -> Nullable<Text>, // Transaction ID of the refund
+        block_hash -> Nullable<Text>, // Hash of the block confirming the payment
+        block_height -> Nullable<Integer>, // Height of the block confirming the payment
+        payment_ack -> Nullable<Blob>, // Encoded PaymentACK issued for the payment
+        payment_request -> Nullable<Blob>, // Encoded PaymentRequest served to wallets
+        fiat_currency -> Nullable<Text>, // Fiat currency the invoice was denominated in
+        fiat_amount -> Nullable<Double>, // Amount in the fiat currency
+        exchange_rate -> Nullable<Double>, // Exchange rate used to derive the amount
+        rejection_reason -> Nullable<Text>, // Reason the node last refused a payment tx
+        min_fee_rate -> Nullable<Double>, // Minimum fee rate of payment txs in sats per byte
+        double_spend_tx_id -> Nullable<Text>, // Transaction conflicting with the payment
+        amount_received -> BigInt, // Total paid towards the invoice
+        amount_due -> BigInt, // Amount still to be paid
+        overpaid_amount -> BigInt, // Amount paid in excess of the invoice, owed as a refund
+        merchant_id -> Text, // Merchant the invoice belongs to
     }
 }
+
+table! {
+    payment_transactions (payment_id, tx_id) {
+        payment_id -> Uuid, // Payment ID
+        tx_id -> Text, // Transaction ID of one of the payment transactions
+    }
+}
+
+table! {
+    payment_inputs (payment_id, prev_tx_id, prev_vout) {
+        payment_id -> Uuid, // Payment ID
+        tx_id -> Text, // Payment transaction spending the output
+        prev_tx_id -> Text, // Transaction ID of the spent output
+        prev_vout -> Integer, // Index of the spent output
+    }
+}
+
+table! {
+    payment_outputs (payment_id, idx) {
+        payment_id -> Uuid, // Payment ID
+        idx -> Integer, // Position within InvoiceRequest.outputs
+        amount -> BigInt, // Amount to be paid to the script
+        script -> Blob, // Output script
+    }
+}
+
+table! {
+    received_amounts (payment_id, script) {
+        payment_id -> Uuid, // Payment ID
+        script -> Blob, // Expected output script
+        amount -> BigInt, // Total received by the script
+    }
+}
+
+table! {
+    refund_outputs (payment_id, idx) {
+        payment_id -> Uuid, // Payment ID
+        idx -> Integer, // Position within Payment.refund_to
+        amount -> Nullable<BigInt>, // Amount to be refunded to the script
+        script -> Blob, // Refund output script
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use super::CallbackStateType;
+    callbacks (id) {
+        id -> BigInt, // Callback ID
+        payment_id -> Uuid, // Payment ID
+        url -> Text, // Callback URL
+        payload -> Blob, // Encoded CallbackPayload
+        callback_state -> CallbackStateType, // Delivery state
+        attempts -> Integer, // Number of delivery attempts
+        next_attempt -> Timestamp, // Time of the next delivery attempt
+        last_attempt -> Nullable<Timestamp>, // Time of the last delivery attempt
+        last_error -> Nullable<Text>, // Error of the last delivery attempt
+    }
+}
+
+table! {
+    derivation_indices (xpub, path) {
+        xpub -> Text, // Extended public key addresses are derived from
+        path -> Text, // Derivation path of the address chain below the xpub
+        next_index -> Integer, // Next unused child index
+    }
+}
+
+table! {
+    address_pool (address) {
+        address -> Text, // Unused address awaiting an invoice
+        added -> Timestamp, // Time the address was fetched
+        merchant_id -> Text, // Merchant the address was fetched for
+    }
+}
+
+table! {
+    merchants (id) {
+        id -> Text, // Merchant ID
+        api_key_hash -> Nullable<Text>, // SHA256 of the key authenticating private API calls
+        secret -> Text, // Secret tokens are generated with
+        address_source -> Text, // Source of invoice addresses
+        wallet -> Nullable<Text>, // Node wallet addresses are fetched from and refunds sent by
+        xpub -> Nullable<Text>, // Extended public key addresses are derived from
+        derivation_path -> Text, // Derivation path of the address chain below the xpub
+        req_memo -> Nullable<Text>, // Default memo to be included in the request
+        ack_memo -> Nullable<Text>, // Default memo to be included in the PaymentACK
+        expiry -> Nullable<BigInt>, // Default validity of invoices in seconds
+        callback_url -> Nullable<Text>, // Default callback URL
+    }
+}
+
+joinable!(payment_inputs -> payments (payment_id));
+joinable!(payment_outputs -> payments (payment_id));
+joinable!(received_amounts -> payments (payment_id));
+joinable!(payment_transactions -> payments (payment_id));
+joinable!(refund_outputs -> payments (payment_id));
+joinable!(callbacks -> payments (payment_id));
+joinable!(payments -> merchants (merchant_id));
+joinable!(address_pool -> merchants (merchant_id));
+
+allow_tables_to_appear_in_same_query!(
+    payments,
+    payment_inputs,
+    payment_outputs,
+    received_amounts,
+    payment_transactions,
+    refund_outputs,
+    callbacks,
+    derivation_indices,
+    address_pool,
+    merchants
+);
@@ -0,0 +1,729 @@
+pub mod models;
+pub mod schema;
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool, PooledConnection},
+    sqlite::SqliteConnection,
+};
+use uuid::Uuid;
+
+use crate::{
+    bitcoin::SpentOutput,
+    models::{Output, PaymentDetails},
+    rates::FiatAmount,
+    sql::{
+        check_nullability,
+        errors::StoreError,
+        models::{
+            CallbackRow, CallbackStateEnum, ColumnInfo, MerchantRow, PaymentInputRow,
+            PaymentOutputRow, PaymentRow, PaymentStateEnum, PaymentTally, ReceivedAmount,
+            RefundOutputRow,
+        },
+        PaymentStore, REFUND_PENDING,
+    },
+};
+
+use models::{
+    NewCallback, NewDerivationIndex, NewMerchant, NewPayment, NewPaymentInput, NewPaymentOutput,
+    NewPaymentTransaction, NewPooledAddress, NewReceivedAmount, NewRefundOutput, SqliteCallbackRow,
+    SqlitePaymentInputRow, SqlitePaymentOutputRow, SqlitePaymentRow, SqliteRefundOutputRow,
+};
+use schema::{
+    address_pool::dsl::address_pool,
+    callbacks::dsl::callbacks,
+    derivation_indices::dsl::derivation_indices,
+    merchants::dsl::merchants,
+    payment_inputs::dsl::payment_inputs,
+    payment_outputs::dsl::payment_outputs,
+    payment_transactions::dsl::payment_transactions,
+    payments::dsl::{self, payments},
+    received_amounts::dsl::received_amounts,
+    refund_outputs::dsl::refund_outputs,
+};
+
+pub type SqlitePool = Pool<ConnectionManager<SqliteConnection>>;
+
+embed_migrations!("migrations_sqlite");
+
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    // Open the database at the path, created empty if missing
+    pub fn new(path: &str) -> Result<Self, StoreError> {
+        // SQLite permits a single writer, a single long-lived connection keeps writes
+        // serialised and in-memory databases alive
+        let manager = ConnectionManager::<SqliteConnection>::new(path);
+        let pool = Pool::builder()
+            .max_size(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .build(manager)
+            .map_err(|_| StoreError::Connection)?;
+        Ok(SqliteStore { pool })
+    }
+
+    fn conn(&self) -> Result<PooledConnection<ConnectionManager<SqliteConnection>>, StoreError> {
+        self.pool.get().map_err(|_| StoreError::Connection)
+    }
+}
+
+fn queue_callback(
+    payment_id: &str,
+    url: &str,
+    payload: &[u8],
+    next_attempt: &NaiveDateTime,
+    conn: &SqliteConnection,
+) -> Result<(), StoreError> {
+    let new_callback = NewCallback {
+        payment_id,
+        url,
+        payload,
+        callback_state: CallbackStateEnum::Pending.as_str(),
+        next_attempt,
+    };
+    diesel::insert_into(callbacks)
+        .values(&new_callback)
+        .execute(conn)?;
+    Ok(())
+}
+
+impl PaymentStore for SqliteStore {
+    fn upsert_merchant(&self, merchant: &MerchantRow) -> Result<(), StoreError> {
+        let new_merchant = NewMerchant::from(merchant);
+        let conn = self.conn()?;
+        conn.transaction::<_, StoreError, _>(|| {
+            // Replacing the row would orphan the merchant's invoices
+            let n_updated = diesel::update(merchants.find(&merchant.id))
+                .set(&new_merchant)
+                .execute(&conn)?;
+            if n_updated == 0 {
+                diesel::insert_into(merchants)
+                    .values(&new_merchant)
+                    .execute(&conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get_merchants(&self) -> Result<Vec<MerchantRow>, StoreError> {
+        Ok(merchants.load(&self.conn()?)?)
+    }
+
+    fn revoke_api_keys(&self, configured_ids: &[String]) -> Result<(), StoreError> {
+        use schema::merchants::dsl::{api_key_hash, id};
+
+        diesel::update(merchants.filter(diesel::dsl::not(id.eq_any(configured_ids))))
+            .set(api_key_hash.eq(None::<String>))
+            .execute(&self.conn()?)?;
+        Ok(())
+    }
+
+    fn add_payment(
+        &self,
+        payment_details: &PaymentDetails,
+        id: &Uuid,
+        merchant_id: &str,
+        address: &str,
+        amount: i64,
+        req_memo: Option<&str>,
+        ack_memo: Option<&str>,
+        tokenize: bool,
+        tx_data: Option<&[u8]>,
+        callback_url: Option<&str>,
+        outputs: &[Output],
+        payment_request: &[u8],
+        fiat_amount: Option<&FiatAmount>,
+        min_fee_rate: Option<f64>,
+    ) -> Result<Uuid, StoreError> {
+        let str_id = id.to_string();
+        let issue_time = &NaiveDateTime::from_timestamp(payment_details.time as i64, 0);
+        let expiry_time = payment_details
+            .expires
+            .map(|value| NaiveDateTime::from_timestamp(value as i64, 0));
+        let amount_due = amount
+            + outputs
+                .iter()
+                .map(|output| output.amount.unwrap_or(0) as i64)
+                .sum::<i64>();
+
+        // Construct row
+        let new_payment = NewPayment {
+            id: &str_id,
+            issue_time,
+            amount,
+            address,
+            expiry_time: expiry_time.as_ref(),
+            req_memo,
+            merchant_data: payment_details
+                .merchant_data
+                .as_ref()
+                .map(|value| &value[..]),
+            ack_memo,
+            tokenize,
+            tx_data,
+            payment_state: PaymentStateEnum::Pending.as_str(),
+            callback_url,
+            payment_request,
+            fiat_currency: fiat_amount.map(|fiat_amount| &fiat_amount.currency[..]),
+            fiat_amount: fiat_amount.map(|fiat_amount| fiat_amount.amount),
+            exchange_rate: fiat_amount.map(|fiat_amount| fiat_amount.rate),
+            min_fee_rate,
+            amount_due,
+            merchant_id,
+        };
+        let new_payment_outputs: Vec<NewPaymentOutput> = outputs
+            .iter()
+            .enumerate()
+            .map(|(idx, output)| NewPaymentOutput {
+                payment_id: &str_id,
+                idx: idx as i32,
+                amount: output.amount.unwrap_or(0) as i64,
+                script: &output.script[..],
+            })
+            .collect();
+        let conn = self.conn()?;
+        conn.transaction::<_, StoreError, _>(|| {
+            diesel::insert_into(payments)
+                .values(&new_payment)
+                .execute(&conn)?;
+            diesel::insert_into(payment_outputs)
+                .values(&new_payment_outputs)
+                .execute(&conn)?;
+            Ok(*id)
+        })
+    }
+
+    fn get_payment_outputs(&self, payment_id: &str) -> Result<Vec<PaymentOutputRow>, StoreError> {
+        use schema::payment_outputs::dsl::{idx, payment_id as dsl_payment_id};
+
+        payment_outputs
+            .filter(dsl_payment_id.eq(payment_id))
+            .order(idx.asc())
+            .load::<SqlitePaymentOutputRow>(&self.conn()?)?
+            .into_iter()
+            .map(SqlitePaymentOutputRow::into_row)
+            .collect()
+    }
+
+    fn get_payment(&self, payment_id: &str) -> Result<PaymentRow, StoreError> {
+        payments
+            .find(payment_id)
+            .first::<SqlitePaymentRow>(&self.conn()?)?
+            .into_row()
+    }
+
+    fn record_rejection(
+        &self,
+        payment_id: &str,
+        rejection_reason: &str,
+        sent_tx_ids: &[String],
+    ) -> Result<(), StoreError> {
+        let new_payment_txs: Vec<NewPaymentTransaction> = sent_tx_ids
+            .iter()
+            .map(|tx_id| NewPaymentTransaction {
+                payment_id,
+                tx_id: tx_id.as_str(),
+            })
+            .collect();
+        let conn = self.conn()?;
+        conn.transaction::<_, StoreError, _>(|| {
+            diesel::update(payments.find(payment_id))
+                .set(dsl::rejection_reason.eq(rejection_reason))
+                .execute(&conn)?;
+            diesel::insert_or_ignore_into(payment_transactions)
+                .values(&new_payment_txs)
+                .execute(&conn)?;
+            Ok(())
+        })
+    }
+
+    fn expire_payments(&self) -> Result<usize, StoreError> {
+        let now = Utc::now().naive_utc();
+        let n_expired = diesel::update(
+            payments
+                .filter(dsl::payment_state.eq_any(vec![
+                    PaymentStateEnum::Pending.as_str(),
+                    PaymentStateEnum::Underpaid.as_str(),
+                ]))
+                .filter(dsl::expiry_time.lt(now)),
+        )
+        .set(dsl::payment_state.eq(PaymentStateEnum::Expired.as_str()))
+        .execute(&self.conn()?)?;
+        Ok(n_expired)
+    }
+
+    fn accept_payment(
+        &self,
+        payment_id: &str,
+        previous_amount_received: i64,
+        tally: &PaymentTally,
+        tx_ids: &[String],
+        inputs: &[SpentOutput],
+        refund_to: &[Output],
+        payment_ack: &[u8],
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError> {
+        let gen_accept_time = Utc::now().naive_utc();
+        let new_payment_txs: Vec<NewPaymentTransaction> = tx_ids
+            .iter()
+            .map(|tx_id| NewPaymentTransaction {
+                payment_id,
+                tx_id: tx_id.as_str(),
+            })
+            .collect();
+        let new_payment_inputs: Vec<NewPaymentInput> = inputs
+            .iter()
+            .map(|input| NewPaymentInput {
+                payment_id,
+                tx_id: &input.tx_id,
+                prev_tx_id: &input.prev_tx_id,
+                prev_vout: input.prev_vout as i32,
+            })
+            .collect();
+        let new_refund_outputs: Vec<NewRefundOutput> = refund_to
+            .iter()
+            .enumerate()
+            .map(|(idx, output)| NewRefundOutput {
+                payment_id,
+                idx: idx as i32,
+                amount: output.amount.map(|amount| amount as i64),
+                script: &output.script[..],
+            })
+            .collect();
+        let new_received_amounts: Vec<NewReceivedAmount> = tally
+            .received_amounts
+            .iter()
+            .map(|received_amount| NewReceivedAmount {
+                payment_id,
+                script: &received_amount.script[..],
+                amount: received_amount.amount,
+            })
+            .collect();
+        let payment_state = if tally.amount_due == 0 {
+            PaymentStateEnum::Received
+        } else {
+            PaymentStateEnum::Underpaid
+        };
+        let conn = self.conn()?;
+        conn.transaction::<_, StoreError, _>(|| {
+            // Only pending or underpaid payments may be accepted, and only if no other payment
+            // was counted towards them in the meantime
+            let updated = diesel::update(
+                payments
+                    .find(payment_id)
+                    .filter(dsl::payment_state.eq_any(vec![
+                        PaymentStateEnum::Pending.as_str(),
+                        PaymentStateEnum::Underpaid.as_str(),
+                    ]))
+                    .filter(dsl::amount_received.eq(previous_amount_received)),
+            )
+            .set((
+                dsl::payment_state.eq(payment_state.as_str()),
+                dsl::payment_time.eq(gen_accept_time),
+                dsl::payment_ack.eq(payment_ack),
+                dsl::amount_received.eq(tally.amount_received),
+                dsl::amount_due.eq(tally.amount_due),
+                dsl::overpaid_amount.eq(tally.overpaid_amount),
+            ))
+            .execute(&conn)?;
+            if updated == 0 {
+                return Ok(false);
+            }
+
+            // Refunds go to the outputs given with the latest payment
+            diesel::delete(
+                refund_outputs.filter(schema::refund_outputs::dsl::payment_id.eq(payment_id)),
+            )
+            .execute(&conn)?;
+
+            // The totals of each script replace those of the previous payment
+            diesel::delete(
+                received_amounts.filter(schema::received_amounts::dsl::payment_id.eq(payment_id)),
+            )
+            .execute(&conn)?;
+            diesel::insert_into(received_amounts)
+                .values(&new_received_amounts)
+                .execute(&conn)?;
+
+            // Txs broadcast by an earlier, partially sent submission may already be recorded
+            diesel::insert_or_ignore_into(payment_transactions)
+                .values(&new_payment_txs)
+                .execute(&conn)?;
+            diesel::insert_into(payment_inputs)
+                .values(&new_payment_inputs)
+                .execute(&conn)?;
+            diesel::insert_into(refund_outputs)
+                .values(&new_refund_outputs)
+                .execute(&conn)?;
+            if let Some((url, payload)) = callback {
+                queue_callback(payment_id, url, payload, &gen_accept_time, &conn)?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn get_received_amounts(&self, payment_id: &str) -> Result<Vec<ReceivedAmount>, StoreError> {
+        use schema::received_amounts::dsl::{amount, payment_id as dsl_payment_id, script};
+
+        Ok(received_amounts
+            .filter(dsl_payment_id.eq(payment_id))
+            .select((script, amount))
+            .load(&self.conn()?)?)
+    }
+
+    fn get_payment_tx_ids(&self, payment_id: &str) -> Result<Vec<String>, StoreError> {
+        use schema::payment_transactions::dsl::{payment_id as dsl_payment_id, tx_id as dsl_tx_id};
+
+        Ok(payment_transactions
+            .filter(dsl_payment_id.eq(payment_id))
+            .select(dsl_tx_id)
+            .load(&self.conn()?)?)
+    }
+
+    fn get_unconfirmed_txs(&self) -> Result<Vec<(Uuid, String)>, StoreError> {
+        use schema::payment_transactions::dsl::tx_id;
+
+        payments
+            .inner_join(payment_transactions)
+            .filter(dsl::payment_state.eq_any(vec![
+                PaymentStateEnum::Received.as_str(),
+                PaymentStateEnum::DoubleSpent.as_str(),
+            ]))
+            .select((dsl::id, tx_id))
+            .load::<(String, String)>(&self.conn()?)?
+            .into_iter()
+            .map(|(payment_id, tx_id)| {
+                let payment_id = Uuid::parse_str(&payment_id).map_err(|_| StoreError::Corrupt)?;
+                Ok((payment_id, tx_id))
+            })
+            .collect()
+    }
+
+    fn get_watched_inputs(&self) -> Result<Vec<PaymentInputRow>, StoreError> {
+        use schema::payment_inputs::all_columns;
+
+        payment_inputs
+            .inner_join(payments)
+            .filter(dsl::payment_state.eq_any(vec![
+                PaymentStateEnum::Received.as_str(),
+                PaymentStateEnum::Underpaid.as_str(),
+            ]))
+            .select(all_columns)
+            .load::<SqlitePaymentInputRow>(&self.conn()?)?
+            .into_iter()
+            .map(SqlitePaymentInputRow::into_row)
+            .collect()
+    }
+
+    fn mark_double_spent(
+        &self,
+        payment_id: &Uuid,
+        double_spend_tx_id: Option<&str>,
+        callback: Option<(&str, &[u8])>,
+    ) -> Result<bool, StoreError> {
+        let str_payment_id = payment_id.to_string();
+        let gen_detect_time = Utc::now().naive_utc();
+        let conn = self.conn()?;
+        conn.transaction::<_, StoreError, _>(|| {
+            // Only unconfirmed payments may be double spent
+            let updated = diesel::update(payments.find(&str_payment_id).filter(
+                dsl::payment_state.eq_any(vec![
+                    PaymentStateEnum::Received.as_str(),
+                    PaymentStateEnum::Underpaid.as_str(),
+                ]),
+            ))
+            .set((
+                dsl::payment_state.eq(PaymentStateEnum::DoubleSpent.as_str()),
+                dsl::double_spend_tx_id.eq(double_spend_tx_id),
+            ))
+            .execute(&conn)?;
+            if updated == 0 {
+                return Ok(false);
+            }
+
+            if let Some((url, payload)) = callback {
+                queue_callback(&str_payment_id, url, payload, &gen_detect_time, &conn)?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn confirm_payment(
+        &self,
+        payment_id: &Uuid,
+        block_hash: &str,
+        block_height: i32,
+    ) -> Result<(), StoreError> {
+        // A double spent payment may still be the one to confirm
+        diesel::update(
+            payments
+                .find(payment_id.to_string())
+                .filter(dsl::payment_state.eq_any(vec![
+                    PaymentStateEnum::Received.as_str(),
+                    PaymentStateEnum::DoubleSpent.as_str(),
+                ])),
+        )
+        .set((
+            dsl::payment_state.eq(PaymentStateEnum::Confirmed.as_str()),
+            dsl::block_hash.eq(block_hash),
+            dsl::block_height.eq(block_height),
+        ))
+        .execute(&self.conn()?)?;
+        Ok(())
+    }
+
+    fn get_refund_outputs(&self, payment_id: &str) -> Result<Vec<RefundOutputRow>, StoreError> {
+        use schema::refund_outputs::dsl::{idx, payment_id as dsl_payment_id};
+
+        refund_outputs
+            .filter(dsl_payment_id.eq(payment_id))
+            .order(idx.asc())
+            .load::<SqliteRefundOutputRow>(&self.conn()?)?
+            .into_iter()
+            .map(SqliteRefundOutputRow::into_row)
+            .collect()
+    }
+
+    fn claim_refund(&self, payment_id: &str) -> Result<bool, StoreError> {
+        let claimed = diesel::update(
+            payments
+                .find(payment_id)
+                .filter(dsl::refund_tx_id.is_null()),
+        )
+        .set(dsl::refund_tx_id.eq(REFUND_PENDING))
+        .execute(&self.conn()?)?;
+        Ok(claimed == 1)
+    }
+
+    fn release_refund(&self, payment_id: &str) -> Result<(), StoreError> {
+        diesel::update(
+            payments
+                .find(payment_id)
+                .filter(dsl::refund_tx_id.eq(REFUND_PENDING)),
+        )
+        .set(dsl::refund_tx_id.eq(None::<String>))
+        .execute(&self.conn()?)?;
+        Ok(())
+    }
+
+    fn set_refund_tx_id(&self, payment_id: &str, tx_id: &str) -> Result<(), StoreError> {
+        diesel::update(payments.find(payment_id))
+            .set(dsl::refund_tx_id.eq(tx_id))
+            .execute(&self.conn()?)?;
+        Ok(())
+    }
+
+    fn get_due_callbacks(&self, limit: i64) -> Result<Vec<CallbackRow>, StoreError> {
+        use schema::callbacks::dsl::{callback_state, next_attempt};
+
+        let now = Utc::now().naive_utc();
+        callbacks
+            .filter(callback_state.eq(CallbackStateEnum::Pending.as_str()))
+            .filter(next_attempt.le(now))
+            .order(next_attempt.asc())
+            .limit(limit)
+            .load::<SqliteCallbackRow>(&self.conn()?)?
+            .into_iter()
+            .map(SqliteCallbackRow::into_row)
+            .collect()
+    }
+
+    fn callback_delivered(&self, callback_id: i64) -> Result<(), StoreError> {
+        use schema::callbacks::dsl::{attempts, callback_state, last_attempt, last_error};
+
+        let now = Utc::now().naive_utc();
+        diesel::update(callbacks.find(callback_id))
+            .set((
+                callback_state.eq(CallbackStateEnum::Delivered.as_str()),
+                attempts.eq(attempts + 1),
+                last_attempt.eq(now),
+                last_error.eq(None::<String>),
+            ))
+            .execute(&self.conn()?)?;
+        Ok(())
+    }
+
+    fn callback_failed(
+        &self,
+        callback_id: i64,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<(), StoreError> {
+        use schema::callbacks::dsl::{
+            attempts, callback_state, last_attempt, last_error, next_attempt,
+        };
+
+        let now = Utc::now().naive_utc();
+        let conn = self.conn()?;
+        let target = callbacks.find(callback_id);
+        let values = (
+            attempts.eq(attempts + 1),
+            last_attempt.eq(now),
+            last_error.eq(error),
+        );
+        match retry_at {
+            Some(retry_at) => diesel::update(target)
+                .set((values, next_attempt.eq(retry_at)))
+                .execute(&conn)?,
+            None => diesel::update(target)
+                .set((
+                    values,
+                    callback_state.eq(CallbackStateEnum::Failed.as_str()),
+                ))
+                .execute(&conn)?,
+        };
+        Ok(())
+    }
+
+    fn get_callback_state(
+        &self,
+        payment_id: &str,
+    ) -> Result<Option<CallbackStateEnum>, StoreError> {
+        use schema::callbacks::dsl::{callback_state, id, payment_id as dsl_payment_id};
+
+        let raw_state: Option<String> = callbacks
+            .filter(dsl_payment_id.eq(payment_id))
+            .order(id.desc())
+            .select(callback_state)
+            .first(&self.conn()?)
+            .optional()?;
+        raw_state
+            .map(|raw_state| raw_state.parse().map_err(|_| StoreError::Corrupt))
+            .transpose()
+    }
+
+    fn reserve_derivation_index(&self, xpub: &str, path: &str) -> Result<i32, StoreError> {
+        use schema::derivation_indices::dsl::{next_index, path as dsl_path, xpub as dsl_xpub};
+
+        let conn = self.conn()?;
+        conn.transaction::<_, StoreError, _>(|| {
+            let target = derivation_indices
+                .filter(dsl_xpub.eq(xpub))
+                .filter(dsl_path.eq(path));
+            let reserved: Option<i32> = target.select(next_index).first(&conn).optional()?;
+            match reserved {
+                Some(reserved) => {
+                    diesel::update(target)
+                        .set(next_index.eq(next_index + 1))
+                        .execute(&conn)?;
+                    Ok(reserved)
+                }
+                None => {
+                    let new_derivation_index = NewDerivationIndex {
+                        xpub,
+                        path,
+                        next_index: 1,
+                    };
+                    diesel::insert_into(derivation_indices)
+                        .values(&new_derivation_index)
+                        .execute(&conn)?;
+                    Ok(0)
+                }
+            }
+        })
+    }
+
+    fn add_pooled_addresses(
+        &self,
+        merchant_id: &str,
+        addresses: &[String],
+    ) -> Result<usize, StoreError> {
+        let now = Utc::now().naive_utc();
+        let new_pooled_addresses: Vec<NewPooledAddress> = addresses
+            .iter()
+            .map(|address| NewPooledAddress {
+                address,
+                added: &now,
+                merchant_id,
+            })
+            .collect();
+        Ok(diesel::insert_or_ignore_into(address_pool)
+            .values(&new_pooled_addresses)
+            .execute(&self.conn()?)?)
+    }
+
+    fn count_pooled_addresses(&self, merchant_id: &str) -> Result<i64, StoreError> {
+        use schema::address_pool::dsl::merchant_id as dsl_merchant_id;
+
+        Ok(address_pool
+            .filter(dsl_merchant_id.eq(merchant_id))
+            .count()
+            .get_result(&self.conn()?)?)
+    }
+
+    fn claim_pooled_address(&self, merchant_id: &str) -> Result<Option<String>, StoreError> {
+        use schema::address_pool::dsl::{added, address, merchant_id as dsl_merchant_id};
+
+        let conn = self.conn()?;
+        conn.transaction::<_, StoreError, _>(|| {
+            let claimed = address_pool
+                .select(address)
+                .filter(dsl_merchant_id.eq(merchant_id))
+                .order(added.asc())
+                .first::<String>(&conn)
+                .optional()?;
+            if let Some(claimed) = &claimed {
+                diesel::delete(address_pool.find(claimed)).execute(&conn)?;
+            }
+            Ok(claimed)
+        })
+    }
+
+    fn run_migrations(&self) -> Result<(), StoreError> {
+        embedded_migrations::run(&self.conn()?).map_err(StoreError::Migration)
+    }
+
+    fn check_schema(&self) -> Result<(), StoreError> {
+        use schema::callbacks::dsl::callback_state;
+
+        let conn = self.conn()?;
+        let probe = || -> QueryResult<()> {
+            let payment_states = PaymentStateEnum::ALL.iter().map(|state| state.as_str());
+            payments
+                .filter(dsl::payment_state.eq_any(payment_states))
+                .limit(0)
+                .load::<SqlitePaymentRow>(&conn)?;
+            payment_outputs
+                .limit(0)
+                .load::<SqlitePaymentOutputRow>(&conn)?;
+            received_amounts
+                .limit(0)
+                .load::<(String, Vec<u8>, i64)>(&conn)?;
+            payment_transactions
+                .limit(0)
+                .load::<(String, String)>(&conn)?;
+            payment_inputs
+                .limit(0)
+                .load::<SqlitePaymentInputRow>(&conn)?;
+            refund_outputs
+                .limit(0)
+                .load::<SqliteRefundOutputRow>(&conn)?;
+            let callback_states = CallbackStateEnum::ALL.iter().map(|state| state.as_str());
+            callbacks
+                .filter(callback_state.eq_any(callback_states))
+                .limit(0)
+                .load::<SqliteCallbackRow>(&conn)?;
+            derivation_indices
+                .limit(0)
+                .load::<(String, String, i32)>(&conn)?;
+            address_pool
+                .limit(0)
+                .load::<(String, NaiveDateTime, String)>(&conn)?;
+            merchants.limit(0).load::<MerchantRow>(&conn)?;
+            Ok(())
+        };
+        probe().map_err(StoreError::Schema)?;
+
+        let columns = diesel::sql_query(
+            "SELECT m.name AS table_name, c.name AS column_name, c.\"notnull\" = 0 AS nullable \
+             FROM sqlite_master m JOIN pragma_table_info(m.name) c WHERE m.type = 'table'",
+        )
+        .load::<ColumnInfo>(&conn)
+        .map_err(StoreError::Schema)?;
+        check_nullability(&columns)
+    }
+}
@@ -0,0 +1,307 @@
+use chrono::NaiveDateTime;
+use diesel::*;
+use uuid::Uuid;
+
+use super::schema::{
+    address_pool, callbacks, derivation_indices, merchants, payment_inputs, payment_outputs,
+    payment_transactions, payments, received_amounts, refund_outputs,
+};
+use crate::sql::{
+    errors::StoreError,
+    models::{
+        CallbackRow, MerchantRow, PaymentInputRow, PaymentOutputRow, PaymentRow, RefundOutputRow,
+    },
+};
+
+// SQLite has no UUID or enum types, so IDs and states are stored as text and converted to
+// the shared rows on load
+
+fn parse_uuid(raw: &str) -> Result<Uuid, StoreError> {
+    Uuid::parse_str(raw).map_err(|_| StoreError::Corrupt)
+}
+
+#[derive(Queryable)]
+pub struct SqlitePaymentRow {
+    pub id: String,
+    pub issue_time: NaiveDateTime,
+    pub amount: i64,
+    pub address: String,
+    pub expiry_time: Option<NaiveDateTime>,
+    pub req_memo: Option<String>,
+    pub merchant_data: Option<Vec<u8>>,
+    pub ack_memo: Option<String>,
+    pub tokenize: bool,
+    pub tx_data: Option<Vec<u8>>,
+    pub payment_state: String,
+    pub payment_time: Option<NaiveDateTime>,
+    pub callback_url: Option<String>,
+    pub refund_tx_id: Option<String>,
+    pub block_hash: Option<String>,
+    pub block_height: Option<i32>,
+    pub payment_ack: Option<Vec<u8>>,
+    pub payment_request: Option<Vec<u8>>,
+    pub fiat_currency: Option<String>,
+    pub fiat_amount: Option<f64>,
+    pub exchange_rate: Option<f64>,
+    pub rejection_reason: Option<String>,
+    pub min_fee_rate: Option<f64>,
+    pub double_spend_tx_id: Option<String>,
+    pub amount_received: i64,
+    pub amount_due: i64,
+    pub overpaid_amount: i64,
+    pub merchant_id: String,
+}
+
+impl SqlitePaymentRow {
+    pub fn into_row(self) -> Result<PaymentRow, StoreError> {
+        Ok(PaymentRow {
+            id: parse_uuid(&self.id)?,
+            issue_time: self.issue_time,
+            amount: self.amount,
+            address: self.address,
+            expiry_time: self.expiry_time,
+            req_memo: self.req_memo,
+            merchant_data: self.merchant_data,
+            ack_memo: self.ack_memo,
+            tokenize: self.tokenize,
+            tx_data: self.tx_data,
+            payment_state: self
+                .payment_state
+                .parse()
+                .map_err(|_| StoreError::Corrupt)?,
+            payment_time: self.payment_time,
+            callback_url: self.callback_url,
+            refund_tx_id: self.refund_tx_id,
+            block_hash: self.block_hash,
+            block_height: self.block_height,
+            payment_ack: self.payment_ack,
+            payment_request: self.payment_request,
+            fiat_currency: self.fiat_currency,
+            fiat_amount: self.fiat_amount,
+            exchange_rate: self.exchange_rate,
+            rejection_reason: self.rejection_reason,
+            min_fee_rate: self.min_fee_rate,
+            double_spend_tx_id: self.double_spend_tx_id,
+            amount_received: self.amount_received,
+            amount_due: self.amount_due,
+            overpaid_amount: self.overpaid_amount,
+            merchant_id: self.merchant_id,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "payments"]
+pub struct NewPayment<'a> {
+    pub id: &'a str,
+    pub issue_time: &'a NaiveDateTime,
+    pub amount: i64,
+    pub address: &'a str,
+    pub expiry_time: Option<&'a NaiveDateTime>,
+    pub req_memo: Option<&'a str>,
+    pub merchant_data: Option<&'a [u8]>,
+    pub tx_data: Option<&'a [u8]>,
+    pub ack_memo: Option<&'a str>,
+    pub payment_state: &'a str,
+    pub tokenize: bool,
+    pub callback_url: Option<&'a str>,
+    pub payment_request: &'a [u8],
+    pub fiat_currency: Option<&'a str>,
+    pub fiat_amount: Option<f64>,
+    pub exchange_rate: Option<f64>,
+    pub min_fee_rate: Option<f64>,
+    pub amount_due: i64,
+    pub merchant_id: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct SqlitePaymentInputRow {
+    pub payment_id: String,
+    pub tx_id: String,
+    pub prev_tx_id: String,
+    pub prev_vout: i32,
+}
+
+impl SqlitePaymentInputRow {
+    pub fn into_row(self) -> Result<PaymentInputRow, StoreError> {
+        Ok(PaymentInputRow {
+            payment_id: parse_uuid(&self.payment_id)?,
+            tx_id: self.tx_id,
+            prev_tx_id: self.prev_tx_id,
+            prev_vout: self.prev_vout,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "payment_inputs"]
+pub struct NewPaymentInput<'a> {
+    pub payment_id: &'a str,
+    pub tx_id: &'a str,
+    pub prev_tx_id: &'a str,
+    pub prev_vout: i32,
+}
+
+#[derive(Queryable)]
+pub struct SqlitePaymentOutputRow {
+    pub payment_id: String,
+    pub idx: i32,
+    pub amount: i64,
+    pub script: Vec<u8>,
+}
+
+impl SqlitePaymentOutputRow {
+    pub fn into_row(self) -> Result<PaymentOutputRow, StoreError> {
+        Ok(PaymentOutputRow {
+            payment_id: parse_uuid(&self.payment_id)?,
+            idx: self.idx,
+            amount: self.amount,
+            script: self.script,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "payment_outputs"]
+pub struct NewPaymentOutput<'a> {
+    pub payment_id: &'a str,
+    pub idx: i32,
+    pub amount: i64,
+    pub script: &'a [u8],
+}
+
+#[derive(Insertable)]
+#[table_name = "received_amounts"]
+pub struct NewReceivedAmount<'a> {
+    pub payment_id: &'a str,
+    pub script: &'a [u8],
+    pub amount: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "payment_transactions"]
+pub struct NewPaymentTransaction<'a> {
+    pub payment_id: &'a str,
+    pub tx_id: &'a str,
+}
+
+#[derive(Queryable)]
+pub struct SqliteRefundOutputRow {
+    pub payment_id: String,
+    pub idx: i32,
+    pub amount: Option<i64>,
+    pub script: Vec<u8>,
+}
+
+impl SqliteRefundOutputRow {
+    pub fn into_row(self) -> Result<RefundOutputRow, StoreError> {
+        Ok(RefundOutputRow {
+            payment_id: parse_uuid(&self.payment_id)?,
+            idx: self.idx,
+            amount: self.amount,
+            script: self.script,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "refund_outputs"]
+pub struct NewRefundOutput<'a> {
+    pub payment_id: &'a str,
+    pub idx: i32,
+    pub amount: Option<i64>,
+    pub script: &'a [u8],
+}
+
+#[derive(Queryable)]
+pub struct SqliteCallbackRow {
+    pub id: i64,
+    pub payment_id: String,
+    pub url: String,
+    pub payload: Vec<u8>,
+    pub callback_state: String,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub last_attempt: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+}
+
+impl SqliteCallbackRow {
+    pub fn into_row(self) -> Result<CallbackRow, StoreError> {
+        Ok(CallbackRow {
+            id: self.id,
+            payment_id: parse_uuid(&self.payment_id)?,
+            url: self.url,
+            payload: self.payload,
+            callback_state: self
+                .callback_state
+                .parse()
+                .map_err(|_| StoreError::Corrupt)?,
+            attempts: self.attempts,
+            next_attempt: self.next_attempt,
+            last_attempt: self.last_attempt,
+            last_error: self.last_error,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "callbacks"]
+pub struct NewCallback<'a> {
+    pub payment_id: &'a str,
+    pub url: &'a str,
+    pub payload: &'a [u8],
+    pub callback_state: &'a str,
+    pub next_attempt: &'a NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "derivation_indices"]
+pub struct NewDerivationIndex<'a> {
+    pub xpub: &'a str,
+    pub path: &'a str,
+    pub next_index: i32,
+}
+
+#[derive(Insertable)]
+#[table_name = "address_pool"]
+pub struct NewPooledAddress<'a> {
+    pub address: &'a str,
+    pub added: &'a NaiveDateTime,
+    pub merchant_id: &'a str,
+}
+
+#[derive(Insertable, AsChangeset, Debug, PartialEq)]
+#[table_name = "merchants"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct NewMerchant<'a> {
+    pub id: &'a str,
+    pub api_key_hash: Option<&'a str>,
+    pub secret: &'a str,
+    pub address_source: &'a str,
+    pub wallet: Option<&'a str>,
+    pub xpub: Option<&'a str>,
+    pub derivation_path: &'a str,
+    pub req_memo: Option<&'a str>,
+    pub ack_memo: Option<&'a str>,
+    pub expiry: Option<i64>,
+    pub callback_url: Option<&'a str>,
+}
+
+impl<'a> From<&'a MerchantRow> for NewMerchant<'a> {
+    fn from(merchant: &'a MerchantRow) -> Self {
+        NewMerchant {
+            id: &merchant.id,
+            api_key_hash: merchant.api_key_hash.as_ref().map(String::as_str),
+            secret: &merchant.secret,
+            address_source: &merchant.address_source,
+            wallet: merchant.wallet.as_ref().map(String::as_str),
+            xpub: merchant.xpub.as_ref().map(String::as_str),
+            derivation_path: &merchant.derivation_path,
+            req_memo: merchant.req_memo.as_ref().map(String::as_str),
+            ack_memo: merchant.ack_memo.as_ref().map(String::as_str),
+            expiry: merchant.expiry,
+            callback_url: merchant.callback_url.as_ref().map(String::as_str),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+table! {
+    payments (id) {
+        id -> Text, // Payment ID
+        issue_time -> Timestamp, // The time of issuance
+        amount -> BigInt, // The amount to be paid
+        address -> Text, // Address to be paid to
+        expiry_time -> Nullable<Timestamp>, // Expiry time of the payment
+        req_memo -> Nullable<Text>, // Memo to be included in the request
+        merchant_data -> Nullable<Blob>, // Merchant data
+        ack_memo -> Nullable<Text>, // Memo to be included in the request
+        tokenize -> Bool, // Assume merchant data is URL and append token
+        tx_data -> Nullable<Blob>, // Encoded pushes required after OP_RETURN
+        payment_state -> Text, // Payment state
+        payment_time -> Nullable<Timestamp>, // Time payment was completed
+        callback_url -> Nullable<Text>, // Callback URL
+        refund_tx_id -> Nullable<Text>, // Transaction ID of the refund
+        block_hash -> Nullable<Text>, // Hash of the block confirming the payment
+        block_height -> Nullable<Integer>, // Height of the block confirming the payment
+        payment_ack -> Nullable<Blob>, // Encoded PaymentACK issued for the payment
+        payment_request -> Nullable<Blob>, // Encoded PaymentRequest served to wallets
+        fiat_currency -> Nullable<Text>, // Fiat currency the invoice was denominated in
+        fiat_amount -> Nullable<Double>, // Amount in the fiat currency
+        exchange_rate -> Nullable<Double>, // Exchange rate used to derive the amount
+        rejection_reason -> Nullable<Text>, // Reason the node last refused a payment tx
+        min_fee_rate -> Nullable<Double>, // Minimum fee rate of payment txs in sats per byte
+        double_spend_tx_id -> Nullable<Text>, // Transaction conflicting with the payment
+        amount_received -> BigInt, // Total paid towards the invoice
+        amount_due -> BigInt, // Amount still to be paid
+        overpaid_amount -> BigInt, // Amount paid in excess of the invoice, owed as a refund
+        merchant_id -> Text, // Merchant the invoice belongs to
+    }
+}
+
+table! {
+    payment_transactions (payment_id, tx_id) {
+        payment_id -> Text, // Payment ID
+        tx_id -> Text, // Transaction ID of one of the payment transactions
+    }
+}
+
+table! {
+    payment_inputs (payment_id, prev_tx_id, prev_vout) {
+        payment_id -> Text, // Payment ID
+        tx_id -> Text, // Payment transaction spending the output
+        prev_tx_id -> Text, // Transaction ID of the spent output
+        prev_vout -> Integer, // Index of the spent output
+    }
+}
+
+table! {
+    payment_outputs (payment_id, idx) {
+        payment_id -> Text, // Payment ID
+        idx -> Integer, // Position within InvoiceRequest.outputs
+        amount -> BigInt, // Amount to be paid to the script
+        script -> Blob, // Output script
+    }
+}
+
+table! {
+    received_amounts (payment_id, script) {
+        payment_id -> Text, // Payment ID
+        script -> Blob, // Expected output script
+        amount -> BigInt, // Total received by the script
+    }
+}
+
+table! {
+    refund_outputs (payment_id, idx) {
+        payment_id -> Text, // Payment ID
+        idx -> Integer, // Position within Payment.refund_to
+        amount -> Nullable<BigInt>, // Amount to be refunded to the script
+        script -> Blob, // Refund output script
+    }
+}
+
+table! {
+    callbacks (id) {
+        id -> BigInt, // Callback ID
+        payment_id -> Text, // Payment ID
+        url -> Text, // Callback URL
+        payload -> Blob, // Encoded CallbackPayload
+        callback_state -> Text, // Delivery state
+        attempts -> Integer, // Number of delivery attempts
+        next_attempt -> Timestamp, // Time of the next delivery attempt
+        last_attempt -> Nullable<Timestamp>, // Time of the last delivery attempt
+        last_error -> Nullable<Text>, // Error of the last delivery attempt
+    }
+}
+
+table! {
+    derivation_indices (xpub, path) {
+        xpub -> Text, // Extended public key addresses are derived from
+        path -> Text, // Derivation path of the address chain below the xpub
+        next_index -> Integer, // Next unused child index
+    }
+}
+
+table! {
+    address_pool (address) {
+        address -> Text, // Unused address awaiting an invoice
+        added -> Timestamp, // Time the address was fetched
+        merchant_id -> Text, // Merchant the address was fetched for
+    }
+}
+
+table! {
+    merchants (id) {
+        id -> Text, // Merchant ID
+        api_key_hash -> Nullable<Text>, // SHA256 of the key authenticating private API calls
+        secret -> Text, // Secret tokens are generated with
+        address_source -> Text, // Source of invoice addresses
+        wallet -> Nullable<Text>, // Node wallet addresses are fetched from and refunds sent by
+        xpub -> Nullable<Text>, // Extended public key addresses are derived from
+        derivation_path -> Text, // Derivation path of the address chain below the xpub
+        req_memo -> Nullable<Text>, // Default memo to be included in the request
+        ack_memo -> Nullable<Text>, // Default memo to be included in the PaymentACK
+        expiry -> Nullable<BigInt>, // Default validity of invoices in seconds
+        callback_url -> Nullable<Text>, // Default callback URL
+    }
+}
+
+joinable!(payment_inputs -> payments (payment_id));
+joinable!(payment_outputs -> payments (payment_id));
+joinable!(received_amounts -> payments (payment_id));
+joinable!(payment_transactions -> payments (payment_id));
+joinable!(refund_outputs -> payments (payment_id));
+joinable!(callbacks -> payments (payment_id));
+joinable!(payments -> merchants (merchant_id));
+joinable!(address_pool -> merchants (merchant_id));
+
+allow_tables_to_appear_in_same_query!(
+    payments,
+    payment_inputs,
+    payment_outputs,
+    received_amounts,
+    payment_transactions,
+    refund_outputs,
+    callbacks,
+    derivation_indices,
+    address_pool,
+    merchants
+);
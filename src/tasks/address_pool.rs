@@ -0,0 +1,85 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::{future, stream, Future, Stream};
+use log::{error, info};
+use tokio_timer::Interval;
+
+use crate::{
+    addresses::{pool::PoolMetrics, AddressProvider},
+    settings::AddressPool,
+    sql::Store,
+};
+
+// Top up the pool from the address source once it falls below the low-water mark
+fn refill(
+    merchant_id: String,
+    source: AddressProvider,
+    store: Store,
+    metrics: Arc<PoolMetrics>,
+    settings: &'static AddressPool,
+) -> impl Future<Item = (), Error = ()> {
+    let store_inner = store.clone();
+    let merchant_id_inner = merchant_id.clone();
+    let merchant_id_log = merchant_id.clone();
+    actix_web::web::block(move || store_inner.count_pooled_addresses(&merchant_id_inner))
+        .map_err(|e| error!("failed to count pooled addresses: {:?}", e))
+        .and_then(move |depth| {
+            metrics.set_depth(depth);
+            if depth as u64 >= settings.low_water {
+                return future::Either::A(future::ok(()));
+            }
+
+            // Fetch sequentially so the source is not flooded, storing each address as it comes so
+            // those fetched before a failure, and any derivation indices reserved for them, are kept
+            let n_missing = settings.size.saturating_sub(depth as u64);
+            let fetched = stream::iter_ok::<_, ()>(0..n_missing)
+                .and_then(move |_| {
+                    let store = store.clone();
+                    let merchant_id = merchant_id.clone();
+                    source
+                        .next_address()
+                        .map_err(|e| error!("failed to fetch pool address: {}", e))
+                        .and_then(move |address| {
+                            actix_web::web::block(move || {
+                                store.add_pooled_addresses(&merchant_id, &[address])
+                            })
+                            .map_err(|e| error!("failed to store pool address: {:?}", e))
+                        })
+                        .then(|result| Ok(result.ok()))
+                })
+                .take_while(|n_added| Ok(n_added.is_some()))
+                .fold(0, |total, n_added| Ok(total + n_added.unwrap_or(0)))
+                .map(move |n_added| {
+                    info!(
+                        "added {} addresses to the pool of {}",
+                        n_added, merchant_id_log
+                    );
+                    metrics.add_fetched(n_added as u64);
+                    metrics.set_depth(depth + n_added as i64);
+                });
+            future::Either::B(fetched)
+        })
+}
+
+// Periodically refill the address pool of a merchant
+pub fn address_pool_refiller(
+    merchant_id: String,
+    source: AddressProvider,
+    store: Store,
+    metrics: Arc<PoolMetrics>,
+    settings: &'static AddressPool,
+) -> impl Future<Item = (), Error = ()> {
+    Interval::new_interval(Duration::from_secs(settings.interval))
+        .map_err(|e| error!("address pool timer error: {:?}", e))
+        .for_each(move |_| {
+            // A failed refill is retried on the next tick
+            refill(
+                merchant_id.clone(),
+                source.clone(),
+                store.clone(),
+                metrics.clone(),
+                settings,
+            )
+            .then(|_| Ok(()))
+        })
+}
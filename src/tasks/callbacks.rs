@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::{future, stream, Future, Stream};
+use log::{error, info, warn};
+use reqwest::{header::CONTENT_TYPE, r#async::Client};
+use tokio_timer::Interval;
+
+use crate::{
+    settings::Callback,
+    sql::{models::CallbackRow, Store},
+};
+
+const BATCH_SIZE: i64 = 64;
+
+// Delay before the next attempt given the number of earlier retries, starting at the base delay
+// and doubling with each retry
+fn backoff(settings: &Callback, retries: i32) -> Duration {
+    let exponent = retries.max(0).min(32) as u32;
+    let delay = settings
+        .base_delay
+        .saturating_mul(2u64.saturating_pow(exponent));
+    Duration::from_secs(delay.min(settings.max_delay))
+}
+
+fn deliver(client: &Client, callback: &CallbackRow) -> impl Future<Item = (), Error = String> {
+    client
+        .post(&callback.url)
+        .header(CONTENT_TYPE, "application/x-protobuf")
+        .body(callback.payload.clone())
+        .send()
+        .map_err(|err| err.to_string())
+        .and_then(|response| {
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(format!("unexpected status {}", response.status()))
+            }
+        })
+}
+
+// Record the outcome of a delivery attempt
+fn record(
+    store: Store,
+    settings: &'static Callback,
+    callback: CallbackRow,
+    result: Result<(), String>,
+) -> impl Future<Item = (), Error = ()> {
+    actix_web::web::block(move || match result {
+        Ok(()) => {
+            info!("delivered callback for {}", callback.payment_id);
+            store.callback_delivered(callback.id)
+        }
+        Err(err) => {
+            let attempts = callback.attempts + 1;
+            let retry_at = if attempts < settings.max_attempts {
+                let delay =
+                    chrono::Duration::from_std(backoff(settings, callback.attempts)).unwrap();
+                warn!(
+                    "callback for {} failed ({}), retrying in {}s",
+                    callback.payment_id,
+                    err,
+                    delay.num_seconds()
+                );
+                Some(Utc::now().naive_utc() + delay)
+            } else {
+                error!(
+                    "callback for {} failed ({}), giving up after {} attempts",
+                    callback.payment_id, err, attempts
+                );
+                None
+            };
+            store.callback_failed(callback.id, &err, retry_at)
+        }
+    })
+    .map_err(|e| error!("failed to record callback attempt: {:?}", e))
+}
+
+// Periodically deliver queued callbacks, several at a time so that a slow endpoint does not hold
+// up the others
+pub fn callback_sender(
+    store: Store,
+    settings: &'static Callback,
+) -> impl Future<Item = (), Error = ()> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(settings.timeout))
+        .build()
+        .expect("failed to build callback client");
+
+    Interval::new_interval(Duration::from_secs(settings.interval))
+        .map_err(|e| error!("callback timer error: {:?}", e))
+        .for_each(move |_| {
+            let store_inner = store.clone();
+            let client = client.clone();
+            let due_callbacks =
+                actix_web::web::block(move || store_inner.get_due_callbacks(BATCH_SIZE))
+                    .map_err(|e| error!("failed to fetch callbacks: {:?}", e));
+
+            let store_inner = store.clone();
+            due_callbacks
+                .and_then(move |due_callbacks| {
+                    stream::iter_ok(due_callbacks)
+                        .map(move |callback| {
+                            let store_inner = store_inner.clone();
+                            deliver(&client, &callback)
+                                .then(move |result| record(store_inner, settings, callback, result))
+                        })
+                        .buffer_unordered(settings.concurrency)
+                        .for_each(|_| Ok(()))
+                })
+                // Do not stop the sender on error
+                .or_else(|_| future::ok(()))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Callback {
+        Callback {
+            interval: 5,
+            timeout: 10,
+            base_delay: 10,
+            max_delay: 3600,
+            max_attempts: 20,
+            concurrency: 16,
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let settings = settings();
+        assert_eq!(backoff(&settings, 0), Duration::from_secs(10));
+        assert_eq!(backoff(&settings, 1), Duration::from_secs(20));
+        assert_eq!(backoff(&settings, 2), Duration::from_secs(40));
+        assert_eq!(backoff(&settings, 8), Duration::from_secs(2560));
+    }
+
+    #[test]
+    fn test_backoff_capped() {
+        let settings = settings();
+        assert_eq!(backoff(&settings, 9), Duration::from_secs(3600));
+        assert_eq!(backoff(&settings, 64), Duration::from_secs(3600));
+        assert_eq!(
+            backoff(&settings, i32::max_value()),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[test]
+    fn test_backoff_negative() {
+        assert_eq!(backoff(&settings(), -1), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use futures::{
+    future::{self, Either},
+    stream, Future, Stream,
+};
+use log::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    bitcoin::{BitcoinClient, TxStatus},
+    sql::Store,
+};
+
+// Check a single payment and mark it confirmed once all of its transactions are buried deep enough
+fn check_payment(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    payment_id: Uuid,
+    tx_ids: Vec<String>,
+    min_confirmations: u32,
+) -> impl Future<Item = (), Error = ()> {
+    let tx_statuses = future::join_all(
+        tx_ids
+            .iter()
+            .map(|tx_id| bitcoin_client.get_tx_status(tx_id))
+            .collect::<Vec<_>>(),
+    )
+    .map_err(move |e| error!("failed to fetch status of {:?}: {:?}", tx_ids, e));
+
+    tx_statuses.and_then(move |tx_statuses| {
+        // The payment is only as deep as its least confirmed transaction
+        let least_confirmed = tx_statuses
+            .into_iter()
+            .min_by_key(|tx_status| tx_status.confirmations.unwrap_or(0));
+        let block_hash = match least_confirmed {
+            Some(TxStatus {
+                confirmations: Some(confirmations),
+                blockhash: Some(block_hash),
+            }) if confirmations >= min_confirmations => block_hash,
+            _ => return Either::B(future::ok(())),
+        };
+
+        let confirmation = bitcoin_client
+            .get_block_header(&block_hash)
+            .map_err(|e| error!("failed to fetch block header: {:?}", e))
+            .and_then(move |block_header| {
+                actix_web::web::block(move || {
+                    store
+                        .confirm_payment(
+                            &payment_id,
+                            &block_header.hash,
+                            block_header.height as i32,
+                        )
+                        .map(|_| block_header)
+                })
+                .map_err(|e| error!("failed to confirm payment: {:?}", e))
+            })
+            .map(move |block_header| {
+                info!(
+                    "payment {} confirmed in block {} at height {}",
+                    payment_id, block_header.hash, block_header.height
+                )
+            });
+        Either::A(confirmation)
+    })
+}
+
+fn check_confirmations(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    min_confirmations: u32,
+) -> impl Future<Item = (), Error = ()> {
+    let store_inner = store.clone();
+    let unconfirmed_txs = actix_web::web::block(move || store_inner.get_unconfirmed_txs())
+        .map_err(|e| error!("failed to fetch unconfirmed payments: {:?}", e));
+
+    unconfirmed_txs.and_then(move |unconfirmed_txs| {
+        // Group transactions by payment
+        let mut payment_txs: HashMap<Uuid, Vec<String>> = HashMap::new();
+        for (payment_id, tx_id) in unconfirmed_txs {
+            payment_txs.entry(payment_id).or_default().push(tx_id);
+        }
+
+        stream::iter_ok(payment_txs).for_each(move |(payment_id, tx_ids)| {
+            check_payment(
+                bitcoin_client.clone(),
+                store.clone(),
+                payment_id,
+                tx_ids,
+                min_confirmations,
+            )
+            // Failure to check one payment should not prevent checking the rest
+            .then(|_| Ok(()))
+        })
+    })
+}
+
+// Check confirmations of received payments on startup and on each new block
+pub fn confirmation_watcher(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    block_stream: impl Stream<Item = String, Error = ()>,
+    min_confirmations: u32,
+) -> impl Future<Item = (), Error = ()> {
+    stream::once(Ok(None))
+        .chain(block_stream.map(Some))
+        .for_each(move |opt_block_hash| {
+            if let Some(block_hash) = opt_block_hash {
+                info!("new block {}", block_hash);
+            }
+            check_confirmations(bitcoin_client.clone(), store.clone(), min_confirmations)
+                .then(|_| Ok(()))
+        })
+}
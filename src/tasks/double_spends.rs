@@ -0,0 +1,231 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use bitcoin::Transaction;
+use futures::{
+    future, stream,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    Future, Stream,
+};
+use log::{debug, error};
+use prost::Message;
+use tokio_timer::Interval;
+use uuid::Uuid;
+
+use crate::{
+    bitcoin::{tx_stream::StreamError, BitcoinClient, SpentOutput},
+    models::{CallbackPayload, PaymentAck, PaymentState},
+    sql::Store,
+};
+
+// Outputs spent by unconfirmed payments, mapped to the payment and the payment tx spending them
+type WatchedInputs = HashMap<(String, u32), (Uuid, String)>;
+
+// Inputs of newly accepted payments, watched from acceptance rather than from the next refresh
+pub type AcceptedPayments = UnboundedSender<(Uuid, Vec<SpentOutput>)>;
+
+// Double spend proofs fetched from the node at once
+const DS_PROOF_CONCURRENCY: usize = 8;
+
+enum Event {
+    Refresh,
+    Tx(Transaction),
+    Accepted(Uuid, Vec<SpentOutput>),
+}
+
+fn load_watched_inputs(store: Store) -> impl Future<Item = WatchedInputs, Error = ()> {
+    actix_web::web::block(move || store.get_watched_inputs())
+        .map_err(|e| error!("failed to fetch watched inputs: {:?}", e))
+        .map(|input_rows| {
+            input_rows
+                .into_iter()
+                .map(|input_row| {
+                    (
+                        (input_row.prev_tx_id, input_row.prev_vout as u32),
+                        (input_row.payment_id, input_row.tx_id),
+                    )
+                })
+                .collect()
+        })
+}
+
+// Move a payment to the double spent state, alert and queue a callback
+fn flag_double_spend(
+    store: Store,
+    payment_id: Uuid,
+    double_spend_tx_id: Option<String>,
+) -> impl Future<Item = (), Error = ()> {
+    let double_spend_tx_id_inner = double_spend_tx_id.clone();
+    actix_web::web::block(move || {
+        let payment_row = store.get_payment(&payment_id.to_string())?;
+
+        // Encode callback payload
+        let callback_payload = CallbackPayload {
+            payment_id: payment_id.to_string(),
+            payment_ack: payment_row
+                .payment_ack
+                .as_ref()
+                .and_then(|raw_ack| PaymentAck::decode(&raw_ack[..]).ok()),
+            state: PaymentState::DoubleSpent as i32,
+        };
+        let mut raw_callback_payload = Vec::with_capacity(callback_payload.encoded_len());
+        callback_payload.encode(&mut raw_callback_payload).unwrap();
+
+        let callback = payment_row
+            .callback_url
+            .as_ref()
+            .map(|url| (url.as_str(), &raw_callback_payload[..]));
+        store.mark_double_spent(
+            &payment_id,
+            double_spend_tx_id_inner.as_ref().map(String::as_str),
+            callback,
+        )
+    })
+    .map_err(move |e| error!("failed to flag double spend of {}: {:?}", payment_id, e))
+    .map(move |flagged| {
+        if flagged {
+            error!(
+                "payment {} double spent by {}",
+                payment_id,
+                double_spend_tx_id.unwrap_or_else(|| "unknown tx (double spend proof)".to_string())
+            );
+        }
+    })
+}
+
+// Flag payments whose inputs are spent by a different tx
+fn check_tx(
+    store: Store,
+    tx: &Transaction,
+    watched_inputs: &WatchedInputs,
+) -> impl Future<Item = (), Error = ()> {
+    let tx_id = tx.txid().to_string();
+    let double_spent: HashSet<Uuid> = tx
+        .input
+        .iter()
+        .filter_map(|input| {
+            let outpoint = (
+                input.previous_output.txid.to_string(),
+                input.previous_output.vout,
+            );
+            match watched_inputs.get(&outpoint) {
+                Some((payment_id, payment_tx_id)) if *payment_tx_id != tx_id => Some(*payment_id),
+                _ => None,
+            }
+        })
+        .collect();
+
+    stream::iter_ok(double_spent).for_each(move |payment_id| {
+        flag_double_spend(store.clone(), payment_id, Some(tx_id.clone())).then(|_| Ok(()))
+    })
+}
+
+// Flag payments whose txs the node holds a double spend proof for
+fn check_ds_proofs(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    watched_inputs: &WatchedInputs,
+) -> impl Future<Item = (), Error = ()> {
+    let payment_txs: HashSet<(Uuid, String)> = watched_inputs.values().cloned().collect();
+
+    stream::iter_ok::<_, ()>(payment_txs)
+        .map(move |(payment_id, tx_id)| {
+            let store = store.clone();
+            bitcoin_client
+                .get_ds_proof(&tx_id)
+                .then(move |result| match result {
+                    Ok(Some(_)) => future::Either::A(flag_double_spend(store, payment_id, None)),
+                    Ok(None) => future::Either::B(future::ok(())),
+                    Err(e) => {
+                        // Nodes without double spend proof support land here too
+                        debug!("failed to fetch double spend proof of {}: {:?}", tx_id, e);
+                        future::Either::B(future::ok(()))
+                    }
+                })
+                .then(|_| Ok(()))
+        })
+        .buffer_unordered(DS_PROOF_CONCURRENCY)
+        .for_each(|_| Ok(()))
+}
+
+// Periodically poll the node for double spend proofs of unconfirmed payments. This runs apart
+// from the tx stream so that a slow node does not hold up checking txs
+fn ds_proof_poller(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    interval: Duration,
+) -> impl Future<Item = (), Error = ()> {
+    Interval::new_interval(interval)
+        .map_err(|e| error!("double spend proof timer error: {:?}", e))
+        .for_each(move |_| {
+            let bitcoin_client = bitcoin_client.clone();
+            let store = store.clone();
+            load_watched_inputs(store.clone())
+                .and_then(move |watched_inputs| {
+                    check_ds_proofs(bitcoin_client, store, &watched_inputs)
+                })
+                .then(|_| Ok(()))
+        })
+}
+
+// Watch unconfirmed payments for conflicting txs and double spend proofs
+pub fn double_spend_watcher(
+    bitcoin_client: BitcoinClient,
+    store: Store,
+    tx_stream: impl Stream<Item = Transaction, Error = StreamError>,
+    accepted_payments: UnboundedReceiver<(Uuid, Vec<SpentOutput>)>,
+    interval: Duration,
+) -> impl Future<Item = (), Error = ()> {
+    let refreshes = Interval::new_interval(interval)
+        .map(|_| Event::Refresh)
+        .map_err(|e| error!("double spend watcher timer error: {:?}", e));
+    let txs = tx_stream
+        .then(|result| match result {
+            Ok(tx) => Ok(Some(Event::Tx(tx))),
+            Err(e) => {
+                // Do not stop the watcher on a malformed tx
+                error!("tx stream error: {:?}", e);
+                Ok(None)
+            }
+        })
+        .filter_map(|opt_event| opt_event);
+    let accepted =
+        accepted_payments.map(|(payment_id, inputs)| Event::Accepted(payment_id, inputs));
+
+    let store_inner = store.clone();
+    let watcher = stream::once(Ok(Event::Refresh))
+        .chain(refreshes)
+        .select(txs)
+        .select(accepted)
+        .fold(WatchedInputs::new(), move |mut watched_inputs, event| {
+            let store = store_inner.clone();
+            match event {
+                Event::Refresh => {
+                    let refresh = load_watched_inputs(store)
+                        // Keep the previous inputs if they could not be reloaded
+                        .or_else(|_| Ok::<_, ()>(watched_inputs));
+                    future::Either::A(refresh)
+                }
+                Event::Tx(tx) => future::Either::B(future::Either::A(
+                    check_tx(store, &tx, &watched_inputs).then(|_| Ok::<_, ()>(watched_inputs)),
+                )),
+                Event::Accepted(payment_id, inputs) => {
+                    for input in inputs {
+                        watched_inputs.insert(
+                            (input.prev_tx_id, input.prev_vout),
+                            (payment_id, input.tx_id),
+                        );
+                    }
+                    future::Either::B(future::Either::B(future::ok(watched_inputs)))
+                }
+            }
+        })
+        .map(|_| ());
+
+    watcher
+        .select(ds_proof_poller(bitcoin_client, store, interval))
+        .map(|_| ())
+        .map_err(|_| ())
+}
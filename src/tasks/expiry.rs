@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use log::{error, info};
+use tokio_timer::Interval;
+
+use crate::sql::Store;
+
+// Periodically mark overdue pending or underpaid payments as expired
+pub fn expiry_sweeper(store: Store, interval: Duration) -> impl Future<Item = (), Error = ()> {
+    Interval::new_interval(interval)
+        .map_err(|e| error!("expiry sweeper timer error: {:?}", e))
+        .for_each(move |_| {
+            let store_inner = store.clone();
+            actix_web::web::block(move || store_inner.expire_payments()).then(|res| {
+                match res {
+                    Ok(0) => (),
+                    Ok(n_expired) => info!("expired {} payments", n_expired),
+                    Err(e) => error!("failed to expire payments: {:?}", e),
+                }
+                Ok(())
+            })
+        })
+}
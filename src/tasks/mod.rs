@@ -0,0 +1,5 @@
+pub mod address_pool;
+pub mod callbacks;
+pub mod confirmations;
+pub mod double_spends;
+pub mod expiry;